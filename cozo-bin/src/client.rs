@@ -6,3 +6,70 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use miette::{bail, miette, IntoDiagnostic, Result};
+use serde_json::Value;
+
+use cozo::{NamedRows, RelationSnapshot, ReplicationSource};
+
+/// Pulls committed mutations from the replication endpoints of a running Cozo server.
+pub(crate) struct HttpReplicationSource {
+    url: String,
+    auth: Option<String>,
+}
+
+impl HttpReplicationSource {
+    pub(crate) fn new(url: &str, auth: Option<String>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            auth,
+        }
+    }
+
+    fn get(&self, path: &str) -> Result<Value> {
+        let mut req = minreq::get(format!("{}{}", self.url, path));
+        if let Some(auth) = &self.auth {
+            req = req.with_header("x-cozo-auth", auth);
+        }
+        let resp = req.send().into_diagnostic()?;
+        let body: Value = serde_json::from_slice(resp.as_bytes()).into_diagnostic()?;
+        if body.get("ok") != Some(&Value::Bool(true)) {
+            bail!(
+                "request to {}{} failed with status {}: {}",
+                self.url,
+                path,
+                resp.status_code,
+                body.get("message").and_then(|m| m.as_str()).unwrap_or("")
+            )
+        }
+        Ok(body)
+    }
+}
+
+impl ReplicationSource for HttpReplicationSource {
+    fn changelog_relations(&self) -> Result<Vec<String>> {
+        let body = self.get("/replication/relations")?;
+        serde_json::from_value(body["relations"].clone()).into_diagnostic()
+    }
+
+    fn snapshot(&self, relation: &str) -> Result<RelationSnapshot> {
+        let body = self.get(&format!("/replication/snapshot/{relation}"))?;
+        Ok(RelationSnapshot {
+            schema: body["schema"]
+                .as_str()
+                .ok_or_else(|| miette!("snapshot of {} has no schema", relation))?
+                .to_string(),
+            rows: NamedRows::from_json(&body)?,
+            seq: body["seq"]
+                .as_i64()
+                .ok_or_else(|| miette!("snapshot of {} has no sequence number", relation))?,
+        })
+    }
+
+    fn read_changelog(&self, relation: &str, from: i64, limit: Option<usize>) -> Result<NamedRows> {
+        let mut path = format!("/changelog/{relation}?from={from}");
+        if let Some(limit) = limit {
+            path.push_str(&format!("&limit={limit}"));
+        }
+        NamedRows::from_json(&self.get(&path)?)
+    }
+}
@@ -0,0 +1,57 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{stdout, BufWriter, Write};
+
+use clap::Args;
+use miette::{miette, IntoDiagnostic, Result};
+
+use cozo::{DbInstance, ScriptMutability};
+
+#[derive(Args, Debug)]
+pub(crate) struct DumpArgs {
+    /// Database engine, can be `mem`, `sqlite`, `rocksdb` and others.
+    #[clap(short, long, default_value_t = String::from("mem"))]
+    engine: String,
+
+    /// Path to the directory to store the database
+    #[clap(short, long, default_value_t = String::from("cozo.db"))]
+    path: String,
+
+    /// Extra config in JSON format
+    #[clap(short, long, default_value_t = String::from("{}"))]
+    config: String,
+
+    /// File to write the script to, instead of the standard output
+    #[clap(short, long)]
+    output: Option<String>,
+
+    /// Relations to dump, all stored relations if none are given
+    relations: Vec<String>,
+}
+
+/// Write a CozoScript that recreates the relations of the database when run.
+pub(crate) fn dump_main(args: DumpArgs) -> Result<()> {
+    let db = DbInstance::new(&args.engine, &args.path, &args.config)?;
+    let script = format!("::dump {}", args.relations.join(", "));
+    let dumped = db.run_script(&script, BTreeMap::new(), ScriptMutability::Immutable)?;
+    let mut out: BufWriter<Box<dyn Write>> = BufWriter::new(match &args.output {
+        Some(path) => Box::new(File::create(path).into_diagnostic()?),
+        None => Box::new(stdout()),
+    });
+    for row in dumped.rows {
+        let statement = row[0]
+            .get_str()
+            .ok_or_else(|| miette!("unexpected dump output {:?}", row))?;
+        writeln!(out, "{statement}").into_diagnostic()?;
+    }
+    out.flush().into_diagnostic()?;
+    Ok(())
+}
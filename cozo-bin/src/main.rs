@@ -13,10 +13,12 @@ use std::process::exit;
 use clap::{Parser, Subcommand};
 use env_logger::Env;
 
+use crate::dump::{dump_main, DumpArgs};
 use crate::repl::{repl_main, ReplArgs};
 use crate::server::{server_main, ServerArgs};
 
 mod client;
+mod dump;
 mod repl;
 mod server;
 
@@ -32,6 +34,7 @@ struct AppArgs {
 enum Commands {
     Server(ServerArgs),
     Repl(ReplArgs),
+    Dump(DumpArgs),
 }
 
 fn main() {
@@ -50,6 +53,12 @@ fn main() {
                 exit(-1);
             }
         }
+        Commands::Dump(args) => {
+            if let Err(e) = dump_main(args) {
+                eprintln!("{e:?}");
+                exit(-1);
+            }
+        }
     };
 
     // if args.repl {
@@ -12,10 +12,11 @@ use std::net::{Ipv6Addr, SocketAddr};
 use std::str::FromStr;
 // use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
-// use std::thread;
+use std::thread;
+use std::time::Duration;
 
 use axum::body::{boxed, Body, BoxBody};
-use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::extract::{DefaultBodyLimit, Path, Query, State};
 use axum::http::{header, HeaderName, Method, Request, Response, StatusCode};
 use axum::response::sse::{Event, KeepAlive};
 use axum::response::{Html, Sse};
@@ -34,7 +35,9 @@ use tower_http::auth::{AsyncAuthorizeRequest, AsyncRequireAuthorizationLayer};
 use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
 
-use cozo::{DataValue, DbInstance, NamedRows, ScriptMutability};
+use cozo::{DataValue, DbInstance, NamedRows, ReplicationSource, ScriptMutability};
+
+use crate::client::HttpReplicationSource;
 
 #[derive(Args, Debug)]
 pub(crate) struct ServerArgs {
@@ -50,6 +53,16 @@ pub(crate) struct ServerArgs {
     #[clap(long)]
     restore: Option<String>,
 
+    /// Archive every committed transaction into this directory, for point-in-time recovery
+    #[clap(long)]
+    mutation_log: Option<String>,
+
+    /// With `--restore`, also replay the mutation log given in `--mutation-log` up to this time,
+    /// in seconds since the UNIX epoch. The log is then only read from, and new transactions
+    /// are not archived.
+    #[clap(long, requires_all = ["restore", "mutation_log"])]
+    restore_until: Option<f64>,
+
     /// Extra config in JSON format
     #[clap(short, long, default_value_t = String::from("{}"))]
     config: String,
@@ -65,11 +78,25 @@ pub(crate) struct ServerArgs {
     /// When set, the content of the named table will be used as a token table
     #[clap(long)]
     token_table: Option<String>,
+
+    /// Run as a read-only replica of the relations with a changelog in another database,
+    /// given as the URL of its server or the path to its SQLite file, which is opened read-only
+    #[clap(long)]
+    replica_of: Option<String>,
+
+    /// Auth token of the server given in `--replica-of`
+    #[clap(long)]
+    replica_auth: Option<String>,
+
+    /// Milliseconds to wait between pulls from the database given in `--replica-of`
+    #[clap(long, default_value_t = 1000)]
+    replica_interval: u64,
 }
 
 #[derive(Clone)]
 struct DbState {
     db: DbInstance,
+    read_only: bool,
     // rule_senders: Arc<Mutex<BTreeMap<u32, crossbeam::channel::Sender<miette::Result<NamedRows>>>>>,
     // rule_counter: Arc<AtomicU32>,
     // tx_counter: Arc<AtomicU32>,
@@ -193,12 +220,58 @@ fn x() {}
 pub(crate) async fn server_main(args: ServerArgs) {
     let db = DbInstance::new(&args.engine, &args.path, &args.config).unwrap();
     if let Some(p) = &args.restore {
-        if let Err(err) = db.restore_backup(p) {
+        let res = match (&args.mutation_log, args.restore_until) {
+            (Some(log_dir), Some(ts)) => db
+                .restore_to_point_in_time(p, log_dir, ts)
+                .map(|n| info!("Replayed {} transactions from the mutation log", n)),
+            _ => db.restore_backup(p),
+        };
+        if let Err(err) = res {
             error!("{}", err);
             error!("Restore from backup failed, terminate");
             panic!()
         }
     }
+    if let Some(log_dir) = &args.mutation_log {
+        if args.restore_until.is_none() {
+            if let Err(err) = db.enable_mutation_log(log_dir) {
+                error!("{}", err);
+                error!("Cannot open mutation log, terminate");
+                panic!()
+            }
+        }
+    }
+
+    if let Some(leader) = &args.replica_of {
+        let source: Box<dyn ReplicationSource + Send> =
+            if leader.starts_with("http://") || leader.starts_with("https://") {
+                Box::new(HttpReplicationSource::new(
+                    leader,
+                    args.replica_auth.clone(),
+                ))
+            } else {
+                match open_replication_source(leader) {
+                    Ok(db) => Box::new(db),
+                    Err(err) => {
+                        error!("{}", err);
+                        error!("Cannot open {} to replicate from, terminate", leader);
+                        panic!()
+                    }
+                }
+            };
+        db.set_read_only(true);
+        let replica = db.clone();
+        let interval = Duration::from_millis(args.replica_interval);
+        info!("Replicating from {}", leader);
+        thread::spawn(move || loop {
+            match replica.replicate_from(source.as_ref()) {
+                Ok(0) => {}
+                Ok(n) => info!("Replicated {} changes", n),
+                Err(err) => warn!("Replication failed: {}", err),
+            }
+            thread::sleep(interval);
+        });
+    }
 
     let skip_auth = args.bind == "127.0.0.1";
 
@@ -232,6 +305,7 @@ pub(crate) async fn server_main(args: ServerArgs) {
 
     let state = DbState {
         db,
+        read_only: args.replica_of.is_some(),
         // rule_senders: Default::default(),
         // rule_counter: Default::default(),
         // tx_counter: Default::default(),
@@ -249,6 +323,9 @@ pub(crate) async fn server_main(args: ServerArgs) {
         .route("/backup", post(backup))
         .route("/import-from-backup", post(import_from_backup))
         .route("/changes/:relation", get(observe_changes))
+        .route("/changelog/:relation", get(read_changelog))
+        .route("/replication/relations", get(changelog_relations))
+        .route("/replication/snapshot/:relation", get(snapshot_relation))
         // .route("/rules/:name", get(register_rule))
         // .route(
         //     "/rule-result/:id",
@@ -377,7 +454,7 @@ async fn text_query(
         .map(|(k, v)| (k, DataValue::from(v)))
         .collect();
     let immutable = match mutability {
-        ScriptMutability::Mutable => payload.immutable.unwrap_or(false),
+        ScriptMutability::Mutable => st.read_only || payload.immutable.unwrap_or(false),
         ScriptMutability::Immutable => true,
     };
     let result = spawn_blocking(move || {
@@ -431,6 +508,9 @@ async fn import_relations(
     State(st): State<DbState>,
     Json(payload): Json<serde_json::Value>,
 ) -> (StatusCode, Json<serde_json::Value>) {
+    if st.read_only {
+        return read_only_error();
+    }
     let payload = match payload.as_object() {
         None => {
             return (
@@ -498,6 +578,9 @@ async fn import_from_backup(
     State(st): State<DbState>,
     Json(payload): Json<BackupImportPayload>,
 ) -> (StatusCode, Json<serde_json::Value>) {
+    if st.read_only {
+        return read_only_error();
+    }
     let result =
         spawn_blocking(move || st.db.import_from_backup(&payload.path, &payload.relations)).await;
 
@@ -662,6 +745,94 @@ async fn observe_changes(
     Sse::new(stream).keep_alive(KeepAlive::default())
 }
 
+#[derive(serde_derive::Deserialize)]
+struct ChangelogParams {
+    #[serde(default)]
+    from: i64,
+    limit: Option<usize>,
+}
+
+async fn read_changelog(
+    State(st): State<DbState>,
+    Path(relation): Path<String>,
+    Query(params): Query<ChangelogParams>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let result =
+        spawn_blocking(move || st.db.read_changelog(&relation, params.from, params.limit)).await;
+    match result {
+        Ok(Ok(res)) => {
+            let mut ret = res.into_json();
+            ret["ok"] = json!(true);
+            (StatusCode::OK, ret.into())
+        }
+        Ok(Err(err)) => {
+            let ret = json!({"ok": false, "message": err.to_string()});
+            (StatusCode::BAD_REQUEST, ret.into())
+        }
+        Err(err) => internal_error(err),
+    }
+}
+
+async fn changelog_relations(State(st): State<DbState>) -> (StatusCode, Json<serde_json::Value>) {
+    let result = spawn_blocking(move || st.db.changelog_relations()).await;
+    match result {
+        Ok(Ok(relations)) => (
+            StatusCode::OK,
+            json!({"ok": true, "relations": relations}).into(),
+        ),
+        Ok(Err(err)) => {
+            let ret = json!({"ok": false, "message": err.to_string()});
+            (StatusCode::BAD_REQUEST, ret.into())
+        }
+        Err(err) => internal_error(err),
+    }
+}
+
+async fn snapshot_relation(
+    State(st): State<DbState>,
+    Path(relation): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let result = spawn_blocking(move || st.db.snapshot_relation(&relation)).await;
+    match result {
+        Ok(Ok(snapshot)) => {
+            let mut ret = snapshot.rows.into_json();
+            ret["ok"] = json!(true);
+            ret["schema"] = json!(snapshot.schema);
+            ret["seq"] = json!(snapshot.seq);
+            (StatusCode::OK, ret.into())
+        }
+        Ok(Err(err)) => {
+            let ret = json!({"ok": false, "message": err.to_string()});
+            (StatusCode::BAD_REQUEST, ret.into())
+        }
+        Err(err) => internal_error(err),
+    }
+}
+
+/// Opens the database file of `leader` for reading only. Only SQLite files can be read while
+/// another process writes to them, other engines lock their files.
+fn open_replication_source(leader: &str) -> miette::Result<DbInstance> {
+    let mut header = [0u8; 16];
+    let is_sqlite = std::fs::File::open(leader)
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut header))
+        .is_ok()
+        && &header == b"SQLite format 3\0";
+    if !is_sqlite {
+        miette::bail!(
+            "{} is not an SQLite database file, give the URL of the server of the database instead",
+            leader
+        )
+    }
+    DbInstance::new("sqlite", leader, r#"{"read_only": true}"#)
+}
+
+fn read_only_error() -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::FORBIDDEN,
+        json!({"ok": false, "message": "the database is a read-only replica"}).into(),
+    )
+}
+
 async fn root() -> Html<&'static str> {
     Html(include_str!("./index.html"))
 }
@@ -52,6 +52,8 @@ pub(crate) struct QueryOutOptions {
     pub(crate) offset: Option<usize>,
     pub(crate) timeout: Option<f64>,
     pub(crate) sleep: Option<f64>,
+    /// `Some(None)` if the query explicitly asks for no limit
+    pub(crate) memory_limit: Option<Option<usize>>,
     pub(crate) sorters: Vec<(Symbol, SortDir)>,
     pub(crate) store_relation: Option<(InputRelationHandle, RelationOp, ReturnMutation)>,
     pub(crate) assertion: Option<QueryAssertion>,
@@ -74,6 +76,11 @@ impl Display for QueryOutOptions {
         if let Some(l) = self.timeout {
             writeln!(f, ":timeout {l};")?;
         }
+        match self.memory_limit {
+            None => {}
+            Some(None) => writeln!(f, ":memory_limit null;")?,
+            Some(Some(l)) => writeln!(f, ":memory_limit {l};")?,
+        }
         for (symb, dir) in &self.sorters {
             write!(f, ":order ")?;
             if *dir == SortDir::Dsc {
@@ -0,0 +1,270 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighbors, Graph};
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+pub(crate) struct ArticulationPoints;
+
+impl FixedRule for ArticulationPoints {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let (graph, indices, _) = edges.as_directed_graph(true)?;
+        let result = biconnectivity(&graph, poison)?;
+        for (node, is_cut) in indices.into_iter().zip(result.articulation_points) {
+            if is_cut {
+                out.put(vec![node]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(1)
+    }
+}
+
+pub(crate) struct Bridges;
+
+impl FixedRule for Bridges {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let (graph, indices, _) = edges.as_directed_graph(true)?;
+        let result = biconnectivity(&graph, poison)?;
+        for (from, to) in result.bridges {
+            out.put(vec![
+                indices[from as usize].clone(),
+                indices[to as usize].clone(),
+            ]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+pub(crate) struct BiconnectedComponents;
+
+impl FixedRule for BiconnectedComponents {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let (graph, indices, _) = edges.as_directed_graph(true)?;
+        let result = biconnectivity(&graph, poison)?;
+        for (grp_id, component) in result.components.into_iter().enumerate() {
+            for node in component {
+                out.put(vec![
+                    indices[node as usize].clone(),
+                    DataValue::from(grp_id as i64),
+                ]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+struct Biconnectivity {
+    articulation_points: Vec<bool>,
+    /// each bridge once, in the direction it was traversed
+    bridges: Vec<(u32, u32)>,
+    /// nodes of each biconnected component; articulation points belong to several.
+    /// Nodes without any edges other than self-loops belong to none.
+    components: Vec<Vec<u32>>,
+}
+
+/// Hopcroft-Tarjan on the graph taken as undirected, with an explicit stack so that long
+/// paths do not overflow the call stack. The graph is expected to contain both directions
+/// of every edge.
+fn biconnectivity(graph: &DirectedCsrGraph<u32>, poison: Poison) -> Result<Biconnectivity> {
+    let n = graph.node_count() as usize;
+    let neighbors = (0..n as u32)
+        .map(|node| {
+            graph
+                .out_neighbors(node)
+                .dedup()
+                .filter(|to| **to != node)
+                .copied()
+                .collect_vec()
+        })
+        .collect_vec();
+
+    let mut discovered = vec![usize::MAX; n];
+    let mut low = vec![0; n];
+    let mut next_id = 0;
+    let mut articulation_points = vec![false; n];
+    let mut bridges = vec![];
+    let mut components = vec![];
+    let mut edge_stack: Vec<(u32, u32)> = vec![];
+
+    for root in 0..n {
+        if discovered[root] != usize::MAX {
+            continue;
+        }
+        discovered[root] = next_id;
+        low[root] = next_id;
+        next_id += 1;
+        let mut root_children = 0;
+        // (node, parent, position in the neighbour list)
+        let mut stack = vec![(root, usize::MAX, 0)];
+        while let Some((node, parent, pos)) = stack.pop() {
+            poison.check()?;
+            if let Some(&to) = neighbors[node].get(pos) {
+                stack.push((node, parent, pos + 1));
+                let to = to as usize;
+                if to == parent {
+                    continue;
+                }
+                if discovered[to] == usize::MAX {
+                    discovered[to] = next_id;
+                    low[to] = next_id;
+                    next_id += 1;
+                    edge_stack.push((node as u32, to as u32));
+                    stack.push((to, node, 0));
+                } else if discovered[to] < discovered[node] {
+                    low[node] = low[node].min(discovered[to]);
+                    edge_stack.push((node as u32, to as u32));
+                }
+            } else if parent != usize::MAX {
+                low[parent] = low[parent].min(low[node]);
+                if low[node] >= discovered[parent] {
+                    if parent == root {
+                        root_children += 1;
+                    } else {
+                        articulation_points[parent] = true;
+                    }
+                    let mut component = vec![];
+                    while let Some((from, to)) = edge_stack.pop() {
+                        component.push(from);
+                        component.push(to);
+                        if (from as usize, to as usize) == (parent, node) {
+                            break;
+                        }
+                    }
+                    component.sort();
+                    component.dedup();
+                    components.push(component);
+                }
+                if low[node] > discovered[parent] {
+                    bridges.push((parent as u32, node as u32));
+                }
+            }
+        }
+        articulation_points[root] = root_children > 1;
+    }
+
+    Ok(Biconnectivity {
+        articulation_points,
+        bridges,
+        components,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use serde_json::json;
+
+    use crate::DbInstance;
+
+    #[test]
+    fn test_biconnectivity() {
+        let db = DbInstance::default();
+        let edges = r#"
+            edges[f, t] <- [[1, 2], [2, 3], [3, 1], [3, 4], [4, 5], [5, 6], [6, 4], [7, 6], [8, 9],
+                            [2, 1], [9, 9]]
+        "#;
+        let res = db
+            .run_default(&format!("{edges} ?[n] <~ ArticulationPoints(edges[])"))
+            .unwrap();
+        assert_eq!(res.into_json()["rows"], json!([[3], [4], [6]]));
+
+        let res = db
+            .run_default(&format!(
+                "{edges} b[f, t] <~ Bridges(edges[]) ?[a, b] := b[f, t], a = min(f, t), b = max(f, t)"
+            ))
+            .unwrap();
+        assert_eq!(res.into_json()["rows"], json!([[3, 4], [6, 7], [8, 9]]));
+
+        let res = db
+            .run_default(&format!(
+                "{edges} c[n, id] <~ BiconnectedComponents(edges[]) ?[id, collect(n)] := c[n, id]"
+            ))
+            .unwrap();
+        let components = res
+            .rows
+            .iter()
+            .map(|row| {
+                row[1]
+                    .get_slice()
+                    .unwrap()
+                    .iter()
+                    .map(|n| n.get_int().unwrap())
+                    .sorted()
+                    .collect_vec()
+            })
+            .sorted()
+            .collect_vec();
+        assert_eq!(
+            components,
+            vec![
+                vec![1, 2, 3],
+                vec![3, 4],
+                vec![4, 5, 6],
+                vec![6, 7],
+                vec![8, 9]
+            ]
+        );
+    }
+}
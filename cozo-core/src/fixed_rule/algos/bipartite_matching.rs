@@ -0,0 +1,327 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, VecDeque};
+
+use miette::Result;
+use ordered_float::OrderedFloat;
+use priority_queue::PriorityQueue;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+pub(crate) struct BipartiteMatching;
+
+impl FixedRule for BipartiteMatching {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let (edges, left, right) = edges.as_bipartite_graph(true)?;
+
+        let mut adjacency = vec![vec![]; left.len()];
+        for (from, to, _) in edges {
+            adjacency[from as usize].push(to);
+        }
+        let matched = hopcroft_karp(&adjacency, right.len(), poison)?;
+        for (l, r) in matched.into_iter().enumerate() {
+            if let Some(r) = r {
+                out.put(vec![left[l].clone(), right[r as usize].clone()]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+pub(crate) struct MinCostAssignment;
+
+impl FixedRule for MinCostAssignment {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let maximize = payload.bool_option("maximize", Some(false))?;
+        let (edges, left, right) = edges.as_bipartite_graph(true)?;
+
+        for (l, r, w) in min_cost_assignment(&edges, left.len(), right.len(), maximize, poison)? {
+            out.put(vec![
+                left[l as usize].clone(),
+                right[r as usize].clone(),
+                DataValue::from(w),
+            ]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+}
+
+/// Returns the right vertex matched to each left vertex.
+fn hopcroft_karp(
+    adjacency: &[Vec<u32>],
+    n_right: usize,
+    poison: Poison,
+) -> Result<Vec<Option<u32>>> {
+    let n_left = adjacency.len();
+    let mut match_left: Vec<Option<u32>> = vec![None; n_left];
+    let mut match_right: Vec<Option<u32>> = vec![None; n_right];
+    loop {
+        // layer the left vertices by their distance from the free left vertices
+        let mut dist = vec![usize::MAX; n_left];
+        let mut queue = VecDeque::new();
+        for l in 0..n_left {
+            if match_left[l].is_none() {
+                dist[l] = 0;
+                queue.push_back(l);
+            }
+        }
+        let mut found = false;
+        while let Some(l) = queue.pop_front() {
+            for &r in &adjacency[l] {
+                match match_right[r as usize] {
+                    None => found = true,
+                    Some(next) => {
+                        let next = next as usize;
+                        if dist[next] == usize::MAX {
+                            dist[next] = dist[l] + 1;
+                            queue.push_back(next);
+                        }
+                    }
+                }
+            }
+        }
+        if !found {
+            break;
+        }
+
+        // augment along vertex-disjoint shortest paths, walking the layers without recursion
+        let mut next_edge = vec![0; n_left];
+        for root in 0..n_left {
+            if match_left[root].is_some() {
+                continue;
+            }
+            let mut stack = vec![root];
+            let mut chosen: Vec<u32> = vec![];
+            while let Some(&l) = stack.last() {
+                if next_edge[l] == adjacency[l].len() {
+                    dist[l] = usize::MAX;
+                    stack.pop();
+                    chosen.pop();
+                    continue;
+                }
+                let r = adjacency[l][next_edge[l]];
+                next_edge[l] += 1;
+                match match_right[r as usize] {
+                    None => {
+                        chosen.push(r);
+                        for (l, r) in stack.iter().zip(chosen.iter()) {
+                            match_left[*l] = Some(*r);
+                            match_right[*r as usize] = Some(*l as u32);
+                        }
+                        break;
+                    }
+                    Some(next) => {
+                        let next = next as usize;
+                        if dist[next] == dist[l] + 1 {
+                            chosen.push(r);
+                            stack.push(next);
+                        }
+                    }
+                }
+            }
+        }
+        poison.check()?;
+    }
+    Ok(match_left)
+}
+
+/// Successive shortest paths on the network source -> left -> right -> sink with unit
+/// capacities, so that the result is a maximum matching of minimum total weight
+/// (or maximum total weight if `maximize` is set).
+fn min_cost_assignment(
+    edges: &[(u32, u32, f64)],
+    n_left: usize,
+    n_right: usize,
+    maximize: bool,
+    poison: Poison,
+) -> Result<Vec<(u32, u32, f64)>> {
+    let source = 0;
+    let sink = n_left + n_right + 1;
+    let n = sink + 1;
+
+    // Every maximum matching has the same number of edges, so shifting all costs by a
+    // constant does not change which one is cheapest, and keeps the costs non-negative
+    // as Dijkstra requires.
+    let costs = edges
+        .iter()
+        .map(|(_, _, w)| if maximize { -*w } else { *w })
+        .collect::<Vec<_>>();
+    let shift = costs.iter().copied().fold(0., f64::min);
+
+    // (to, residual capacity, cost); the arc at `i ^ 1` is the reverse of the arc at `i`
+    let mut arcs: Vec<(usize, u32, f64)> = vec![];
+    let mut adjacency = vec![vec![]; n];
+    let mut add_arc = |from: usize, to: usize, cost: f64| {
+        adjacency[from].push(arcs.len());
+        arcs.push((to, 1, cost));
+        adjacency[to].push(arcs.len());
+        arcs.push((from, 0, -cost));
+    };
+    for l in 0..n_left {
+        add_arc(source, 1 + l, 0.);
+    }
+    for r in 0..n_right {
+        add_arc(1 + n_left + r, sink, 0.);
+    }
+    let first_edge_arc = 2 * (n_left + n_right);
+    for ((l, r, _), cost) in edges.iter().zip(costs) {
+        add_arc(1 + *l as usize, 1 + n_left + *r as usize, cost - shift);
+    }
+
+    let mut potential = vec![0.; n];
+    loop {
+        let mut dist = vec![f64::INFINITY; n];
+        let mut back_arc = vec![usize::MAX; n];
+        let mut pq = PriorityQueue::new();
+        dist[source] = 0.;
+        pq.push(source, Reverse(OrderedFloat(0.)));
+        while let Some((node, Reverse(OrderedFloat(d)))) = pq.pop() {
+            if d > dist[node] {
+                continue;
+            }
+            for &arc in &adjacency[node] {
+                let (to, capacity, cost) = arcs[arc];
+                if capacity == 0 {
+                    continue;
+                }
+                // reduced costs are non-negative up to rounding
+                let reduced = (cost + potential[node] - potential[to]).max(0.);
+                let nxt = d + reduced;
+                if nxt < dist[to] {
+                    dist[to] = nxt;
+                    back_arc[to] = arc;
+                    pq.push_increase(to, Reverse(OrderedFloat(nxt)));
+                }
+            }
+        }
+        if !dist[sink].is_finite() {
+            break;
+        }
+        for (p, d) in potential.iter_mut().zip(dist.iter()) {
+            if d.is_finite() {
+                *p += d;
+            }
+        }
+        let mut node = sink;
+        while node != source {
+            let arc = back_arc[node];
+            arcs[arc].1 -= 1;
+            arcs[arc ^ 1].1 += 1;
+            node = arcs[arc ^ 1].0;
+        }
+        poison.check()?;
+    }
+
+    Ok(edges
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| arcs[first_edge_arc + 2 * i].1 == 0)
+        .map(|(_, edge)| *edge)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use serde_json::json;
+
+    use crate::DbInstance;
+
+    #[test]
+    fn test_bipartite_matching() {
+        let db = DbInstance::default();
+        let res = db
+            .run_default(
+                r#"
+                edges[l, r] <- [['l1', 'r1'], ['l1', 'r2'], ['l2', 'r1'], ['l3', 'r2'], ['r1', 'l1']]
+                ?[l, r] <~ BipartiteMatching(edges[])
+                "#,
+            )
+            .unwrap();
+        assert_eq!(res.rows.len(), 3);
+        assert!(res.rows.iter().any(|row| row[0].get_str() == Some("r1")));
+        assert_eq!(
+            res.rows.iter().map(|row| &row[1]).unique().count(),
+            res.rows.len()
+        );
+
+        let costs = r#"
+            costs[w, j, c] <- [['a', 'x', 4], ['a', 'y', 1], ['a', 'z', 3],
+                               ['b', 'x', 2], ['b', 'y', 0], ['b', 'z', 5],
+                               ['c', 'x', 3], ['c', 'y', 2], ['c', 'z', 2]]
+        "#;
+        let res = db
+            .run_default(&format!("{costs} ?[w, j, c] <~ MinCostAssignment(costs[])"))
+            .unwrap();
+        assert_eq!(
+            res.into_json()["rows"],
+            json!([["a", "y", 1.0], ["b", "x", 2.0], ["c", "z", 2.0]])
+        );
+        let res = db
+            .run_default(&format!(
+                "{costs} ?[w, j, c] <~ MinCostAssignment(costs[], maximize: true)"
+            ))
+            .unwrap();
+        assert_eq!(
+            res.into_json()["rows"],
+            json!([["a", "x", 4.0], ["b", "z", 5.0], ["c", "y", 2.0]])
+        );
+
+        let res = db
+            .run_default(
+                r#"
+                costs[w, j, c] <- [['a', 'x', -1], ['a', 'y', -5], ['b', 'y', -2]]
+                ?[w, j, c] <~ MinCostAssignment(costs[])
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            res.into_json()["rows"],
+            json!([["a", "x", -1.0], ["b", "y", -2.0]])
+        );
+    }
+}
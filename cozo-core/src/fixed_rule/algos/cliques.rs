@@ -0,0 +1,234 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighbors, Graph};
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::algos::k_core::core_decomposition;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+pub(crate) struct MaximalCliques;
+
+impl FixedRule for MaximalCliques {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let min_size = payload.pos_integer_option("min_size", Some(1))?;
+        let limit = payload
+            .has_option("limit")
+            .then(|| payload.pos_integer_option("limit", None))
+            .transpose()?;
+
+        let (graph, indices, _) = edges.as_directed_graph(true)?;
+        let mut clique_id = 0;
+        let mut emit = |clique: &[u32]| {
+            for node in clique.iter().sorted() {
+                out.put(vec![
+                    DataValue::from(clique_id as i64),
+                    indices[*node as usize].clone(),
+                ]);
+            }
+            clique_id += 1;
+            !matches!(limit, Some(l) if clique_id >= l)
+        };
+        maximal_cliques(&graph, min_size, &mut emit, poison)?;
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// Bron-Kerbosch with pivoting, with the outermost level in degeneracy order so that the
+/// candidate sets stay no larger than the degeneracy of the graph (Eppstein et al.).
+/// `emit` returns whether more cliques are wanted.
+fn maximal_cliques(
+    graph: &DirectedCsrGraph<u32>,
+    min_size: usize,
+    emit: &mut impl FnMut(&[u32]) -> bool,
+    poison: Poison,
+) -> Result<()> {
+    let neighbors = (0..graph.node_count())
+        .map(|node| {
+            graph
+                .out_neighbors(node)
+                .dedup()
+                .filter(|to| **to != node)
+                .copied()
+                .collect_vec()
+        })
+        .collect_vec();
+    let (_, position) = core_decomposition(graph, poison.clone())?;
+
+    let mut search = CliqueSearch {
+        neighbors: &neighbors,
+        min_size,
+        emit,
+        poison,
+    };
+    for node in (0..neighbors.len()).sorted_by_key(|node| position[*node]) {
+        let (later, earlier): (Vec<u32>, Vec<u32>) = neighbors[node]
+            .iter()
+            .partition(|to| position[**to as usize] > position[node]);
+        if !search.expand(&mut vec![node as u32], later, earlier)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+struct CliqueSearch<'a, F> {
+    /// sorted neighbours of each node
+    neighbors: &'a [Vec<u32>],
+    min_size: usize,
+    emit: &'a mut F,
+    poison: Poison,
+}
+
+impl<F: FnMut(&[u32]) -> bool> CliqueSearch<'_, F> {
+    fn intersect(&self, set: &[u32], node: u32) -> Vec<u32> {
+        let neighbors = &self.neighbors[node as usize];
+        set.iter()
+            .filter(|n| neighbors.binary_search(n).is_ok())
+            .copied()
+            .collect_vec()
+    }
+
+    /// Extend the clique `current` with the `candidates`, none of which may be added if it is
+    /// adjacent to all of `excluded`. Returns whether the enumeration should go on.
+    fn expand(
+        &mut self,
+        current: &mut Vec<u32>,
+        mut candidates: Vec<u32>,
+        mut excluded: Vec<u32>,
+    ) -> Result<bool> {
+        if candidates.is_empty() {
+            if excluded.is_empty() && current.len() >= self.min_size {
+                return Ok((self.emit)(current));
+            }
+            return Ok(true);
+        }
+        if current.len() + candidates.len() < self.min_size {
+            return Ok(true);
+        }
+        self.poison.check()?;
+
+        let pivot = candidates
+            .iter()
+            .chain(excluded.iter())
+            .copied()
+            .max_by_key(|u| {
+                let neighbors = &self.neighbors[*u as usize];
+                candidates
+                    .iter()
+                    .filter(|c| neighbors.binary_search(c).is_ok())
+                    .count()
+            })
+            .unwrap();
+        let pivot_neighbors = &self.neighbors[pivot as usize];
+        let branches = candidates
+            .iter()
+            .filter(|c| pivot_neighbors.binary_search(c).is_err())
+            .copied()
+            .collect_vec();
+        for node in branches {
+            current.push(node);
+            let proceed = self.expand(
+                current,
+                self.intersect(&candidates, node),
+                self.intersect(&excluded, node),
+            )?;
+            current.pop();
+            if !proceed {
+                return Ok(false);
+            }
+            candidates.retain(|c| *c != node);
+            excluded.push(node);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use crate::data::value::DataValue;
+    use crate::DbInstance;
+
+    #[test]
+    fn test_maximal_cliques() {
+        let db = DbInstance::default();
+        let edges = r#"
+            edges[f, t] <- [[1, 2], [1, 3], [1, 4], [2, 3], [2, 4], [3, 4], [4, 5], [5, 6], [6, 4],
+                            [2, 1], [7, 8], [9, 9]]
+        "#;
+        let res = db
+            .run_default(&format!(
+                "{edges} c[id, n] <~ MaximalCliques(edges[]) ?[id, collect(n)] := c[id, n]"
+            ))
+            .unwrap();
+        let cliques = res
+            .rows
+            .iter()
+            .map(|row| {
+                row[1]
+                    .get_slice()
+                    .unwrap()
+                    .iter()
+                    .map(|n| n.get_int().unwrap())
+                    .sorted()
+                    .collect_vec()
+            })
+            .sorted()
+            .collect_vec();
+        assert_eq!(
+            cliques,
+            vec![vec![1, 2, 3, 4], vec![4, 5, 6], vec![7, 8], vec![9]]
+        );
+
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[id, n] <~ MaximalCliques(edges[], min_size: 3)"
+            ))
+            .unwrap();
+        assert_eq!(res.rows.len(), 7);
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[id, n] <~ MaximalCliques(edges[], min_size: 3, limit: 1)"
+            ))
+            .unwrap();
+        assert!(res.rows.iter().all(|row| row[0] == DataValue::from(0)));
+        assert!(res.rows.len() == 3 || res.rows.len() == 4);
+        assert!(db
+            .run_default(&format!(
+                "{edges} ?[id, n] <~ MaximalCliques(edges[], limit: 0)"
+            ))
+            .is_err());
+    }
+}
@@ -0,0 +1,419 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighbors, Graph};
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+pub(crate) struct SimpleCycles;
+
+impl FixedRule for SimpleCycles {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let max_length = payload
+            .has_option("max_length")
+            .then(|| payload.pos_integer_option("max_length", None))
+            .transpose()?;
+        let limit = payload
+            .has_option("limit")
+            .then(|| payload.pos_integer_option("limit", None))
+            .transpose()?;
+
+        let (graph, indices, _) = edges.as_directed_graph(false)?;
+        let adjacency = adjacency_lists(&graph);
+        let mut count = 0;
+        let mut emit = |cycle: &[u32]| {
+            out.put(vec![cycle_to_path(cycle, &indices)]);
+            count += 1;
+            !matches!(limit, Some(l) if count >= l)
+        };
+
+        let mut proceed = true;
+        for (node, neighbors) in adjacency.iter().enumerate() {
+            if proceed && neighbors.contains(&(node as u32)) {
+                proceed = emit(&[node as u32]);
+            }
+        }
+        if proceed {
+            simple_cycles(&adjacency, max_length, &mut emit, &poison)?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(1)
+    }
+}
+
+pub(crate) struct FindCycle;
+
+impl FixedRule for FindCycle {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let (graph, indices, _) = edges.as_directed_graph(false)?;
+        if let Some(cycle) = find_cycle(&graph, poison)? {
+            out.put(vec![cycle_to_path(&cycle, &indices)]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(1)
+    }
+}
+
+/// The cycle as a path, with the first node repeated at the end
+fn cycle_to_path(cycle: &[u32], indices: &[DataValue]) -> DataValue {
+    DataValue::List(
+        cycle
+            .iter()
+            .chain(cycle.first())
+            .map(|idx| indices[*idx as usize].clone())
+            .collect_vec(),
+    )
+}
+
+/// Deduplicated out-neighbours of every node
+fn adjacency_lists(graph: &DirectedCsrGraph<u32>) -> Vec<Vec<u32>> {
+    (0..graph.node_count())
+        .map(|node| graph.out_neighbors(node).dedup().copied().collect_vec())
+        .collect_vec()
+}
+
+/// Strongly connected components with more than one node of the subgraph induced by the
+/// `active` nodes, ignoring self-loops. Iterative Tarjan.
+fn nontrivial_sccs(adjacency: &[Vec<u32>], active: &[bool]) -> Vec<Vec<u32>> {
+    let n = adjacency.len();
+    let mut ids = vec![usize::MAX; n];
+    let mut low = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = vec![];
+    let mut next_id = 0;
+    let mut ret = vec![];
+    for root in 0..n {
+        if !active[root] || ids[root] != usize::MAX {
+            continue;
+        }
+        let mut call_stack = vec![(root, 0)];
+        ids[root] = next_id;
+        low[root] = next_id;
+        next_id += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        while let Some((node, pos)) = call_stack.pop() {
+            if let Some(&to) = adjacency[node].get(pos) {
+                call_stack.push((node, pos + 1));
+                let to = to as usize;
+                if !active[to] || to == node {
+                    continue;
+                }
+                if ids[to] == usize::MAX {
+                    ids[to] = next_id;
+                    low[to] = next_id;
+                    next_id += 1;
+                    stack.push(to);
+                    on_stack[to] = true;
+                    call_stack.push((to, 0));
+                } else if on_stack[to] {
+                    low[node] = low[node].min(ids[to]);
+                }
+            } else {
+                if low[node] == ids[node] {
+                    let mut component = vec![];
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member as u32);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    if component.len() > 1 {
+                        ret.push(component);
+                    }
+                }
+                if let Some((parent, _)) = call_stack.last() {
+                    low[*parent] = low[*parent].min(low[node]);
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// Johnson's algorithm: find the cycles through the smallest node of a strongly connected
+/// component, remove that node, and repeat on the components of what is left.
+/// Self-loops are not reported. With `max_length`, the blocking of Johnson's algorithm is not
+/// valid any more and a plain depth-limited search is used instead.
+/// `emit` returns whether more cycles are wanted.
+fn simple_cycles(
+    adjacency: &[Vec<u32>],
+    max_length: Option<usize>,
+    emit: &mut impl FnMut(&[u32]) -> bool,
+    poison: &Poison,
+) -> Result<()> {
+    let n = adjacency.len();
+    let mut active = vec![false; n];
+    let mut components = nontrivial_sccs(adjacency, &vec![true; n]);
+    while let Some(component) = components.pop() {
+        for node in &component {
+            active[*node as usize] = true;
+        }
+        let start = *component.iter().min().unwrap();
+        let proceed = match max_length {
+            None => johnson_search(adjacency, &active, start, emit, poison)?,
+            Some(l) => bounded_search(adjacency, &active, start, l, emit, poison)?,
+        };
+        if !proceed {
+            return Ok(());
+        }
+        active[start as usize] = false;
+        components.extend(nontrivial_sccs(adjacency, &active));
+        for node in &component {
+            active[*node as usize] = false;
+        }
+    }
+    Ok(())
+}
+
+fn johnson_search(
+    adjacency: &[Vec<u32>],
+    active: &[bool],
+    start: u32,
+    emit: &mut impl FnMut(&[u32]) -> bool,
+    poison: &Poison,
+) -> Result<bool> {
+    let n = adjacency.len();
+    let mut blocked = vec![false; n];
+    let mut blocked_by: Vec<Vec<u32>> = vec![vec![]; n];
+    let mut path = vec![start];
+    // position in the neighbour list of each node on the path, and whether a cycle was closed
+    let mut positions = vec![0];
+    let mut closed = vec![false];
+    blocked[start as usize] = true;
+    while let Some(&node) = path.last() {
+        let pos = positions.last_mut().unwrap();
+        if let Some(&to) = adjacency[node as usize].get(*pos) {
+            *pos += 1;
+            if !active[to as usize] || to == node {
+                continue;
+            }
+            if to == start {
+                if !emit(&path) {
+                    return Ok(false);
+                }
+                *closed.last_mut().unwrap() = true;
+                poison.check()?;
+            } else if !blocked[to as usize] {
+                path.push(to);
+                positions.push(0);
+                closed.push(false);
+                blocked[to as usize] = true;
+            }
+        } else {
+            path.pop();
+            positions.pop();
+            if closed.pop().unwrap() {
+                if let Some(parent) = closed.last_mut() {
+                    *parent = true;
+                }
+                let mut to_unblock = vec![node];
+                while let Some(u) = to_unblock.pop() {
+                    if blocked[u as usize] {
+                        blocked[u as usize] = false;
+                        to_unblock.append(&mut blocked_by[u as usize]);
+                    }
+                }
+            } else {
+                for &to in &adjacency[node as usize] {
+                    if active[to as usize] && !blocked_by[to as usize].contains(&node) {
+                        blocked_by[to as usize].push(node);
+                    }
+                }
+            }
+        }
+    }
+    Ok(true)
+}
+
+fn bounded_search(
+    adjacency: &[Vec<u32>],
+    active: &[bool],
+    start: u32,
+    max_length: usize,
+    emit: &mut impl FnMut(&[u32]) -> bool,
+    poison: &Poison,
+) -> Result<bool> {
+    let mut on_path = vec![false; adjacency.len()];
+    let mut path = vec![start];
+    let mut positions = vec![0];
+    on_path[start as usize] = true;
+    while let Some(&node) = path.last() {
+        let pos = positions.last_mut().unwrap();
+        if let Some(&to) = adjacency[node as usize].get(*pos) {
+            *pos += 1;
+            if !active[to as usize] || to == node {
+                continue;
+            }
+            if to == start {
+                if !emit(&path) {
+                    return Ok(false);
+                }
+                poison.check()?;
+            } else if !on_path[to as usize] && path.len() < max_length {
+                path.push(to);
+                positions.push(0);
+                on_path[to as usize] = true;
+            }
+        } else {
+            on_path[node as usize] = false;
+            path.pop();
+            positions.pop();
+        }
+    }
+    Ok(true)
+}
+
+/// Depth-first search for a back edge, returning the cycle it closes
+fn find_cycle(graph: &DirectedCsrGraph<u32>, poison: Poison) -> Result<Option<Vec<u32>>> {
+    let n = graph.node_count() as usize;
+    // 0: unvisited, 1: on the current path, 2: finished
+    let mut state = vec![0u8; n];
+    for root in 0..n as u32 {
+        if state[root as usize] != 0 {
+            continue;
+        }
+        let mut path = vec![root];
+        let mut iters = vec![graph.out_neighbors(root)];
+        state[root as usize] = 1;
+        while let Some(it) = iters.last_mut() {
+            poison.check()?;
+            match it.next() {
+                Some(&to) => match state[to as usize] {
+                    0 => {
+                        state[to as usize] = 1;
+                        path.push(to);
+                        iters.push(graph.out_neighbors(to));
+                    }
+                    1 => {
+                        let begin = path.iter().position(|node| *node == to).unwrap();
+                        return Ok(Some(path.split_off(begin)));
+                    }
+                    _ => {}
+                },
+                None => {
+                    let node = path.pop().unwrap();
+                    state[node as usize] = 2;
+                    iters.pop();
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::DbInstance;
+
+    #[test]
+    fn test_simple_cycles() {
+        let db = DbInstance::default();
+        let edges = r#"
+            edges[f, t] <- [['a', 'b'], ['b', 'c'], ['c', 'a'], ['b', 'a'], ['c', 'd'], ['d', 'd'],
+                            ['d', 'e'], ['e', 'f'], ['f', 'g'], ['g', 'e'], ['x', 'y']]
+        "#;
+        let res = db
+            .run_default(&format!("{edges} ?[c] <~ SimpleCycles(edges[])"))
+            .unwrap();
+        assert_eq!(
+            res.into_json()["rows"],
+            json!([
+                [["a", "b", "a"]],
+                [["a", "b", "c", "a"]],
+                [["d", "d"]],
+                [["e", "f", "g", "e"]]
+            ])
+        );
+
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[c] <~ SimpleCycles(edges[], max_length: 2)"
+            ))
+            .unwrap();
+        assert_eq!(
+            res.into_json()["rows"],
+            json!([[["a", "b", "a"]], [["d", "d"]]])
+        );
+
+        let res = db
+            .run_default(&format!("{edges} ?[c] <~ SimpleCycles(edges[], limit: 2)"))
+            .unwrap();
+        assert_eq!(res.rows.len(), 2);
+        for opts in ["limit: 0", "max_length: 0", "max_length: 'x'"] {
+            assert!(db
+                .run_default(&format!("{edges} ?[c] <~ SimpleCycles(edges[], {opts})"))
+                .is_err());
+        }
+
+        // every cycle of a complete directed graph on 5 nodes
+        let res = db
+            .run_default(
+                r#"
+                nodes[n] <- [[1], [2], [3], [4], [5]]
+                edges[a, b] := nodes[a], nodes[b], a != b
+                ?[c] <~ SimpleCycles(edges[])
+                "#,
+            )
+            .unwrap();
+        assert_eq!(res.rows.len(), 84);
+
+        let res = db
+            .run_default(&format!("{edges} ?[c] <~ FindCycle(edges[])"))
+            .unwrap();
+        let cycle = res.rows[0][0].get_slice().unwrap();
+        assert_eq!(cycle.first(), cycle.last());
+        assert!(db
+            .run_default("edges[f, t] <- [[1, 2], [2, 3], [1, 3]] ?[c] <~ FindCycle(edges[])")
+            .unwrap()
+            .rows
+            .is_empty());
+    }
+}
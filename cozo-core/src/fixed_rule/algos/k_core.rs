@@ -0,0 +1,192 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighbors, Graph};
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+pub(crate) struct KCore;
+
+impl FixedRule for KCore {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let undirected = payload.bool_option("undirected", Some(true))?;
+        let k = payload.non_neg_integer_option("k", Some(0))?;
+
+        let (graph, indices, _) = edges.as_directed_graph(undirected)?;
+        let (cores, order) = core_decomposition(&graph, poison)?;
+        for (idx, node) in indices.into_iter().enumerate() {
+            if cores[idx] >= k {
+                out.put(vec![
+                    node,
+                    DataValue::from(cores[idx] as i64),
+                    DataValue::from(order[idx] as i64),
+                ]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+}
+
+/// Batagelj-Zaversnik peeling: repeatedly remove a node of minimum remaining degree.
+/// The degree counted is the number of distinct out-neighbours, which for a graph built
+/// with `undirected` is the usual degree. Returns the core number of every node and
+/// the position of the node in the removal order, which is a degeneracy ordering.
+pub(crate) fn core_decomposition(
+    graph: &DirectedCsrGraph<u32>,
+    poison: Poison,
+) -> Result<(Vec<usize>, Vec<usize>)> {
+    let n = graph.node_count() as usize;
+    let mut in_neighbors: Vec<Vec<u32>> = vec![vec![]; n];
+    let mut degree = vec![0; n];
+    for (node, d) in degree.iter_mut().enumerate() {
+        for to in graph.out_neighbors(node as u32).dedup() {
+            if *to as usize != node {
+                *d += 1;
+                in_neighbors[*to as usize].push(node as u32);
+            }
+        }
+    }
+
+    // nodes sorted by degree, with `bin_start[d]` the position of the first node of degree `d`
+    let max_degree = degree.iter().copied().max().unwrap_or(0);
+    let mut bin_start = vec![0; max_degree + 1];
+    for d in &degree {
+        bin_start[*d] += 1;
+    }
+    let mut start = 0;
+    for bin in bin_start.iter_mut() {
+        let count = *bin;
+        *bin = start;
+        start += count;
+    }
+    let mut sorted = vec![0; n];
+    let mut position = vec![0; n];
+    {
+        let mut next = bin_start.clone();
+        for node in 0..n {
+            position[node] = next[degree[node]];
+            sorted[position[node]] = node;
+            next[degree[node]] += 1;
+        }
+    }
+
+    for i in 0..n {
+        let node = sorted[i];
+        for &u in &in_neighbors[node] {
+            let u = u as usize;
+            if degree[u] > degree[node] {
+                // move `u` to the front of its bin, then shrink the bin past it
+                let du = degree[u];
+                let first = sorted[bin_start[du]];
+                if first != u {
+                    sorted.swap(position[u], bin_start[du]);
+                    position[first] = position[u];
+                    position[u] = bin_start[du];
+                }
+                bin_start[du] += 1;
+                degree[u] -= 1;
+            }
+        }
+        if i % 1000 == 0 {
+            poison.check()?;
+        }
+    }
+    Ok((degree, position))
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use serde_json::json;
+
+    use crate::DbInstance;
+
+    #[test]
+    fn test_k_core() {
+        let db = DbInstance::default();
+        // a 4-clique {a, b, c, d}, a triangle {d, e, f} hanging off it, and a tail f - g
+        let edges = r#"
+            edges[f, t] <- [['a', 'b'], ['a', 'c'], ['a', 'd'], ['b', 'c'], ['b', 'd'], ['c', 'd'],
+                            ['d', 'e'], ['e', 'f'], ['f', 'd'], ['f', 'g'], ['b', 'a'], ['g', 'g']]
+        "#;
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[n, core] := r[n, core, _] r[] <~ KCore(edges[])"
+            ))
+            .unwrap();
+        assert_eq!(
+            res.into_json()["rows"],
+            json!([
+                ["a", 3],
+                ["b", 3],
+                ["c", 3],
+                ["d", 3],
+                ["e", 2],
+                ["f", 2],
+                ["g", 1]
+            ])
+        );
+
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[n, core, order] <~ KCore(edges[], k: 2)"
+            ))
+            .unwrap();
+        let orders = res
+            .rows
+            .iter()
+            .sorted_by_key(|row| row[2].get_int().unwrap())
+            .map(|row| row[1].get_int().unwrap())
+            .collect_vec();
+        assert_eq!(orders, vec![2, 2, 3, 3, 3, 3]);
+
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[n, core] := r[n, core, _] r[] <~ KCore(edges[], undirected: false)"
+            ))
+            .unwrap();
+        assert_eq!(
+            res.into_json()["rows"],
+            json!([
+                ["a", 1],
+                ["b", 1],
+                ["c", 1],
+                ["d", 1],
+                ["e", 1],
+                ["f", 1],
+                ["g", 0]
+            ])
+        );
+    }
+}
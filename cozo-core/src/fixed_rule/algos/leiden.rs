@@ -0,0 +1,475 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, VecDeque};
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighborsWithValues, Graph};
+use itertools::Itertools;
+use log::debug;
+use miette::{bail, Result};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{BadExprValueError, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+pub(crate) struct CommunityDetectionLeiden;
+
+impl FixedRule for CommunityDetectionLeiden {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let max_iter = payload.pos_integer_option("max_iter", Some(10))?;
+        let resolution = payload.float_option("resolution", Some(1.))?;
+        let randomness = payload.float_option("randomness", Some(0.01))?;
+        if randomness.is_nan() || randomness <= 0. {
+            bail!(BadExprValueError(
+                DataValue::from(randomness),
+                payload.option_span("randomness")?,
+                "Randomness must be positive".to_string()
+            ))
+        }
+        let seed = payload
+            .has_option("seed")
+            .then(|| payload.integer_option("seed", None))
+            .transpose()?;
+        let keep_depth = payload.non_neg_integer_option("keep_depth", None).ok();
+
+        let (graph, indices, _inv_indices) = edges.as_directed_weighted_graph(false, false)?;
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed as u64),
+            None => StdRng::from_entropy(),
+        };
+        let params = LeidenParams {
+            resolution,
+            randomness,
+        };
+        let result = leiden(&graph, &params, max_iter, &mut rng, poison)?;
+        for (idx, node) in indices.into_iter().enumerate() {
+            let mut labels = result
+                .iter()
+                .rev()
+                .map(|level| DataValue::from(level[idx] as i64))
+                .collect_vec();
+            if let Some(l) = keep_depth {
+                labels.truncate(l);
+            }
+            out.put(vec![DataValue::List(labels), node]);
+        }
+
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+struct LeidenParams {
+    resolution: f64,
+    randomness: f64,
+}
+
+/// Symmetric weighted adjacency lists. A self-loop of an aggregated node carries twice the
+/// weight of the edges inside it, so that the degree of a node is the sum of its row.
+struct Network {
+    adjacency: Vec<Vec<(u32, f64)>>,
+    degrees: Vec<f64>,
+    total_weight: f64,
+}
+
+impl Network {
+    fn new(adjacency: Vec<Vec<(u32, f64)>>) -> Self {
+        let degrees = adjacency
+            .iter()
+            .map(|row| row.iter().map(|(_, w)| w).sum())
+            .collect_vec();
+        let total_weight = degrees.iter().sum();
+        Self {
+            adjacency,
+            degrees,
+            total_weight,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.adjacency.len()
+    }
+
+    /// Weights from `node` to the communities of its neighbours, self-loops excluded
+    fn weights_to(&self, node: usize, community: &[u32]) -> BTreeMap<u32, f64> {
+        let mut ret = BTreeMap::new();
+        for (to, w) in &self.adjacency[node] {
+            if *to as usize != node {
+                *ret.entry(community[*to as usize]).or_default() += *w;
+            }
+        }
+        ret
+    }
+
+    /// Merge the nodes in each community of `community` into one node
+    fn aggregate(&self, community: &[u32], n_communities: usize) -> Network {
+        let mut rows: Vec<BTreeMap<u32, f64>> = vec![BTreeMap::new(); n_communities];
+        for (from, row) in self.adjacency.iter().enumerate() {
+            let target = &mut rows[community[from] as usize];
+            for (to, w) in row {
+                *target.entry(community[*to as usize]).or_default() += *w;
+            }
+        }
+        Network::new(
+            rows.into_iter()
+                .map(|row| row.into_iter().collect_vec())
+                .collect_vec(),
+        )
+    }
+}
+
+/// Returns the community of every original node after each level, finest level first.
+/// Edge directions are ignored, as the modularity being optimised is the undirected one.
+fn leiden(
+    graph: &DirectedCsrGraph<u32, (), f32>,
+    params: &LeidenParams,
+    max_iter: usize,
+    rng: &mut StdRng,
+    poison: Poison,
+) -> Result<Vec<Vec<u32>>> {
+    let n = graph.node_count() as usize;
+    let mut rows: Vec<BTreeMap<u32, f64>> = vec![BTreeMap::new(); n];
+    for from in 0..n as u32 {
+        for target in graph.out_neighbors_with_values(from) {
+            *rows[from as usize].entry(target.target).or_default() += target.value as f64;
+            *rows[target.target as usize].entry(from).or_default() += target.value as f64;
+        }
+    }
+    let mut network = Network::new(
+        rows.into_iter()
+            .map(|row| row.into_iter().collect_vec())
+            .collect_vec(),
+    );
+
+    // the aggregated node each original node currently belongs to
+    let mut membership = (0..n as u32).collect_vec();
+    let mut partition = (0..n as u32).collect_vec();
+    let mut collected = vec![];
+    for _ in 0..max_iter {
+        if network.total_weight <= 0. {
+            break;
+        }
+        move_nodes_fast(&network, params, &mut partition, rng, &poison)?;
+        let n_communities = renumber(&mut partition);
+        debug!(
+            "before size: {}, after size: {}",
+            network.len(),
+            n_communities
+        );
+        if n_communities == network.len() {
+            break;
+        }
+        let level = membership
+            .iter()
+            .map(|node| partition[*node as usize])
+            .collect_vec();
+        // when refinement leaves every node a singleton, aggregation makes no progress and
+        // the moving phase may settle on the same communities again
+        if collected.last() == Some(&level) {
+            break;
+        }
+        collected.push(level);
+
+        let mut refined = refine_partition(&network, params, &partition, rng, &poison)?;
+        let n_refined = renumber(&mut refined);
+        let mut next_partition = vec![0; n_refined];
+        for (node, r) in refined.iter().enumerate() {
+            next_partition[*r as usize] = partition[node];
+        }
+        network = network.aggregate(&refined, n_refined);
+        for node in membership.iter_mut() {
+            *node = refined[*node as usize];
+        }
+        partition = next_partition;
+    }
+    Ok(collected)
+}
+
+/// Renumber the labels densely from zero in order of first occurrence, returning their count
+fn renumber(labels: &mut [u32]) -> usize {
+    let mut mapping: BTreeMap<u32, u32> = BTreeMap::new();
+    for label in labels.iter_mut() {
+        let next = mapping.len() as u32;
+        *label = *mapping.entry(*label).or_insert(next);
+    }
+    mapping.len()
+}
+
+/// Local moving phase: visit nodes from a queue and move each to the community with the best
+/// quality gain, re-queueing the neighbours that end up outside the node's new community.
+fn move_nodes_fast(
+    network: &Network,
+    params: &LeidenParams,
+    partition: &mut [u32],
+    rng: &mut StdRng,
+    poison: &Poison,
+) -> Result<()> {
+    let n = network.len();
+    let mut community_weights = vec![0.; n];
+    let mut community_sizes = vec![0usize; n];
+    for (node, c) in partition.iter().enumerate() {
+        community_weights[*c as usize] += network.degrees[node];
+        community_sizes[*c as usize] += 1;
+    }
+    let mut empty = (0..n as u32)
+        .filter(|c| community_sizes[*c as usize] == 0)
+        .collect_vec();
+
+    let mut order = (0..n).collect_vec();
+    order.shuffle(rng);
+    let mut queue = VecDeque::from(order);
+    let mut queued = vec![true; n];
+    while let Some(node) = queue.pop_front() {
+        queued[node] = false;
+        let current = partition[node];
+        let degree = network.degrees[node];
+        community_weights[current as usize] -= degree;
+        community_sizes[current as usize] -= 1;
+        if community_sizes[current as usize] == 0 {
+            empty.push(current);
+        }
+
+        let gain = |c: u32, w: f64| {
+            w - params.resolution * degree * community_weights[c as usize] / network.total_weight
+        };
+        let weights_to = network.weights_to(node, partition);
+        let mut best = current;
+        let mut best_gain = gain(current, weights_to.get(&current).copied().unwrap_or(0.));
+        for (c, w) in &weights_to {
+            let g = gain(*c, *w);
+            if g > best_gain {
+                best = *c;
+                best_gain = g;
+            }
+        }
+        if best_gain < 0. {
+            // an empty community has zero gain
+            best = *empty.last().unwrap();
+        }
+
+        if community_sizes[best as usize] == 0 {
+            empty.retain(|c| *c != best);
+        }
+        community_weights[best as usize] += degree;
+        community_sizes[best as usize] += 1;
+        partition[node] = best;
+        if best != current {
+            for (to, _) in &network.adjacency[node] {
+                let to = *to as usize;
+                if !queued[to] && partition[to] != best {
+                    queued[to] = true;
+                    queue.push_back(to);
+                }
+            }
+        }
+        poison.check()?;
+    }
+    Ok(())
+}
+
+/// Refinement phase: within each community, start from singletons and merge nodes into
+/// well-connected sub-communities, choosing randomly among the ones that do not decrease
+/// the quality. The resulting sub-communities are always connected.
+fn refine_partition(
+    network: &Network,
+    params: &LeidenParams,
+    partition: &[u32],
+    rng: &mut StdRng,
+    poison: &Poison,
+) -> Result<Vec<u32>> {
+    let n = network.len();
+    let mut members: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    let mut community_weights: BTreeMap<u32, f64> = BTreeMap::new();
+    for (node, c) in partition.iter().enumerate() {
+        members.entry(*c).or_default().push(node);
+        *community_weights.entry(*c).or_default() += network.degrees[node];
+    }
+
+    let mut refined = (0..n as u32).collect_vec();
+    let mut refined_weights = network.degrees.clone();
+    let mut refined_sizes = vec![1usize; n];
+    // weight of the edges from each sub-community to the rest of its community
+    let mut external = (0..n)
+        .map(|node| {
+            network.adjacency[node]
+                .iter()
+                .filter(|(to, _)| {
+                    *to as usize != node && partition[*to as usize] == partition[node]
+                })
+                .map(|(_, w)| w)
+                .sum::<f64>()
+        })
+        .collect_vec();
+
+    for (c, mut nodes) in members {
+        let total = community_weights[&c];
+        let well_connected = |external: f64, weight: f64| {
+            external >= params.resolution * weight * (total - weight) / network.total_weight
+        };
+        nodes.shuffle(rng);
+        for node in nodes {
+            if refined_sizes[refined[node] as usize] != 1
+                || !well_connected(external[node], network.degrees[node])
+            {
+                continue;
+            }
+            let degree = network.degrees[node];
+            let mut candidates = vec![(refined[node], 0., 0.)];
+            for (r, w) in network.weights_to(node, &refined) {
+                let member = r as usize;
+                if partition[member] != c
+                    || !well_connected(external[member], refined_weights[member])
+                {
+                    continue;
+                }
+                let gain =
+                    w - params.resolution * degree * refined_weights[member] / network.total_weight;
+                if gain >= 0. {
+                    candidates.push((r, gain, w));
+                }
+            }
+            let max_gain = candidates
+                .iter()
+                .map(|(_, g, _)| *g)
+                .fold(f64::NEG_INFINITY, f64::max);
+            let chosen = candidates
+                .choose_weighted(rng, |(_, g, _)| ((g - max_gain) / params.randomness).exp())
+                .copied()
+                .unwrap_or(candidates[0]);
+            let (target, _, w) = chosen;
+            if target != refined[node] {
+                let target_idx = target as usize;
+                external[target_idx] += external[node] - 2. * w;
+                refined_weights[target_idx] += degree;
+                refined_sizes[target_idx] += 1;
+                refined_sizes[node] -= 1;
+                refined[node] = target;
+            }
+            poison.check()?;
+        }
+    }
+    Ok(refined)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use itertools::Itertools;
+    use serde_json::json;
+
+    use crate::data::value::DataValue;
+    use crate::DbInstance;
+
+    #[test]
+    fn test_leiden() {
+        let db = DbInstance::default();
+        // two 5-cliques joined by a single light edge, plus a separate triangle
+        let mut edges = vec![
+            json!([4, 10, 0.5]),
+            json!([20, 21, 2.0]),
+            json!([21, 22, 2.0]),
+            json!([22, 20, 2.0]),
+        ];
+        for offset in [0, 10] {
+            for (a, b) in (0..5).tuple_combinations() {
+                edges.push(json!([a + offset, b + offset, 1.0]));
+            }
+        }
+        let script = format!(
+            "edges[a, b, w] <- {} ?[labels, n] <~ CommunityDetectionLeiden(edges[], seed: 42)",
+            json!(edges)
+        );
+        let res = db.run_default(&script).unwrap();
+        let communities: BTreeMap<i64, i64> = res
+            .rows
+            .iter()
+            .map(|row| {
+                let labels = row[0].get_slice().unwrap();
+                (row[1].get_int().unwrap(), labels[0].get_int().unwrap())
+            })
+            .collect();
+        assert_eq!(communities.len(), 13);
+        let groups = communities
+            .iter()
+            .map(|(n, c)| (*c, *n))
+            .into_group_map()
+            .into_values()
+            .map(|mut members| {
+                members.sort();
+                members
+            })
+            .sorted()
+            .collect_vec();
+        assert_eq!(
+            groups,
+            vec![
+                vec![0, 1, 2, 3, 4],
+                vec![10, 11, 12, 13, 14],
+                vec![20, 21, 22]
+            ]
+        );
+        assert_eq!(db.run_default(&script).unwrap().rows, res.rows);
+
+        let res = db
+            .run_default(
+                r#"
+                edges[a, b] <- [[1, 2], [2, 3], [3, 1], [3, 4]]
+                ?[labels, n] <~ CommunityDetectionLeiden(edges[], resolution: 0.01, keep_depth: 1)
+                "#,
+            )
+            .unwrap();
+        assert!(res
+            .rows
+            .iter()
+            .all(|row| row[0] == DataValue::List(vec![DataValue::from(0)])));
+
+        // a level that does not change the communities ends the run
+        let res = db
+            .run_default(
+                r#"
+                edges[a, b] <- [[1, 2], [2, 3], [3, 1], [3, 4]]
+                ?[labels, n] <~ CommunityDetectionLeiden(edges[], resolution: 0.5, seed: 2)
+                "#,
+            )
+            .unwrap();
+        assert!(res
+            .rows
+            .iter()
+            .all(|row| row[0] == DataValue::List(vec![DataValue::from(0)])));
+
+        for opts in ["seed: 'x'", "randomness: 0", "randomness: -1"] {
+            assert!(db
+                .run_default(&format!(
+                    "e[a, b] <- [[1, 2]] ?[l, n] <~ CommunityDetectionLeiden(e[], {opts})"
+                ))
+                .is_err());
+        }
+    }
+}
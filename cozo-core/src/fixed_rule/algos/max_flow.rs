@@ -0,0 +1,385 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, VecDeque};
+
+use miette::{bail, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{
+    BadEdgeWeightError, BadExprValueError, CannotDetermineArity, FixedRule, FixedRulePayload,
+    NodeNotFoundError,
+};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+pub(crate) struct MaxFlow;
+
+impl FixedRule for MaxFlow {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let output = payload.string_option("output", Some("flows"))?;
+
+        // capacities are kept as f64 throughout, so the residual network is built directly
+        // from the tuples instead of through the f32-weighted graph
+        let mut indices: Vec<DataValue> = vec![];
+        let mut inv_indices: BTreeMap<DataValue, u32> = BTreeMap::new();
+        let mut network = FlowNetwork::default();
+        for tuple in edges.ensure_min_len(2)?.iter()? {
+            let mut tuple = tuple?.into_iter();
+            let mut index_of = |node: DataValue| {
+                *inv_indices.entry(node).or_insert_with_key(|k| {
+                    indices.push(k.clone());
+                    network.add_node()
+                })
+            };
+            let from = index_of(tuple.next().unwrap());
+            let to = index_of(tuple.next().unwrap());
+            let capacity = match tuple.next() {
+                None => 1.,
+                Some(d) => match d.get_float() {
+                    Some(f) if f.is_finite() && f >= 0. => f,
+                    _ => bail!(BadEdgeWeightError(d, edges.span())),
+                },
+            };
+            network.add_arc(from, to, capacity);
+            if undirected {
+                network.add_arc(to, from, capacity);
+            }
+            poison.check()?;
+        }
+
+        let terminal = |name: &str| -> Result<u32> {
+            let val = payload.expr_option(name, None)?.eval_to_const()?;
+            match inv_indices.get(&val) {
+                Some(idx) => Ok(*idx),
+                None => bail!(NodeNotFoundError {
+                    missing: val,
+                    span: payload.option_span(name)?,
+                }),
+            }
+        };
+        let source = terminal("source")?;
+        let sink = terminal("sink")?;
+        if source == sink {
+            bail!(BadExprValueError(
+                indices[sink as usize].clone(),
+                payload.option_span("sink")?,
+                "The sink must be different from the source".to_string()
+            ))
+        }
+
+        let value = network.dinic(source, sink, poison)?;
+
+        match output.as_str() {
+            "value" => out.put(vec![DataValue::from(value)]),
+            "flows" => {
+                for ((from, to), flow) in network.net_flows() {
+                    out.put(vec![
+                        indices[from as usize].clone(),
+                        indices[to as usize].clone(),
+                        DataValue::from(flow),
+                    ]);
+                }
+            }
+            "cut" => {
+                let source_side = network.residual_reachable(source);
+                for (idx, node) in indices.into_iter().enumerate() {
+                    let side = if source_side[idx] { "source" } else { "sink" };
+                    out.put(vec![node, DataValue::from(side)]);
+                }
+            }
+            s => bail!(BadExprValueError(
+                DataValue::from(s),
+                payload.option_span("output")?,
+                "Output must be one of 'flows', 'value' or 'cut'".to_string()
+            )),
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(match options.get("output") {
+            None => 3,
+            Some(Expr::Const {
+                val: DataValue::Str(s),
+                ..
+            }) => match s as &str {
+                "flows" => 3,
+                "value" => 1,
+                "cut" => 2,
+                _ => bail!(CannotDetermineArity(
+                    "MaxFlow".to_string(),
+                    format!("invalid option 'output' given: '{s}'"),
+                    span
+                )),
+            },
+            _ => bail!(CannotDetermineArity(
+                "MaxFlow".to_string(),
+                "invalid option 'output' given, expect one of 'flows', 'value' or 'cut'"
+                    .to_string(),
+                span
+            )),
+        })
+    }
+}
+
+struct FlowArc {
+    to: u32,
+    capacity: f64,
+    flow: f64,
+    /// `false` for the reverse arcs added for the residual graph
+    forward: bool,
+}
+
+/// Residual network stored as an arc list; the arc at `i ^ 1` is the reverse of the arc at `i`.
+#[derive(Default)]
+struct FlowNetwork {
+    arcs: Vec<FlowArc>,
+    adjacency: Vec<Vec<usize>>,
+    /// Residual capacities not above this are treated as saturated, to guard against rounding
+    tolerance: f64,
+}
+
+impl FlowNetwork {
+    fn add_node(&mut self) -> u32 {
+        self.adjacency.push(vec![]);
+        (self.adjacency.len() - 1) as u32
+    }
+
+    fn add_arc(&mut self, from: u32, to: u32, capacity: f64) {
+        self.adjacency[from as usize].push(self.arcs.len());
+        self.arcs.push(FlowArc {
+            to,
+            capacity,
+            flow: 0.,
+            forward: true,
+        });
+        self.adjacency[to as usize].push(self.arcs.len());
+        self.arcs.push(FlowArc {
+            to: from,
+            capacity: 0.,
+            flow: 0.,
+            forward: false,
+        });
+        self.tolerance = self.tolerance.max(capacity * 1e-12);
+    }
+
+    fn residual(&self, arc: usize) -> f64 {
+        self.arcs[arc].capacity - self.arcs[arc].flow
+    }
+
+    fn push(&mut self, arc: usize, amount: f64) {
+        self.arcs[arc].flow += amount;
+        self.arcs[arc ^ 1].flow -= amount;
+    }
+
+    fn levels(&self, source: u32) -> Vec<Option<usize>> {
+        let mut levels = vec![None; self.adjacency.len()];
+        levels[source as usize] = Some(0);
+        let mut queue = VecDeque::from([source]);
+        while let Some(node) = queue.pop_front() {
+            let level = levels[node as usize].unwrap();
+            for &arc in &self.adjacency[node as usize] {
+                let to = self.arcs[arc].to as usize;
+                if levels[to].is_none() && self.residual(arc) > self.tolerance {
+                    levels[to] = Some(level + 1);
+                    queue.push_back(to as u32);
+                }
+            }
+        }
+        levels
+    }
+
+    fn residual_reachable(&self, source: u32) -> Vec<bool> {
+        self.levels(source)
+            .into_iter()
+            .map(|l| l.is_some())
+            .collect()
+    }
+
+    fn dinic(&mut self, source: u32, sink: u32, poison: Poison) -> Result<f64> {
+        let mut total = 0.;
+        loop {
+            let levels = self.levels(source);
+            if levels[sink as usize].is_none() {
+                break;
+            }
+            total += self.blocking_flow(source, sink, &levels);
+            poison.check()?;
+        }
+        Ok(total)
+    }
+
+    /// Saturate the level graph by repeatedly walking from the source towards the sink.
+    /// Arcs that cannot lead to the sink are skipped for the rest of the phase via `next_arc`.
+    fn blocking_flow(&mut self, source: u32, sink: u32, levels: &[Option<usize>]) -> f64 {
+        let mut total = 0.;
+        let mut next_arc = vec![0; self.adjacency.len()];
+        let mut path: Vec<usize> = vec![];
+        let mut node = source as usize;
+        loop {
+            if node == sink as usize {
+                let bottleneck = path
+                    .iter()
+                    .map(|arc| self.residual(*arc))
+                    .fold(f64::INFINITY, f64::min);
+                for arc in &path {
+                    self.push(*arc, bottleneck);
+                }
+                total += bottleneck;
+                path.clear();
+                node = source as usize;
+                continue;
+            }
+            let mut advanced = false;
+            while next_arc[node] < self.adjacency[node].len() {
+                let arc = self.adjacency[node][next_arc[node]];
+                let to = self.arcs[arc].to as usize;
+                if self.residual(arc) > self.tolerance && levels[to] == levels[node].map(|l| l + 1)
+                {
+                    path.push(arc);
+                    node = to;
+                    advanced = true;
+                    break;
+                }
+                next_arc[node] += 1;
+            }
+            if !advanced {
+                match path.pop() {
+                    None => return total,
+                    Some(arc) => {
+                        node = self.arcs[arc ^ 1].to as usize;
+                        next_arc[node] += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Flows on the original edges, summed over parallel edges, with flows in opposite
+    /// directions between the same pair of nodes cancelled out.
+    fn net_flows(&self) -> BTreeMap<(u32, u32), f64> {
+        let mut flows: BTreeMap<(u32, u32), f64> = BTreeMap::new();
+        for (from, arcs) in self.adjacency.iter().enumerate() {
+            for &arc in arcs {
+                let arc = &self.arcs[arc];
+                if arc.forward && arc.flow > 0. {
+                    *flows.entry((from as u32, arc.to)).or_default() += arc.flow;
+                }
+            }
+        }
+        let mut ret = BTreeMap::new();
+        for (&(from, to), &flow) in &flows {
+            let net = flow - flows.get(&(to, from)).copied().unwrap_or(0.);
+            if net > self.tolerance {
+                ret.insert((from, to), net);
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use itertools::Itertools;
+
+    use crate::data::value::DataValue;
+    use crate::DbInstance;
+
+    #[test]
+    fn test_max_flow() {
+        let db = DbInstance::default();
+        let edges = r#"
+            edges[f, t, c] <- [['s', 'v1', 16], ['s', 'v2', 13], ['v1', 'v3', 12], ['v2', 'v1', 4],
+                               ['v2', 'v4', 14], ['v3', 'v2', 9], ['v3', 't', 20], ['v4', 'v3', 7],
+                               ['v4', 't', 4]]
+        "#;
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[v] <~ MaxFlow(edges[], source: 's', sink: 't', output: 'value')"
+            ))
+            .unwrap();
+        assert_eq!(res.rows, vec![vec![DataValue::from(23.)]]);
+
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[f, t, flow] <~ MaxFlow(edges[], source: 's', sink: 't')"
+            ))
+            .unwrap();
+        let mut balance: BTreeMap<&str, f64> = BTreeMap::new();
+        for row in &res.rows {
+            let flow = row[2].get_float().unwrap();
+            assert!(flow > 0.);
+            *balance.entry(row[0].get_str().unwrap()).or_default() -= flow;
+            *balance.entry(row[1].get_str().unwrap()).or_default() += flow;
+        }
+        assert_eq!(balance["s"], -23.);
+        assert_eq!(balance["t"], 23.);
+        assert!(["v1", "v2", "v3", "v4"]
+            .iter()
+            .all(|n| balance.get(n).copied().unwrap_or(0.) == 0.));
+
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[n, side] <~ MaxFlow(edges[], source: 's', sink: 't', output: 'cut')"
+            ))
+            .unwrap();
+        let source_side = res
+            .rows
+            .iter()
+            .filter(|row| row[1].get_str() == Some("source"))
+            .map(|row| row[0].get_str().unwrap())
+            .collect_vec();
+        assert_eq!(source_side, vec!["s", "v1", "v2", "v4"]);
+
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[v] <~ MaxFlow(edges[], source: 's', sink: 't', undirected: true, output: 'value')"
+            ))
+            .unwrap();
+        assert_eq!(res.rows, vec![vec![DataValue::from(24.)]]);
+
+        // not representable as f32
+        let res = db
+            .run_default(
+                "edges[f, t, c] <- [['s', 'a', 16777217], ['a', 't', 16777219]]
+                 ?[v] <~ MaxFlow(edges[], source: 's', sink: 't', output: 'value')",
+            )
+            .unwrap();
+        assert_eq!(res.rows, vec![vec![DataValue::from(16777217.)]]);
+
+        assert!(db
+            .run_default(&format!(
+                "{edges} ?[v] <~ MaxFlow(edges[], source: 's', sink: 'x', output: 'value')"
+            ))
+            .is_err());
+        assert!(db
+            .run_default(
+                "edges[f, t, c] <- [['s', 't', -1]] ?[f, t, flow] <~ MaxFlow(edges[], source: 's', sink: 't')"
+            )
+            .is_err());
+    }
+}
@@ -9,17 +9,26 @@
 pub(crate) mod all_pairs_shortest_path;
 pub(crate) mod astar;
 pub(crate) mod bfs;
+pub(crate) mod biconnected;
+pub(crate) mod bipartite_matching;
+pub(crate) mod cliques;
+pub(crate) mod cycles;
 pub(crate) mod degree_centrality;
 pub(crate) mod dfs;
+pub(crate) mod k_core;
 pub(crate) mod kruskal;
 pub(crate) mod label_propagation;
+pub(crate) mod leiden;
 pub(crate) mod louvain;
+pub(crate) mod max_flow;
+pub(crate) mod node_similarity;
 pub(crate) mod pagerank;
 pub(crate) mod prim;
 pub(crate) mod random_walk;
 pub(crate) mod shortest_path_bfs;
 pub(crate) mod shortest_path_dijkstra;
 pub(crate) mod strongly_connected_components;
+pub(crate) mod subgraph_match;
 pub(crate) mod top_sort;
 pub(crate) mod triangles;
 pub(crate) mod yen;
@@ -27,17 +36,26 @@ pub(crate) mod yen;
 pub(crate) use all_pairs_shortest_path::{BetweennessCentrality, ClosenessCentrality};
 pub(crate) use astar::ShortestPathAStar;
 pub(crate) use bfs::Bfs;
+pub(crate) use biconnected::{ArticulationPoints, BiconnectedComponents, Bridges};
+pub(crate) use bipartite_matching::{BipartiteMatching, MinCostAssignment};
+pub(crate) use cliques::MaximalCliques;
+pub(crate) use cycles::{FindCycle, SimpleCycles};
 pub(crate) use degree_centrality::DegreeCentrality;
 pub(crate) use dfs::Dfs;
+pub(crate) use k_core::KCore;
 pub(crate) use kruskal::MinimumSpanningForestKruskal;
 pub(crate) use label_propagation::LabelPropagation;
+pub(crate) use leiden::CommunityDetectionLeiden;
 pub(crate) use louvain::CommunityDetectionLouvain;
-pub(crate) use pagerank::PageRank;
+pub(crate) use max_flow::MaxFlow;
+pub(crate) use node_similarity::NodeSimilarity;
+pub(crate) use pagerank::{PageRank, PersonalizedPageRank};
 pub(crate) use prim::MinimumSpanningTreePrim;
 pub(crate) use random_walk::RandomWalk;
 pub(crate) use shortest_path_bfs::ShortestPathBFS;
 pub(crate) use shortest_path_dijkstra::ShortestPathDijkstra;
 pub(crate) use strongly_connected_components::StronglyConnectedComponent;
+pub(crate) use subgraph_match::SubgraphMatch;
 pub(crate) use top_sort::TopSort;
 pub(crate) use triangles::ClusteringCoefficients;
 pub(crate) use yen::KShortestPathYen;
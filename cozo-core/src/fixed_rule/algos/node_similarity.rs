@@ -0,0 +1,298 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighbors, Graph};
+use itertools::Itertools;
+use miette::{bail, Result};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{BadExprValueError, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+pub(crate) struct NodeSimilarity;
+
+#[derive(Copy, Clone)]
+enum SimilarityMetric {
+    Jaccard,
+    Overlap,
+    Cosine,
+    CommonNeighbors,
+    AdamicAdar,
+    ResourceAllocation,
+}
+
+impl FixedRule for NodeSimilarity {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let candidates = payload.get_input(1);
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        // zero means no limit
+        let top_k = payload.non_neg_integer_option("top_k", Some(0))?;
+        let cutoff = payload.float_option("cutoff", Some(0.))?;
+        let metric = match payload.string_option("metric", Some("jaccard"))?.as_str() {
+            "jaccard" => SimilarityMetric::Jaccard,
+            "overlap" => SimilarityMetric::Overlap,
+            "cosine" => SimilarityMetric::Cosine,
+            "common_neighbors" => SimilarityMetric::CommonNeighbors,
+            "adamic_adar" => SimilarityMetric::AdamicAdar,
+            "resource_allocation" => SimilarityMetric::ResourceAllocation,
+            s => bail!(BadExprValueError(
+                DataValue::from(s),
+                payload.option_span("metric")?,
+                "Metric must be one of 'jaccard', 'overlap', 'cosine', 'common_neighbors', \
+                 'adamic_adar' or 'resource_allocation'"
+                    .to_string()
+            )),
+        };
+
+        let (graph, indices, inv_indices) = edges.as_directed_graph(undirected)?;
+        let neighborhoods = Neighborhoods::new(&graph);
+
+        let mut scored: BTreeMap<u32, Vec<(u32, f64)>> = BTreeMap::new();
+        match candidates {
+            Ok(candidates) => {
+                for tuple in candidates.ensure_min_len(2)?.iter()? {
+                    let tuple = tuple?;
+                    // nodes outside the graph have no neighbours to share
+                    if let (Some(a), Some(b)) =
+                        (inv_indices.get(&tuple[0]), inv_indices.get(&tuple[1]))
+                    {
+                        let score = neighborhoods.pair_score(*a, *b, metric);
+                        scored.entry(*a).or_default().push((*b, score));
+                    }
+                    poison.check()?;
+                }
+            }
+            Err(_) => {
+                let it = 0..graph.node_count();
+                #[cfg(feature = "rayon")]
+                let it = it.into_par_iter();
+
+                scored = it
+                    .map(|a| -> Result<(u32, Vec<(u32, f64)>)> {
+                        let res = neighborhoods.sharing_scores(a, metric);
+                        poison.check()?;
+                        Ok((a, res))
+                    })
+                    .collect::<Result<_>>()?;
+            }
+        }
+
+        for (a, mut res) in scored {
+            res.retain(|(_, score)| *score >= cutoff);
+            res.sort_by(|(_, l), (_, r)| r.total_cmp(l));
+            if top_k > 0 {
+                res.truncate(top_k);
+            }
+            for (b, score) in res {
+                out.put(vec![
+                    indices[a as usize].clone(),
+                    indices[b as usize].clone(),
+                    DataValue::from(score),
+                ]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+}
+
+/// Sorted, deduplicated out-neighbours of every node with self-loops removed,
+/// together with the reverse lists.
+struct Neighborhoods {
+    out: Vec<Vec<u32>>,
+    into: Vec<Vec<u32>>,
+}
+
+/// Statistics of the neighbours shared by two nodes
+#[derive(Default)]
+struct Shared {
+    count: usize,
+    adamic_adar: f64,
+    resource_allocation: f64,
+}
+
+impl Neighborhoods {
+    fn new(graph: &DirectedCsrGraph<u32>) -> Self {
+        let n = graph.node_count() as usize;
+        let mut out = Vec::with_capacity(n);
+        let mut into = vec![vec![]; n];
+        for node in 0..n as u32 {
+            let neighbors = graph
+                .out_neighbors(node)
+                .dedup()
+                .filter(|to| **to != node)
+                .copied()
+                .collect_vec();
+            for to in &neighbors {
+                into[*to as usize].push(node);
+            }
+            out.push(neighbors);
+        }
+        Self { out, into }
+    }
+
+    fn add_shared(&self, shared: &mut Shared, via: u32) {
+        let degree = self.into[via as usize].len() as f64;
+        shared.count += 1;
+        // the degree is only one when a node is compared with itself
+        if degree > 1. {
+            shared.adamic_adar += 1. / degree.ln();
+        }
+        shared.resource_allocation += 1. / degree;
+    }
+
+    fn score(&self, a: u32, b: u32, shared: &Shared, metric: SimilarityMetric) -> f64 {
+        if shared.count == 0 {
+            return 0.;
+        }
+        let n_a = self.out[a as usize].len() as f64;
+        let n_b = self.out[b as usize].len() as f64;
+        let count = shared.count as f64;
+        match metric {
+            SimilarityMetric::Jaccard => count / (n_a + n_b - count),
+            SimilarityMetric::Overlap => count / n_a.min(n_b),
+            SimilarityMetric::Cosine => count / (n_a * n_b).sqrt(),
+            SimilarityMetric::CommonNeighbors => count,
+            SimilarityMetric::AdamicAdar => shared.adamic_adar,
+            SimilarityMetric::ResourceAllocation => shared.resource_allocation,
+        }
+    }
+
+    fn pair_score(&self, a: u32, b: u32, metric: SimilarityMetric) -> f64 {
+        let mut shared = Shared::default();
+        let (mut l, mut r) = (
+            self.out[a as usize].iter().peekable(),
+            self.out[b as usize].iter().peekable(),
+        );
+        while let (Some(x), Some(y)) = (l.peek(), r.peek()) {
+            match x.cmp(y) {
+                std::cmp::Ordering::Less => {
+                    l.next();
+                }
+                std::cmp::Ordering::Greater => {
+                    r.next();
+                }
+                std::cmp::Ordering::Equal => {
+                    self.add_shared(&mut shared, **x);
+                    l.next();
+                    r.next();
+                }
+            }
+        }
+        self.score(a, b, &shared, metric)
+    }
+
+    /// Scores between `a` and every other node sharing at least one neighbour with it
+    fn sharing_scores(&self, a: u32, metric: SimilarityMetric) -> Vec<(u32, f64)> {
+        let mut shared: BTreeMap<u32, Shared> = BTreeMap::new();
+        for via in &self.out[a as usize] {
+            for b in &self.into[*via as usize] {
+                if *b != a {
+                    self.add_shared(shared.entry(*b).or_default(), *via);
+                }
+            }
+        }
+        shared
+            .into_iter()
+            .map(|(b, s)| (b, self.score(a, b, &s, metric)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use crate::data::value::DataValue;
+    use crate::DbInstance;
+
+    #[test]
+    fn test_node_similarity() {
+        let db = DbInstance::default();
+        let edges = r#"
+            edges[f, t] <- [['a', 'b'], ['a', 'c'], ['b', 'c'], ['b', 'd'], ['c', 'd'], ['d', 'e']]
+        "#;
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[a, b, s] <~ NodeSimilarity(edges[], undirected: true, top_k: 1)"
+            ))
+            .unwrap();
+        let best: BTreeMap<_, _> = res
+            .rows
+            .iter()
+            .map(|row| {
+                (
+                    row[0].get_str().unwrap(),
+                    (row[1].get_str().unwrap(), row[2].get_float().unwrap()),
+                )
+            })
+            .collect();
+        assert_eq!(best.len(), 5);
+        assert_eq!(best["a"].0, "d");
+        assert!((best["a"].1 - 2. / 3.).abs() < 1e-9);
+
+        for (metric, expected) in [
+            ("common_neighbors", 2.),
+            ("overlap", 1.),
+            ("cosine", 2. / 6f64.sqrt()),
+            ("adamic_adar", 2. / 3f64.ln()),
+            ("resource_allocation", 2. / 3.),
+        ] {
+            let res = db
+                .run_default(&format!(
+                    "{edges} pairs[a, b] <- [['a', 'd'], ['a', 'e'], ['a', 'x']]
+                     ?[a, b, s] <~ NodeSimilarity(edges[], pairs[], undirected: true, metric: '{metric}')"
+                ))
+                .unwrap();
+            assert_eq!(res.rows.len(), 2);
+            assert_eq!(res.rows[0][1], DataValue::from("d"));
+            assert!((res.rows[0][2].get_float().unwrap() - expected).abs() < 1e-9);
+            assert_eq!(res.rows[1][2], DataValue::from(0.));
+        }
+
+        let res = db
+            .run_default(&format!(
+                "{edges} ?[a, b, s] <~ NodeSimilarity(edges[], undirected: true, cutoff: 0.6)"
+            ))
+            .unwrap();
+        assert_eq!(
+            res.into_json()["rows"],
+            json!([["a", "d", 2. / 3.], ["d", "a", 2. / 3.]])
+        );
+        assert!(db
+            .run_default(&format!(
+                "{edges} ?[a, b, s] <~ NodeSimilarity(edges[], metric: 'euclid')"
+            ))
+            .is_err());
+    }
+}
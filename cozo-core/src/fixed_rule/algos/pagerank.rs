@@ -10,14 +10,18 @@ use std::collections::BTreeMap;
 
 #[cfg(not(feature = "rayon"))]
 use approx::AbsDiffEq;
-use graph::prelude::{page_rank, PageRankConfig};
-use miette::Result;
+use graph::prelude::{
+    page_rank, DirectedCsrGraph, DirectedDegrees, DirectedNeighbors, Graph, PageRankConfig,
+};
+use miette::{bail, Result};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::Expr;
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
-use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::fixed_rule::{BadExprValueError, CannotDetermineArity, FixedRule, FixedRulePayload};
 use crate::parse::SourceSpan;
 use crate::runtime::db::Poison;
 use crate::runtime::temp_store::RegularTempStore;
@@ -65,6 +69,184 @@ impl FixedRule for PageRank {
     }
 }
 
+pub(crate) struct PersonalizedPageRank;
+
+impl FixedRule for PersonalizedPageRank {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let seeds = payload.get_input(1)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let theta = payload.unit_interval_option("theta", Some(0.85))?;
+        let epsilon = payload.unit_interval_option("epsilon", Some(0.0001))?;
+        let iterations = payload.pos_integer_option("iterations", Some(10))?;
+        let batched = payload.bool_option("batched", Some(false))?;
+
+        let (graph, indices, inv_indices) = edges.as_directed_graph(undirected)?;
+
+        let mut seed_weights: BTreeMap<u32, f64> = BTreeMap::new();
+        for tuple in seeds.iter()? {
+            let tuple = tuple?;
+            let weight = match tuple.get(1) {
+                None => 1.,
+                Some(v) => match v.get_float() {
+                    Some(f) if f.is_finite() && f >= 0. => f,
+                    _ => bail!(BadExprValueError(
+                        v.clone(),
+                        seeds.span(),
+                        "Seed weights must be non-negative numbers".to_string()
+                    )),
+                },
+            };
+            // seeds not occurring in the graph cannot propagate anything
+            if let Some(idx) = inv_indices.get(&tuple[0]) {
+                *seed_weights.entry(*idx).or_default() += weight;
+            }
+            poison.check()?;
+        }
+
+        if seed_weights.is_empty() {
+            return Ok(());
+        }
+
+        if batched {
+            let seed_it = seed_weights.keys().copied().collect::<Vec<_>>();
+            #[cfg(feature = "rayon")]
+            let seed_it = seed_it.into_par_iter();
+            #[cfg(not(feature = "rayon"))]
+            let seed_it = seed_it.into_iter();
+
+            let res_all: Vec<_> = seed_it
+                .map(|seed| -> Result<(u32, Vec<f64>)> {
+                    Ok((
+                        seed,
+                        personalized_page_rank(
+                            &graph,
+                            &[(seed, 1.)],
+                            theta,
+                            epsilon,
+                            iterations,
+                            poison.clone(),
+                        )?,
+                    ))
+                })
+                .collect::<Result<_>>()?;
+
+            for (seed, ranks) in res_all {
+                for (idx, score) in ranks.into_iter().enumerate() {
+                    if score > 0. {
+                        out.put(vec![
+                            indices[seed as usize].clone(),
+                            indices[idx].clone(),
+                            DataValue::from(score),
+                        ]);
+                    }
+                }
+            }
+        } else {
+            let total: f64 = seed_weights.values().sum();
+            if total <= 0. {
+                bail!(BadExprValueError(
+                    DataValue::from(total),
+                    seeds.span(),
+                    "The total weight of the seeds must be positive".to_string()
+                ))
+            }
+            let teleport = seed_weights
+                .into_iter()
+                .map(|(idx, w)| (idx, w / total))
+                .collect::<Vec<_>>();
+            let ranks =
+                personalized_page_rank(&graph, &teleport, theta, epsilon, iterations, poison)?;
+            for (idx, score) in ranks.into_iter().enumerate() {
+                if score > 0. {
+                    out.put(vec![indices[idx].clone(), DataValue::from(score)]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(match options.get("batched") {
+            None => 2,
+            Some(Expr::Const {
+                val: DataValue::Bool(false),
+                ..
+            }) => 2,
+            Some(Expr::Const {
+                val: DataValue::Bool(true),
+                ..
+            }) => 3,
+            _ => bail!(CannotDetermineArity(
+                "PersonalizedPageRank".to_string(),
+                "invalid option 'batched' given, expect a boolean".to_string(),
+                span
+            )),
+        })
+    }
+}
+
+/// Power iteration where the random surfer teleports back to the seeds instead of
+/// to a uniformly chosen node. `teleport` must sum to one. Nodes without outgoing edges
+/// hand their score back to the seeds as well, so the scores always sum to one.
+fn personalized_page_rank(
+    graph: &DirectedCsrGraph<u32>,
+    teleport: &[(u32, f64)],
+    theta: f64,
+    epsilon: f64,
+    iterations: usize,
+    poison: Poison,
+) -> Result<Vec<f64>> {
+    let n = graph.node_count() as usize;
+    let mut ranks = vec![0.; n];
+    for (idx, w) in teleport {
+        ranks[*idx as usize] = *w;
+    }
+    for _ in 0..iterations {
+        let mut next = vec![0.; n];
+        let mut dangling = 0.;
+        for (node, rank) in ranks.iter().enumerate() {
+            if *rank == 0. {
+                continue;
+            }
+            let degree = graph.out_degree(node as u32);
+            if degree == 0 {
+                dangling += rank;
+            } else {
+                let share = theta * rank / degree as f64;
+                for to in graph.out_neighbors(node as u32) {
+                    next[*to as usize] += share;
+                }
+            }
+        }
+        let restart = 1. - theta + theta * dangling;
+        for (idx, w) in teleport {
+            next[*idx as usize] += restart * w;
+        }
+        let diff: f64 = next
+            .iter()
+            .zip(ranks.iter())
+            .map(|(a, b)| (a - b).abs())
+            .sum();
+        ranks = next;
+        if diff < epsilon {
+            break;
+        }
+        poison.check()?;
+    }
+    Ok(ranks)
+}
+
 #[cfg(not(feature = "rayon"))]
 fn pagerank(
     edges: &[Vec<usize>],
@@ -107,3 +289,82 @@ fn pagerank(
     }
     Ok(pi_vec)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use itertools::Itertools;
+
+    use crate::DbInstance;
+
+    #[test]
+    fn test_personalized_pagerank() {
+        let db = DbInstance::default();
+        let res = db
+            .run_default(
+                r#"
+                edges[f, t] <- [['a', 'b'], ['b', 'c'], ['c', 'a'], ['c', 'd'], ['e', 'f']]
+                seeds[n] <- [['a']]
+                ?[node, score] <~ PersonalizedPageRank(edges[], seeds[], iterations: 100)
+                "#,
+            )
+            .unwrap();
+        let scores: BTreeMap<_, _> = res
+            .rows
+            .iter()
+            .map(|row| (row[0].get_str().unwrap(), row[1].get_float().unwrap()))
+            .collect();
+        assert_eq!(
+            scores.keys().copied().collect_vec(),
+            vec!["a", "b", "c", "d"]
+        );
+        assert!((scores.values().sum::<f64>() - 1.).abs() < 1e-3);
+        assert!(scores["a"] > scores["b"]);
+        assert!(scores["b"] > scores["c"]);
+        assert!(scores["c"] > scores["d"]);
+
+        let res = db
+            .run_default(
+                r#"
+                edges[f, t] <- [['a', 'b'], ['b', 'c'], ['c', 'a'], ['c', 'd'], ['e', 'f']]
+                seeds[n, w] <- [['a', 0.0], ['e', 2.0], ['x', 1.0]]
+                ?[node, score] <~ PersonalizedPageRank(edges[], seeds[], undirected: true)
+                "#,
+            )
+            .unwrap();
+        let nodes = res
+            .rows
+            .iter()
+            .map(|row| row[0].get_str().unwrap())
+            .collect_vec();
+        assert_eq!(nodes, vec!["e", "f"]);
+
+        let res = db
+            .run_default(
+                r#"
+                edges[f, t] <- [['a', 'b'], ['b', 'c'], ['c', 'a'], ['c', 'd'], ['e', 'f']]
+                seeds[n] <- [['d'], ['e']]
+                ?[seed, node, score] <~ PersonalizedPageRank(edges[], seeds[], batched: true)
+                "#,
+            )
+            .unwrap();
+        let pairs = res
+            .rows
+            .iter()
+            .map(|row| (row[0].get_str().unwrap(), row[1].get_str().unwrap()))
+            .collect_vec();
+        assert_eq!(pairs, vec![("d", "d"), ("e", "e"), ("e", "f")]);
+        assert!((res.rows[0][2].get_float().unwrap() - 1.).abs() < 1e-9);
+
+        assert!(db
+            .run_default(
+                r#"
+                edges[f, t] <- [['a', 'b']]
+                seeds[n, w] <- [['a', -1.0]]
+                ?[node, score] <~ PersonalizedPageRank(edges[], seeds[])
+                "#,
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,370 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+pub(crate) struct SubgraphMatch;
+
+impl FixedRule for SubgraphMatch {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let data_edges = payload.get_input(0)?;
+        let pattern_edges = payload.get_input(1)?;
+        let data_labels = payload.get_input(2).ok();
+        let pattern_labels = payload.get_input(3).ok();
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let induced = payload.bool_option("induced", Some(false))?;
+        let limit = payload
+            .has_option("limit")
+            .then(|| payload.pos_integer_option("limit", None))
+            .transpose()?;
+
+        let data = LabelledGraph::new(data_edges, data_labels, undirected)?;
+        let pattern = LabelledGraph::new(pattern_edges, pattern_labels, undirected)?;
+        if pattern.nodes.is_empty() {
+            return Ok(());
+        }
+
+        let mut match_id = 0;
+        let mut emit = |mapping: &[u32]| {
+            for (p, d) in mapping.iter().enumerate() {
+                out.put(vec![
+                    DataValue::from(match_id as i64),
+                    pattern.nodes[p].clone(),
+                    data.nodes[*d as usize].clone(),
+                ]);
+            }
+            match_id += 1;
+            !matches!(limit, Some(l) if match_id >= l)
+        };
+        let mut matcher = Matcher {
+            order: pattern.matching_order(),
+            pattern: &pattern,
+            data: &data,
+            induced,
+            mapping: vec![u32::MAX; pattern.nodes.len()],
+            used: vec![false; data.nodes.len()],
+            emit: &mut emit,
+            poison,
+        };
+        matcher.extend(0)?;
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+}
+
+/// A graph whose nodes and edges may carry sets of labels.
+/// Edge labels come from the optional third column of the edge relation,
+/// node labels from a separate relation of `[node, label]` pairs.
+struct LabelledGraph {
+    nodes: Vec<DataValue>,
+    /// sorted and deduplicated
+    out: Vec<Vec<u32>>,
+    /// sorted and deduplicated
+    into: Vec<Vec<u32>>,
+    node_labels: Vec<BTreeSet<DataValue>>,
+    edge_labels: BTreeMap<(u32, u32), BTreeSet<DataValue>>,
+}
+
+impl LabelledGraph {
+    fn new(
+        edges: FixedRuleInputRelation<'_, '_>,
+        labels: Option<FixedRuleInputRelation<'_, '_>>,
+        undirected: bool,
+    ) -> Result<Self> {
+        let mut graph = LabelledGraph {
+            nodes: vec![],
+            out: vec![],
+            into: vec![],
+            node_labels: vec![],
+            edge_labels: Default::default(),
+        };
+        let mut inv_indices: BTreeMap<DataValue, u32> = Default::default();
+        let mut index_of = |graph: &mut LabelledGraph, node: DataValue| -> u32 {
+            *inv_indices.entry(node).or_insert_with_key(|k| {
+                graph.nodes.push(k.clone());
+                graph.out.push(vec![]);
+                graph.into.push(vec![]);
+                graph.node_labels.push(Default::default());
+                (graph.nodes.len() - 1) as u32
+            })
+        };
+
+        for tuple in edges.ensure_min_len(2)?.iter()? {
+            let mut tuple = tuple?.into_iter();
+            let from = index_of(&mut graph, tuple.next().unwrap());
+            let to = index_of(&mut graph, tuple.next().unwrap());
+            let label = tuple.next().filter(|l| *l != DataValue::Null);
+            let mut arcs = vec![(from, to)];
+            if undirected && from != to {
+                arcs.push((to, from));
+            }
+            for (from, to) in arcs {
+                graph.out[from as usize].push(to);
+                graph.into[to as usize].push(from);
+                if let Some(label) = &label {
+                    graph
+                        .edge_labels
+                        .entry((from, to))
+                        .or_default()
+                        .insert(label.clone());
+                }
+            }
+        }
+        if let Some(labels) = labels {
+            for tuple in labels.ensure_min_len(2)?.iter()? {
+                let mut tuple = tuple?.into_iter();
+                let node = index_of(&mut graph, tuple.next().unwrap());
+                graph.node_labels[node as usize].insert(tuple.next().unwrap());
+            }
+        }
+        for neighbors in graph.out.iter_mut().chain(graph.into.iter_mut()) {
+            neighbors.sort_unstable();
+            neighbors.dedup();
+        }
+        Ok(graph)
+    }
+
+    fn has_edge(&self, from: u32, to: u32) -> bool {
+        self.out[from as usize].binary_search(&to).is_ok()
+    }
+
+    fn degree(&self, node: usize) -> usize {
+        self.out[node].len() + self.into[node].len()
+    }
+
+    /// Pattern nodes ordered so that each node is as connected as possible to the ones
+    /// before it, starting from the node of highest degree, so that candidates can be drawn
+    /// from the neighbourhoods of already matched nodes and mismatches are found early.
+    fn matching_order(&self) -> Vec<usize> {
+        let n = self.nodes.len();
+        let mut order = vec![];
+        let mut placed = vec![false; n];
+        let mut connections = vec![0usize; n];
+        while order.len() < n {
+            let next = (0..n)
+                .filter(|node| !placed[*node])
+                .max_by_key(|node| {
+                    (
+                        connections[*node],
+                        self.node_labels[*node].len(),
+                        self.degree(*node),
+                        n - node,
+                    )
+                })
+                .unwrap();
+            placed[next] = true;
+            order.push(next);
+            for neighbor in self.out[next].iter().chain(self.into[next].iter()) {
+                connections[*neighbor as usize] += 1;
+            }
+        }
+        order
+    }
+}
+
+struct Matcher<'a, F> {
+    order: Vec<usize>,
+    pattern: &'a LabelledGraph,
+    data: &'a LabelledGraph,
+    induced: bool,
+    /// data node matched to each pattern node, `u32::MAX` if not yet matched
+    mapping: Vec<u32>,
+    used: Vec<bool>,
+    emit: &'a mut F,
+    poison: Poison,
+}
+
+impl<F: FnMut(&[u32]) -> bool> Matcher<'_, F> {
+    fn edge_compatible(&self, p_from: u32, p_to: u32, d_from: u32, d_to: u32) -> bool {
+        if !self.data.has_edge(d_from, d_to) {
+            return false;
+        }
+        match self.pattern.edge_labels.get(&(p_from, p_to)) {
+            None => true,
+            Some(required) => matches!(
+                self.data.edge_labels.get(&(d_from, d_to)),
+                Some(present) if required.is_subset(present)
+            ),
+        }
+    }
+
+    /// Whether pattern node `p` can be matched to data node `d` given the current mapping
+    fn feasible(&self, p: usize, d: u32) -> bool {
+        let d_idx = d as usize;
+        if self.used[d_idx]
+            || self.data.out[d_idx].len() < self.pattern.out[p].len()
+            || self.data.into[d_idx].len() < self.pattern.into[p].len()
+            || !self.pattern.node_labels[p].is_subset(&self.data.node_labels[d_idx])
+        {
+            return false;
+        }
+        if self.pattern.has_edge(p as u32, p as u32)
+            && !self.edge_compatible(p as u32, p as u32, d, d)
+        {
+            return false;
+        }
+        for (q, m) in self.mapping.iter().enumerate() {
+            if *m == u32::MAX {
+                continue;
+            }
+            for (pf, pt, df, dt) in [(p, q, d, *m), (q, p, *m, d)] {
+                if self.pattern.has_edge(pf as u32, pt as u32) {
+                    if !self.edge_compatible(pf as u32, pt as u32, df, dt) {
+                        return false;
+                    }
+                } else if self.induced && self.data.has_edge(df, dt) {
+                    return false;
+                }
+            }
+        }
+        if self.induced && self.data.has_edge(d, d) && !self.pattern.has_edge(p as u32, p as u32) {
+            return false;
+        }
+        true
+    }
+
+    /// Match the pattern nodes from position `depth` of the order onwards.
+    /// Returns whether more matches are wanted.
+    fn extend(&mut self, depth: usize) -> Result<bool> {
+        if depth == self.order.len() {
+            return Ok((self.emit)(&self.mapping));
+        }
+        self.poison.check()?;
+        let p = self.order[depth];
+        // draw candidates from the neighbourhood of a matched node if there is one
+        let anchored = self.pattern.into[p]
+            .iter()
+            .map(|q| (*q, true))
+            .chain(self.pattern.out[p].iter().map(|q| (*q, false)))
+            .find(|(q, _)| self.mapping[*q as usize] != u32::MAX);
+        let candidates = match anchored {
+            Some((q, true)) => self.data.out[self.mapping[q as usize] as usize].clone(),
+            Some((q, false)) => self.data.into[self.mapping[q as usize] as usize].clone(),
+            None => (0..self.data.nodes.len() as u32).collect_vec(),
+        };
+        for d in candidates {
+            if !self.feasible(p, d) {
+                continue;
+            }
+            self.mapping[p] = d;
+            self.used[d as usize] = true;
+            let proceed = self.extend(depth + 1)?;
+            self.mapping[p] = u32::MAX;
+            self.used[d as usize] = false;
+            if !proceed {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::data::value::DataValue;
+    use crate::DbInstance;
+
+    #[test]
+    fn test_subgraph_match() {
+        let db = DbInstance::default();
+        let data = r#"
+            data[f, t, l] <- [[1, 2, 'knows'], [2, 3, 'knows'], [3, 1, 'pays'], [3, 4, 'knows'],
+                              [4, 5, 'knows'], [5, 3, 'knows']]
+            triangle[f, t, l] <- [['a', 'b', null], ['b', 'c', null], ['c', 'a', null]]
+        "#;
+        let res = db
+            .run_default(&format!(
+                "{data} ?[id, p, d] <~ SubgraphMatch(data[], triangle[])"
+            ))
+            .unwrap();
+        assert_eq!(res.rows.len(), 6 * 3);
+
+        let res = db
+            .run_default(&format!(
+                "{data}
+                data_labels[n, l] <- [[3, 'hub'], [4, 'leaf']]
+                pattern_labels[n, l] <- [['a', 'hub']]
+                ?[id, p, d] <~ SubgraphMatch(data[], triangle[], data_labels[], pattern_labels[])"
+            ))
+            .unwrap();
+        assert_eq!(res.rows.len(), 2 * 3);
+        assert!(res
+            .rows
+            .iter()
+            .filter(|row| row[1] == DataValue::from("a"))
+            .all(|row| row[2] == DataValue::from(3)));
+
+        let res = db
+            .run_default(&format!(
+                "{data}
+                paying[f, t, l] <- [['a', 'b', 'pays'], ['b', 'c', null], ['c', 'a', null]]
+                ?[p, d] := m[_, p, d]
+                m[id, p, d] <~ SubgraphMatch(data[], paying[])"
+            ))
+            .unwrap();
+        assert_eq!(
+            res.into_json()["rows"],
+            json!([["a", 3], ["b", 1], ["c", 2]])
+        );
+
+        let undirected = r#"
+            data[f, t] <- [[1, 2], [2, 3], [3, 1], [3, 4]]
+            path[f, t] <- [['a', 'b'], ['b', 'c']]
+        "#;
+        let res = db
+            .run_default(&format!(
+                "{undirected} ?[id, p, d] <~ SubgraphMatch(data[], path[], undirected: true)"
+            ))
+            .unwrap();
+        assert_eq!(res.rows.len(), 10 * 3);
+        let res = db
+            .run_default(&format!(
+                "{undirected} ?[id, p, d] <~ SubgraphMatch(data[], path[], undirected: true, induced: true)"
+            ))
+            .unwrap();
+        assert_eq!(res.rows.len(), 4 * 3);
+        let res = db
+            .run_default(&format!(
+                "{undirected} ?[id, p, d] <~ SubgraphMatch(data[], path[], undirected: true, limit: 1)"
+            ))
+            .unwrap();
+        assert_eq!(res.rows.len(), 3);
+        assert!(db
+            .run_default(&format!(
+                "{undirected} ?[id, p, d] <~ SubgraphMatch(data[], path[], limit: 0)"
+            ))
+            .is_err());
+    }
+}
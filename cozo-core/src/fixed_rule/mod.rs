@@ -326,6 +326,55 @@ impl<'a, 'b> FixedRuleInputRelation<'a, 'b> {
 
         Ok((graph, indices, inv_indices))
     }
+    /// Interpret the input relation as the edges of a bipartite graph: the first column
+    /// holds the left vertices and the second column the right vertices, so the same value
+    /// occurring in both columns denotes two different vertices. The optional third column
+    /// holds the edge weights, which default to `1.0`.
+    ///
+    /// Returns the edges as pairs of indices into the returned left and right vertex vectors,
+    /// together with their weights.
+    #[cfg(feature = "graph-algo")]
+    pub fn as_bipartite_graph(
+        &self,
+        allow_negative_weights: bool,
+    ) -> Result<(Vec<(u32, u32, f64)>, Vec<DataValue>, Vec<DataValue>)> {
+        let mut left: Vec<DataValue> = vec![];
+        let mut inv_left: BTreeMap<DataValue, u32> = Default::default();
+        let mut right: Vec<DataValue> = vec![];
+        let mut inv_right: BTreeMap<DataValue, u32> = Default::default();
+        let mut edges = vec![];
+        for tuple in self.iter()? {
+            let mut tuple = tuple?.into_iter();
+            let (from, to) = match (tuple.next(), tuple.next()) {
+                (Some(from), Some(to)) => (from, to),
+                _ => bail!(NotAnEdgeError(self.span())),
+            };
+            let weight = match tuple.next() {
+                None => 1.0,
+                Some(d) => match d.get_float() {
+                    Some(f) if f.is_finite() && (allow_negative_weights || f >= 0.) => f,
+                    _ => bail!(BadEdgeWeightError(
+                        d,
+                        self.arg_manifest
+                            .bindings()
+                            .get(2)
+                            .map(|s| s.span)
+                            .unwrap_or_else(|| self.span())
+                    )),
+                },
+            };
+            let from_idx = *inv_left.entry(from).or_insert_with_key(|k| {
+                left.push(k.clone());
+                (left.len() - 1) as u32
+            });
+            let to_idx = *inv_right.entry(to).or_insert_with_key(|k| {
+                right.push(k.clone());
+                (right.len() - 1) as u32
+            });
+            edges.push((from_idx, to_idx, weight));
+        }
+        Ok((edges, left, right))
+    }
 }
 
 impl<'a, 'b> FixedRulePayload<'a, 'b> {
@@ -350,6 +399,10 @@ impl<'a, 'b> FixedRulePayload<'a, 'b> {
     pub fn span(&self) -> SourceSpan {
         self.manifest.span
     }
+    /// Whether the option is given
+    pub fn has_option(&self, name: &str) -> bool {
+        self.manifest.options.contains_key(name)
+    }
     /// Extract an expression option
     pub fn expr_option(&self, name: &str, default: Option<Expr>) -> Result<Expr> {
         match self.manifest.options.get(name) {
@@ -781,6 +834,16 @@ lazy_static! {
                 Arc::<Box<dyn FixedRule>>::new(Box::new(TopSort)),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "SimpleCycles".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(SimpleCycles)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "FindCycle".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(FindCycle)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "ConnectedComponents".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(StronglyConnectedComponent::new(false))),
@@ -796,16 +859,76 @@ lazy_static! {
                 Arc::<Box<dyn FixedRule>>::new(Box::new(StronglyConnectedComponent::new(true))),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "ArticulationPoints".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(ArticulationPoints)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "Bridges".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Bridges)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "BiconnectedComponents".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(BiconnectedComponents)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "PageRank".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(PageRank)),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "PersonalizedPageRank".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(PersonalizedPageRank)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "MaxFlow".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(MaxFlow)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "BipartiteMatching".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(BipartiteMatching)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "MinCostAssignment".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(MinCostAssignment)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "KCore".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(KCore)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "MaximalCliques".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(MaximalCliques)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "SubgraphMatch".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(SubgraphMatch)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "NodeSimilarity".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(NodeSimilarity)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "CommunityDetectionLouvain".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(CommunityDetectionLouvain)),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "CommunityDetectionLeiden".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(CommunityDetectionLeiden)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "LabelPropagation".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(LabelPropagation)),
@@ -857,7 +980,7 @@ struct NotAnEdgeError(#[label] SourceSpan);
 #[diagnostic(help(
     "Edge weights must be finite numbers. Some algorithm also requires positivity."
 ))]
-struct BadEdgeWeightError(DataValue, #[label] SourceSpan);
+pub(crate) struct BadEdgeWeightError(pub(crate) DataValue, #[label] pub(crate) SourceSpan);
 
 #[derive(Error, Diagnostic, Debug)]
 #[error("The requested rule '{0}' cannot be found")]
@@ -54,13 +54,21 @@ pub use runtime::db::Db;
 pub use runtime::db::NamedRows;
 pub use runtime::relation::decode_tuple_from_kv;
 pub use runtime::temp_store::RegularTempStore;
-pub use storage::mem::{new_cozo_mem, MemStorage};
+pub use storage::encrypted::{new_cozo_encrypted, EncryptedStorage, EncryptedTx, StorageCipher};
+pub use storage::mem::{new_cozo_mem, new_cozo_mem_from_snapshot, MemStorage};
+#[cfg(feature = "storage-redb")]
+pub use storage::redb::{new_cozo_redb, RedbStorage};
 #[cfg(feature = "storage-rocksdb")]
-pub use storage::rocks::{new_cozo_rocksdb, RocksDbStorage};
+pub use storage::rocks::{
+    new_cozo_rocksdb, new_cozo_rocksdb_from_backup, new_cozo_rocksdb_with_options,
+    RocksDbOptions, RocksDbStorage,
+};
 #[cfg(feature = "storage-sled")]
 pub use storage::sled::{new_cozo_sled, SledStorage};
 #[cfg(feature = "storage-sqlite")]
-pub use storage::sqlite::{new_cozo_sqlite, SqliteStorage};
+pub use storage::sqlite::{
+    new_cozo_sqlite, new_cozo_sqlite_with_options, SqliteOptions, SqliteStorage,
+};
 #[cfg(feature = "storage-tikv")]
 pub use storage::tikv::{new_cozo_tikv, TiKvStorage};
 pub use storage::{Storage, StoreTx};
@@ -77,6 +85,7 @@ pub use crate::runtime::db::get_variables;
 pub use crate::runtime::db::Poison;
 pub use crate::runtime::db::ScriptMutability;
 pub use crate::runtime::db::TransactionPayload;
+pub use crate::runtime::replication::{RelationSnapshot, ReplicationSource};
 
 pub(crate) mod data;
 pub(crate) mod fixed_rule;
@@ -106,12 +115,17 @@ pub enum DbInstance {
     #[cfg(feature = "storage-rocksdb")]
     /// RocksDB storage
     RocksDb(Db<RocksDbStorage>),
+    #[cfg(feature = "storage-redb")]
+    /// redb storage
+    Redb(Db<RedbStorage>),
     #[cfg(feature = "storage-sled")]
     /// Sled storage (experimental)
     Sled(Db<SledStorage>),
     #[cfg(feature = "storage-tikv")]
     /// TiKV storage (experimental)
     TiKv(Db<TiKvStorage>),
+    /// Any of the persistent engines above, with encrypted values
+    Encrypted(Db<EncryptedStorage>),
 }
 
 impl Default for DbInstance {
@@ -127,25 +141,130 @@ impl DbInstance {
     /// * `mem`
     /// * `sqlite`
     /// * `rocksdb`
+    /// * `redb`
     /// * `sled`
     /// * `tikv`
     ///
     /// assuming all features are enabled during compilation. Otherwise only
     /// some of the engines are available. The `mem` engine is always available.
     ///
-    /// `path` is ignored for `mem` and `tikv` engines.
-    /// `options` is ignored for every engine except `tikv`.
+    /// `path` is ignored for the `tikv` engine. For the `mem` engine, `path` is ignored unless
+    /// `options` is a JSON object asking for snapshots of the data to be kept there (see
+    /// [crate::Db::save_snapshot]): with `"load_snapshot": true` the database starts with the data
+    /// of the snapshot, if the file exists, and with `"save_on_close": true` a snapshot is saved
+    /// there when the database is dropped.
+    ///
+    /// For the `sqlite`, `rocksdb`, `redb` and `sled` engines, `options` must be empty or a JSON
+    /// object, even if the engine takes no other options. Setting `encryption_key` in it to a
+    /// 256-bit key written as 64 hexadecimal digits encrypts the stored values and the backups
+    /// with it, see [EncryptedStorage]; the instance is then [DbInstance::Encrypted] whatever
+    /// the engine. A database must always be opened with the key it was created with. Keys of
+    /// the store are not encrypted, and asking for it with `"encrypt_keys": true` is an error.
+    /// For `sqlite`, the options may also set the fields of [SqliteOptions]: the journal mode
+    /// (WAL by default), the `synchronous`, `cache_size` and `mmap_size` pragmas, the busy
+    /// timeout, the size of the connection pool, and whether to open an existing file
+    /// read-only.
+    /// For `rocksdb`, setting `statistics` to `true` collects the statistics behind the block
+    /// cache hit rate reported by `::stats`, see [RocksDbOptions].
+    /// For `tikv`, the options give the connection parameters.
     #[allow(unused_variables)]
     pub fn new(engine: &str, path: impl AsRef<Path>, options: &str) -> Result<Self> {
         let options = if options.is_empty() { "{}" } else { options };
+        #[derive(serde_derive::Deserialize)]
+        struct EncryptionOpts {
+            encryption_key: Option<String>,
+            #[serde(default)]
+            encrypt_keys: bool,
+        }
+        let encryptable = matches!(engine, "sqlite" | "rocksdb" | "redb" | "sled");
+        let enc_opts: Option<EncryptionOpts> = if encryptable {
+            Some(serde_json::from_str(options).into_diagnostic()?)
+        } else {
+            // other engines do not require the options to be JSON
+            serde_json::from_str(options).ok()
+        };
+        let cipher = match enc_opts {
+            None => None,
+            Some(enc_opts) => {
+                if enc_opts.encrypt_keys {
+                    bail!(
+                        "encryption of keys is not supported: range scans need the order of keys, \
+                        which order-preserving encryption would reveal"
+                    )
+                }
+                match &enc_opts.encryption_key {
+                    None => None,
+                    Some(_) if !encryptable => {
+                        bail!("encryption is not supported for the '{}' engine", engine)
+                    }
+                    Some(key) => Some(StorageCipher::from_hex(key)?),
+                }
+            }
+        };
         Ok(match engine {
-            "mem" => Self::Mem(new_cozo_mem()?),
+            "mem" => {
+                #[derive(serde_derive::Deserialize)]
+                struct MemOpts {
+                    #[serde(default)]
+                    load_snapshot: bool,
+                    #[serde(default)]
+                    save_on_close: bool,
+                }
+                let opts: MemOpts = serde_json::from_str(options).into_diagnostic()?;
+                let path = path.as_ref();
+                let has_path = !path.as_os_str().is_empty();
+                if (opts.load_snapshot || opts.save_on_close) && !has_path {
+                    bail!("'load_snapshot' and 'save_on_close' require a path for the snapshot")
+                }
+                let db = if opts.load_snapshot && path.exists() {
+                    let file = std::fs::File::open(path).into_diagnostic()?;
+                    new_cozo_mem_from_snapshot(file)?
+                } else {
+                    new_cozo_mem()?
+                };
+                if opts.save_on_close {
+                    db.db.save_snapshot_on_drop(path);
+                }
+                Self::Mem(db)
+            }
             #[cfg(feature = "storage-sqlite")]
-            "sqlite" => Self::Sqlite(new_cozo_sqlite(path)?),
+            "sqlite" => {
+                let opts: SqliteOptions = serde_json::from_str(options).into_diagnostic()?;
+                match cipher {
+                    None => Self::Sqlite(new_cozo_sqlite_with_options(path, opts)?),
+                    Some(cipher) => Self::Encrypted(new_cozo_encrypted(
+                        storage::sqlite::new_sqlite_storage(path, opts)?,
+                        cipher,
+                    )?),
+                }
+            }
             #[cfg(feature = "storage-rocksdb")]
-            "rocksdb" => Self::RocksDb(new_cozo_rocksdb(path)?),
+            "rocksdb" => {
+                let opts: RocksDbOptions = serde_json::from_str(options).into_diagnostic()?;
+                match cipher {
+                    None => Self::RocksDb(new_cozo_rocksdb_with_options(path, opts)?),
+                    Some(cipher) => Self::Encrypted(new_cozo_encrypted(
+                        storage::rocks::new_rocksdb_storage(path, opts)?,
+                        cipher,
+                    )?),
+                }
+            }
+            #[cfg(feature = "storage-redb")]
+            "redb" => match cipher {
+                None => Self::Redb(new_cozo_redb(path)?),
+                Some(cipher) => Self::Encrypted(new_cozo_encrypted(
+                    storage::redb::new_redb_storage(path)?,
+                    cipher,
+                )?),
+            },
             #[cfg(feature = "storage-sled")]
-            "sled" => Self::Sled(new_cozo_sled(path)?),
+            "sled" => match cipher {
+                None => Self::Sled(new_cozo_sled(path)?),
+                Some(cipher) => Self::Encrypted(new_cozo_encrypted(
+                    storage::sled::new_sled_storage(path)?,
+                    cipher,
+                )?),
+            },
             #[cfg(feature = "storage-tikv")]
             "tikv" => {
                 #[derive(serde_derive::Deserialize)]
@@ -183,10 +302,13 @@ impl DbInstance {
             DbInstance::Sqlite(db) => db.run_script(payload, params, mutability),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.run_script(payload, params, mutability),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.run_script(payload, params, mutability),
             #[cfg(feature = "storage-sled")]
             DbInstance::Sled(db) => db.run_script(payload, params, mutability),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.run_script(payload, params, mutability),
+            DbInstance::Encrypted(db) => db.run_script(payload, params, mutability),
         }
     }
     /// `run_script` with mutable script and no parameters
@@ -260,10 +382,103 @@ impl DbInstance {
             DbInstance::Sqlite(db) => db.export_relations(relations),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.export_relations(relations),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.export_relations(relations),
             #[cfg(feature = "storage-sled")]
             DbInstance::Sled(db) => db.export_relations(relations),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.export_relations(relations),
+            DbInstance::Encrypted(db) => db.export_relations(relations),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::read_changelog].
+    pub fn read_changelog(
+        &self,
+        relation: &str,
+        from: i64,
+        limit: Option<usize>,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.read_changelog(relation, from, limit),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.read_changelog(relation, from, limit),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.read_changelog(relation, from, limit),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.read_changelog(relation, from, limit),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.read_changelog(relation, from, limit),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.read_changelog(relation, from, limit),
+            DbInstance::Encrypted(db) => db.read_changelog(relation, from, limit),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::changelog_relations].
+    pub fn changelog_relations(&self) -> Result<Vec<String>> {
+        match self {
+            DbInstance::Mem(db) => db.changelog_relations(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.changelog_relations(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.changelog_relations(),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.changelog_relations(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.changelog_relations(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.changelog_relations(),
+            DbInstance::Encrypted(db) => db.changelog_relations(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::snapshot_relation].
+    pub fn snapshot_relation(&self, relation: &str) -> Result<RelationSnapshot> {
+        match self {
+            DbInstance::Mem(db) => db.snapshot_relation(relation),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.snapshot_relation(relation),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.snapshot_relation(relation),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.snapshot_relation(relation),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.snapshot_relation(relation),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.snapshot_relation(relation),
+            DbInstance::Encrypted(db) => db.snapshot_relation(relation),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::replica_position].
+    pub fn replica_position(&self, relation: &str) -> Result<Option<i64>> {
+        match self {
+            DbInstance::Mem(db) => db.replica_position(relation),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.replica_position(relation),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.replica_position(relation),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.replica_position(relation),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.replica_position(relation),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.replica_position(relation),
+            DbInstance::Encrypted(db) => db.replica_position(relation),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::replicate_from].
+    pub fn replicate_from(&self, source: &dyn ReplicationSource) -> Result<usize> {
+        match self {
+            DbInstance::Mem(db) => db.replicate_from(source),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.replicate_from(source),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.replicate_from(source),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.replicate_from(source),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.replicate_from(source),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.replicate_from(source),
+            DbInstance::Encrypted(db) => db.replicate_from(source),
         }
     }
     /// Export relations to JSON-encoded string.
@@ -300,10 +515,30 @@ impl DbInstance {
             DbInstance::Sqlite(db) => db.import_relations(data),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.import_relations(data),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.import_relations(data),
             #[cfg(feature = "storage-sled")]
             DbInstance::Sled(db) => db.import_relations(data),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.import_relations(data),
+            DbInstance::Encrypted(db) => db.import_relations(data),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::import_relations_bulk].
+    pub fn import_relations_bulk(&self, data: BTreeMap<String, NamedRows>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.import_relations_bulk(data),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_relations_bulk(data),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_relations_bulk(data),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.import_relations_bulk(data),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_relations_bulk(data),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_relations_bulk(data),
+            DbInstance::Encrypted(db) => db.import_relations_bulk(data),
         }
     }
     /// Import a relation, the data is given as a JSON string, and the returned result is converted into a string.
@@ -341,10 +576,30 @@ impl DbInstance {
             DbInstance::Sqlite(db) => db.backup_db(out_file),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.backup_db(out_file),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.backup_db(out_file),
             #[cfg(feature = "storage-sled")]
             DbInstance::Sled(db) => db.backup_db(out_file),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.backup_db(out_file),
+            DbInstance::Encrypted(db) => db.backup_db(out_file),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::backup_db_native].
+    pub fn backup_db_native(&self, out_dir: impl AsRef<Path>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.backup_db_native(out_dir),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.backup_db_native(out_dir),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.backup_db_native(out_dir),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.backup_db_native(out_dir),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.backup_db_native(out_dir),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.backup_db_native(out_dir),
+            DbInstance::Encrypted(db) => db.backup_db_native(out_dir),
         }
     }
     /// Backup the running database into an Sqlite file, with JSON string return value.
@@ -355,6 +610,24 @@ impl DbInstance {
             Err(err) => json!({"ok": false, "message": err.to_string()}).to_string(),
         }
     }
+    /// Save a snapshot of an in-memory database to `path`. See [crate::Db::save_snapshot].
+    /// Other engines have their own persistence, and return an error.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.save_snapshot(path),
+            #[allow(unreachable_patterns)]
+            _ => bail!("snapshots are only supported by the 'mem' engine, use backups instead"),
+        }
+    }
+    /// Write a snapshot of an in-memory database to `writer`. See [crate::Db::write_snapshot].
+    /// Other engines return an error.
+    pub fn write_snapshot(&self, writer: impl std::io::Write) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.write_snapshot(writer),
+            #[allow(unreachable_patterns)]
+            _ => bail!("snapshots are only supported by the 'mem' engine, use backups instead"),
+        }
+    }
     /// Dispatcher method. See [crate::Db::restore_backup].
     pub fn restore_backup(&self, in_file: impl AsRef<Path>) -> Result<()> {
         match self {
@@ -363,10 +636,13 @@ impl DbInstance {
             DbInstance::Sqlite(db) => db.restore_backup(in_file),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.restore_backup(in_file),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.restore_backup(in_file),
             #[cfg(feature = "storage-sled")]
             DbInstance::Sled(db) => db.restore_backup(in_file),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.restore_backup(in_file),
+            DbInstance::Encrypted(db) => db.restore_backup(in_file),
         }
     }
     /// Restore from an Sqlite backup, with JSON string return value.
@@ -377,6 +653,45 @@ impl DbInstance {
             Err(err) => json!({"ok": false, "message": err.to_string()}).to_string(),
         }
     }
+    /// Dispatcher method. See [crate::Db::enable_mutation_log].
+    pub fn enable_mutation_log(&self, dir: impl AsRef<Path>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.enable_mutation_log(dir),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.enable_mutation_log(dir),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.enable_mutation_log(dir),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.enable_mutation_log(dir),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.enable_mutation_log(dir),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.enable_mutation_log(dir),
+            DbInstance::Encrypted(db) => db.enable_mutation_log(dir),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::restore_to_point_in_time].
+    pub fn restore_to_point_in_time(
+        &self,
+        backup: impl AsRef<Path>,
+        log_dir: impl AsRef<Path>,
+        ts: f64,
+    ) -> Result<usize> {
+        match self {
+            DbInstance::Mem(db) => db.restore_to_point_in_time(backup, log_dir, ts),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.restore_to_point_in_time(backup, log_dir, ts),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.restore_to_point_in_time(backup, log_dir, ts),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.restore_to_point_in_time(backup, log_dir, ts),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.restore_to_point_in_time(backup, log_dir, ts),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.restore_to_point_in_time(backup, log_dir, ts),
+            DbInstance::Encrypted(db) => db.restore_to_point_in_time(backup, log_dir, ts),
+        }
+    }
     /// Dispatcher method. See [crate::Db::import_from_backup].
     pub fn import_from_backup(
         &self,
@@ -389,10 +704,13 @@ impl DbInstance {
             DbInstance::Sqlite(db) => db.import_from_backup(in_file, relations),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.import_from_backup(in_file, relations),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.import_from_backup(in_file, relations),
             #[cfg(feature = "storage-sled")]
             DbInstance::Sled(db) => db.import_from_backup(in_file, relations),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.import_from_backup(in_file, relations),
+            DbInstance::Encrypted(db) => db.import_from_backup(in_file, relations),
         }
     }
     /// Import relations from an Sqlite backup, with JSON string return value.
@@ -427,10 +745,13 @@ impl DbInstance {
             DbInstance::Sqlite(db) => db.register_callback(relation, capacity),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.register_callback(relation, capacity),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.register_callback(relation, capacity),
             #[cfg(feature = "storage-sled")]
             DbInstance::Sled(db) => db.register_callback(relation, capacity),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.register_callback(relation, capacity),
+            DbInstance::Encrypted(db) => db.register_callback(relation, capacity),
         }
     }
 
@@ -443,10 +764,47 @@ impl DbInstance {
             DbInstance::Sqlite(db) => db.unregister_callback(id),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.unregister_callback(id),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.unregister_callback(id),
             #[cfg(feature = "storage-sled")]
             DbInstance::Sled(db) => db.unregister_callback(id),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.unregister_callback(id),
+            DbInstance::Encrypted(db) => db.unregister_callback(id),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_read_only].
+    pub fn set_read_only(&self, read_only: bool) {
+        match self {
+            DbInstance::Mem(db) => db.set_read_only(read_only),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_read_only(read_only),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_read_only(read_only),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.set_read_only(read_only),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_read_only(read_only),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_read_only(read_only),
+            DbInstance::Encrypted(db) => db.set_read_only(read_only),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_default_memory_limit].
+    pub fn set_default_memory_limit(&self, limit: Option<usize>) {
+        match self {
+            DbInstance::Mem(db) => db.set_default_memory_limit(limit),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_default_memory_limit(limit),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_default_memory_limit(limit),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.set_default_memory_limit(limit),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_default_memory_limit(limit),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_default_memory_limit(limit),
+            DbInstance::Encrypted(db) => db.set_default_memory_limit(limit),
         }
     }
     /// Dispatcher method. See [crate::Db::register_fixed_rule].
@@ -460,10 +818,13 @@ impl DbInstance {
             DbInstance::Sqlite(db) => db.register_fixed_rule(name, rule_impl),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.register_fixed_rule(name, rule_impl),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.register_fixed_rule(name, rule_impl),
             #[cfg(feature = "storage-sled")]
             DbInstance::Sled(db) => db.register_fixed_rule(name, rule_impl),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.register_fixed_rule(name, rule_impl),
+            DbInstance::Encrypted(db) => db.register_fixed_rule(name, rule_impl),
         }
     }
     /// Dispatcher method. See [crate::Db::unregister_fixed_rule]
@@ -474,10 +835,13 @@ impl DbInstance {
             DbInstance::Sqlite(db) => db.unregister_fixed_rule(name),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.unregister_fixed_rule(name),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.unregister_fixed_rule(name),
             #[cfg(feature = "storage-sled")]
             DbInstance::Sled(db) => db.unregister_fixed_rule(name),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.unregister_fixed_rule(name),
+            DbInstance::Encrypted(db) => db.unregister_fixed_rule(name),
         }
     }
 
@@ -494,10 +858,13 @@ impl DbInstance {
             DbInstance::Sqlite(db) => db.run_multi_transaction(write, payloads, results),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.run_multi_transaction(write, payloads, results),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.run_multi_transaction(write, payloads, results),
             #[cfg(feature = "storage-sled")]
             DbInstance::Sled(db) => db.run_multi_transaction(write, payloads, results),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.run_multi_transaction(write, payloads, results),
+            DbInstance::Encrypted(db) => db.run_multi_transaction(write, payloads, results),
         }
     }
     /// A higher-level, blocking wrapper for [crate::Db::run_multi_transaction]. Runs the transaction on a dedicated thread.
@@ -151,6 +151,34 @@ impl ImperativeStmt {
             ImperativeStmt::SysOp { .. } => {}
         }
     }
+    /// Collect the relations that system ops in the statement lock exclusively.
+    /// Such locks must be taken before the script starts, as the script holds the
+    /// shared locks of the relations it writes to.
+    pub(crate) fn needs_exclusive_locks(
+        &self,
+        collector: &mut BTreeSet<SmartString<LazyCompact>>,
+    ) {
+        match self {
+            ImperativeStmt::SysOp { sysop } => {
+                collector.extend(sysop.sysop.exclusive_lock_names().cloned())
+            }
+            ImperativeStmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                for prog in then_branch.iter().chain(else_branch.iter()) {
+                    prog.needs_exclusive_locks(collector);
+                }
+            }
+            ImperativeStmt::Loop { body, .. } => {
+                for prog in body {
+                    prog.needs_exclusive_locks(collector);
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 impl CozoScript {
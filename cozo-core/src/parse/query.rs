@@ -251,6 +251,23 @@ pub(crate) fn parse_query(
                     out_opts.timeout = None;
                 }
             }
+            Rule::memory_limit_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let limit = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("memory_limit", span, [err]))?;
+                // `null` lifts any limit set as the database default
+                out_opts.memory_limit = if limit == DataValue::Null {
+                    Some(None)
+                } else {
+                    let limit = limit
+                        .get_non_neg_int()
+                        .filter(|l| *l > 0)
+                        .ok_or(OptionNotPosIntError("memory_limit", span))?;
+                    Some(Some(limit as usize))
+                };
+            }
             Rule::sleep_option => {
                 #[cfg(target_arch = "wasm32")]
                 bail!(":sleep is not supported under WASM");
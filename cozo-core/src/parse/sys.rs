@@ -29,6 +29,8 @@ use crate::{Expr, FixedRule};
 #[derive(Debug)]
 pub(crate) enum SysOp {
     Compact,
+    Stats(Option<Symbol>, bool),
+    Dump(Vec<Symbol>),
     ListColumns(Symbol),
     ListIndices(Symbol),
     ListRelations,
@@ -39,16 +41,41 @@ pub(crate) enum SysOp {
     RemoveRelation(Vec<Symbol>),
     RenameRelation(Vec<(Symbol, Symbol)>),
     ShowTrigger(Symbol),
-    SetTriggers(Symbol, Vec<String>, Vec<String>, Vec<String>),
+    SetTriggers(
+        Symbol,
+        Vec<String>,
+        Vec<String>,
+        Vec<String>,
+        Vec<String>,
+        Vec<String>,
+    ),
     SetAccessLevel(Vec<Symbol>, AccessLevel),
     CreateIndex(Symbol, Symbol, Vec<Symbol>),
     CreateVectorIndex(HnswIndexConfig),
     CreateFtsIndex(FtsIndexConfig),
     CreateMinHashLshIndex(MinHashLshConfig),
     RemoveIndex(Symbol, Symbol),
+    SetChangelog(Symbol, Option<u64>),
+    RemoveChangelog(Symbol),
     DescribeRelation(Symbol, SmartString<LazyCompact>)
 }
 
+impl SysOp {
+    /// The relations the op locks exclusively while it runs.
+    pub(crate) fn exclusive_lock_names(&self) -> impl Iterator<Item = &SmartString<LazyCompact>> {
+        let name = match self {
+            SysOp::CreateIndex(rel_name, _, _)
+            | SysOp::SetChangelog(rel_name, _)
+            | SysOp::RemoveChangelog(rel_name) => Some(&rel_name.name),
+            SysOp::CreateVectorIndex(config) => Some(&config.base_relation),
+            SysOp::CreateFtsIndex(config) => Some(&config.base_relation),
+            SysOp::CreateMinHashLshIndex(config) => Some(&config.base_relation),
+            _ => None,
+        };
+        name.into_iter()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct FtsIndexConfig {
     pub(crate) base_relation: SmartString<LazyCompact>,
@@ -110,6 +137,24 @@ pub(crate) fn parse_sys(
     let inner = src.next().unwrap();
     Ok(match inner.as_rule() {
         Rule::compact_op => SysOp::Compact,
+        Rule::dump_op => {
+            let rels = inner
+                .into_inner()
+                .map(|rel_p| Symbol::new(rel_p.as_str(), rel_p.extract_span()))
+                .collect_vec();
+            SysOp::Dump(rels)
+        }
+        Rule::stats_op => {
+            let mut exact = false;
+            let mut rel = None;
+            for p in inner.into_inner() {
+                match p.as_rule() {
+                    Rule::stats_exact => exact = true,
+                    _ => rel = Some(Symbol::new(p.as_str(), p.extract_span())),
+                }
+            }
+            SysOp::Stats(rel, exact)
+        }
         Rule::running_op => SysOp::ListRunning,
         Rule::kill_op => {
             let i_expr = inner.into_inner().next().unwrap();
@@ -200,12 +245,15 @@ pub(crate) fn parse_sys(
             let mut puts = vec![];
             let mut rms = vec![];
             let mut replaces = vec![];
+            let mut before_puts = vec![];
+            let mut before_rms = vec![];
             for clause in src {
                 let mut clause_inner = clause.into_inner();
                 let op = clause_inner.next().unwrap();
                 let script = clause_inner.next().unwrap();
                 let script_str = script.as_str();
-                parse_query(
+                let span = script.extract_span();
+                let program = parse_query(
                     script.into_inner(),
                     &Default::default(),
                     algorithms,
@@ -215,10 +263,29 @@ pub(crate) fn parse_sys(
                     Rule::trigger_put => puts.push(script_str.to_string()),
                     Rule::trigger_rm => rms.push(script_str.to_string()),
                     Rule::trigger_replace => replaces.push(script_str.to_string()),
+                    Rule::trigger_before_put | Rule::trigger_before_rm => {
+                        #[derive(Debug, Error, Diagnostic)]
+                        #[error("Before triggers cannot mutate relations")]
+                        #[diagnostic(code(parser::mutating_before_trigger))]
+                        #[diagnostic(help(
+                            "A before trigger rejects the write by returning rows; use an 'on put' or 'on rm' trigger for mutations"
+                        ))]
+                        struct MutatingBeforeTrigger(#[label] SourceSpan);
+
+                        ensure!(
+                            program.out_opts.store_relation.is_none(),
+                            MutatingBeforeTrigger(span)
+                        );
+                        if op.as_rule() == Rule::trigger_before_put {
+                            before_puts.push(script_str.to_string())
+                        } else {
+                            before_rms.push(script_str.to_string())
+                        }
+                    }
                     r => unreachable!("{:?}", r),
                 }
             }
-            SysOp::SetTriggers(rel, puts, rms, replaces)
+            SysOp::SetTriggers(rel, puts, rms, replaces, before_puts, before_rms)
         }
         Rule::lsh_idx_op => {
             let inner = inner.into_inner().next().unwrap();
@@ -669,6 +736,39 @@ pub(crate) fn parse_sys(
                 _ => unreachable!(),
             }
         }
+        Rule::changelog_op => {
+            let inner = inner.into_inner().next().unwrap();
+            match inner.as_rule() {
+                Rule::changelog_on => {
+                    let mut inner = inner.into_inner();
+                    let rel = inner.next().unwrap();
+                    let retain = match inner.next() {
+                        None => None,
+                        Some(retain_p) => {
+                            let span = retain_p.extract_span();
+                            let retain = build_expr(retain_p, param_pool)?.eval_to_const()?;
+                            match retain.get_non_neg_int() {
+                                Some(n) if n > 0 => Some(n),
+                                _ => {
+                                    #[derive(Debug, Diagnostic, Error)]
+                                    #[error("changelog retention must be a positive integer")]
+                                    #[diagnostic(code(parser::bad_changelog_retention))]
+                                    struct BadChangelogRetention(#[label] SourceSpan);
+
+                                    bail!(BadChangelogRetention(span))
+                                }
+                            }
+                        }
+                    };
+                    SysOp::SetChangelog(Symbol::new(rel.as_str(), rel.extract_span()), retain)
+                }
+                Rule::changelog_off => {
+                    let rel = inner.into_inner().next().unwrap();
+                    SysOp::RemoveChangelog(Symbol::new(rel.as_str(), rel.extract_span()))
+                }
+                _ => unreachable!(),
+            }
+        }
         Rule::list_fixed_rules => SysOp::ListFixedRules,
         r => unreachable!("{:?}", r),
     })
@@ -27,7 +27,9 @@ use crate::query::compile::{
     AggrKind, CompiledProgram, CompiledRule, CompiledRuleSet, ContainedRuleMultiplicity,
 };
 use crate::runtime::db::Poison;
-use crate::runtime::temp_store::{EpochStore, MeetAggrStore, RegularTempStore};
+use crate::runtime::temp_store::{
+    approx_tuple_size, EpochStore, MeetAggrStore, MemoryBudget, RegularTempStore,
+};
 use crate::runtime::transact::SessionTx;
 
 pub(crate) struct QueryLimiter {
@@ -69,6 +71,7 @@ impl<'a> SessionTx<'a> {
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
         poison: Poison,
+        budget: MemoryBudget,
     ) -> Result<(EpochStore, bool)> {
         let mut stores: BTreeMap<MagicSymbol, EpochStore> = BTreeMap::new();
         let mut early_return = false;
@@ -101,6 +104,7 @@ impl<'a> SessionTx<'a> {
                 total_num_to_take,
                 num_to_skip,
                 poison.clone(),
+                &budget,
             )?;
         }
         let entry_symbol = MagicSymbol::Muggle {
@@ -117,6 +121,7 @@ impl<'a> SessionTx<'a> {
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
         poison: Poison,
+        budget: &MemoryBudget,
     ) -> Result<bool> {
         let limiter = QueryLimiter {
             total: total_num_to_take,
@@ -128,6 +133,7 @@ impl<'a> SessionTx<'a> {
 
         for epoch in 0u32.. {
             debug!("epoch {}", epoch);
+            budget.reset(stores.values().map(|s| s.approx_size()).sum())?;
             let mut to_merge = BTreeMap::new();
             let borrowed_stores = stores as &BTreeMap<_, _>;
             if epoch == 0 {
@@ -142,6 +148,7 @@ impl<'a> SessionTx<'a> {
                                     borrowed_stores,
                                     &limiter,
                                     poison.clone(),
+                                    budget,
                                 )?;
                                 used_limiter.fetch_or(res.0, Ordering::Relaxed);
                                 res.1.wrap()
@@ -153,6 +160,7 @@ impl<'a> SessionTx<'a> {
                                     borrowed_stores,
                                     &limiter,
                                     poison.clone(),
+                                    budget,
                                 )?;
                                 used_limiter.fetch_or(res.0, Ordering::Relaxed);
                                 res.1.wrap()
@@ -163,6 +171,7 @@ impl<'a> SessionTx<'a> {
                                     &ruleset,
                                     borrowed_stores,
                                     poison.clone(),
+                                    budget,
                                 )?;
                                 new.wrap()
                             }
@@ -176,6 +185,7 @@ impl<'a> SessionTx<'a> {
                                 tx: self,
                             };
                             fixed_impl.run(payload, &mut out, poison.clone())?;
+                            budget.charge(out.approx_size())?;
                             out.wrap()
                         }
                     };
@@ -228,6 +238,7 @@ impl<'a> SessionTx<'a> {
                                         borrowed_stores,
                                         &limiter,
                                         poison.clone(),
+                                        budget,
                                     )?;
                                     used_limiter.fetch_or(res.0, Ordering::Relaxed);
                                     res.1.wrap()
@@ -238,6 +249,7 @@ impl<'a> SessionTx<'a> {
                                         &ruleset,
                                         borrowed_stores,
                                         poison.clone(),
+                                        budget,
                                     )?;
                                     new.wrap()
                                 }
@@ -309,6 +321,7 @@ impl<'a> SessionTx<'a> {
         stores: &BTreeMap<MagicSymbol, EpochStore>,
         limiter: &QueryLimiter,
         poison: Poison,
+        budget: &MemoryBudget,
     ) -> Result<(bool, RegularTempStore)> {
         let mut out_store = RegularTempStore::default();
         let should_check_limit = limiter.total.is_some() && rule_symb.is_prog_entry();
@@ -320,18 +333,14 @@ impl<'a> SessionTx<'a> {
                 trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
                 if should_check_limit {
                     if !out_store.exists(&item) {
-                        if limiter.should_skip_next() {
-                            out_store.put_with_skip(item);
-                        } else {
-                            out_store.put(item);
-                        }
+                        out_store.put_charged(item, limiter.should_skip_next(), budget)?;
                         if limiter.incr_and_should_stop() {
                             trace!("early stopping due to result count limit exceeded");
                             return Ok((true, out_store));
                         }
                     }
                 } else {
-                    out_store.put(item);
+                    out_store.put_charged(item, false, budget)?;
                 }
             }
             poison.check()?;
@@ -345,6 +354,7 @@ impl<'a> SessionTx<'a> {
         ruleset: &[CompiledRule],
         stores: &BTreeMap<MagicSymbol, EpochStore>,
         poison: Poison,
+        budget: &MemoryBudget,
     ) -> Result<MeetAggrStore> {
         let mut out_store = MeetAggrStore::new(ruleset[0].aggr.clone())?;
 
@@ -357,7 +367,7 @@ impl<'a> SessionTx<'a> {
             for item_res in rule.relation.iter(self, None, stores)? {
                 let item = item_res?;
                 trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
-                out_store.meet_put(item)?;
+                out_store.meet_put(item, budget)?;
             }
             poison.check()?;
         }
@@ -374,7 +384,7 @@ impl<'a> SessionTx<'a> {
                     Ok(op.init_val())
                 })
                 .try_collect()?;
-            out_store.meet_put(value)?;
+            out_store.meet_put(value, budget)?;
         }
         Ok(out_store)
     }
@@ -385,6 +395,7 @@ impl<'a> SessionTx<'a> {
         stores: &BTreeMap<MagicSymbol, EpochStore>,
         limiter: &QueryLimiter,
         poison: Poison,
+        budget: &MemoryBudget,
     ) -> Result<(bool, RegularTempStore)> {
         let mut out_store = RegularTempStore::default();
         let should_check_limit = limiter.total.is_some() && rule_symb.is_prog_entry();
@@ -432,6 +443,7 @@ impl<'a> SessionTx<'a> {
                         }
                     }
                     Entry::Vacant(ent) => {
+                        budget.charge(approx_tuple_size(ent.key()))?;
                         let mut aggr_ops = Vec::with_capacity(val_indices_and_aggrs.len());
                         for (i, (aggr, params)) in &val_indices_and_aggrs {
                             let mut cur_aggr = aggr.clone();
@@ -471,7 +483,7 @@ impl<'a> SessionTx<'a> {
                     op.get()
                 })
                 .try_collect()?;
-            out_store.put(empty_result);
+            out_store.put_charged(empty_result, false, budget)?;
         }
 
         for (keys, aggrs) in aggr_work {
@@ -488,18 +500,14 @@ impl<'a> SessionTx<'a> {
             let tuple = tuple_data;
             if should_check_limit {
                 if !out_store.exists(&tuple) {
-                    if limiter.should_skip_next() {
-                        out_store.put_with_skip(tuple);
-                    } else {
-                        out_store.put(tuple);
-                    }
+                    out_store.put_charged(tuple, limiter.should_skip_next(), budget)?;
                     if limiter.incr_and_should_stop() {
                         return Ok((true, out_store));
                     }
                 }
                 // else, do nothing
             } else {
-                out_store.put(tuple);
+                out_store.put_charged(tuple, false, budget)?;
             }
         }
         Ok((should_check_limit, out_store))
@@ -512,6 +520,7 @@ impl<'a> SessionTx<'a> {
         stores: &BTreeMap<MagicSymbol, EpochStore>,
         limiter: &QueryLimiter,
         poison: Poison,
+        budget: &MemoryBudget,
     ) -> Result<(bool, RegularTempStore)> {
         let prev_store = stores.get(rule_symb).unwrap();
         let mut out_store = RegularTempStore::default();
@@ -555,11 +564,7 @@ impl<'a> SessionTx<'a> {
                             item,
                             epoch
                         );
-                        if limiter.should_skip_next() {
-                            out_store.put_with_skip(item);
-                        } else {
-                            out_store.put(item);
-                        }
+                        out_store.put_charged(item, limiter.should_skip_next(), budget)?;
                         if should_check_limit && limiter.incr_and_should_stop() {
                             trace!("early stopping due to result count limit exceeded");
                             return Ok((true, out_store));
@@ -595,11 +600,7 @@ impl<'a> SessionTx<'a> {
                                 item,
                                 epoch
                             );
-                            if limiter.should_skip_next() {
-                                out_store.put_with_skip(item);
-                            } else {
-                                out_store.put(item);
-                            }
+                            out_store.put_charged(item, limiter.should_skip_next(), budget)?;
                             if should_check_limit && limiter.incr_and_should_stop() {
                                 trace!("early stopping due to result count limit exceeded");
                                 return Ok((true, out_store));
@@ -618,6 +619,7 @@ impl<'a> SessionTx<'a> {
         ruleset: &[CompiledRule],
         stores: &BTreeMap<MagicSymbol, EpochStore>,
         poison: Poison,
+        budget: &MemoryBudget,
     ) -> Result<MeetAggrStore> {
         let mut out_store = MeetAggrStore::new(ruleset[0].aggr.clone())?;
         for (rule_n, rule) in ruleset.iter().enumerate() {
@@ -646,7 +648,7 @@ impl<'a> SessionTx<'a> {
             if need_complete_run {
                 debug!("complete run for rule {:?}.{}", rule_symb, rule_n);
                 for item_res in rule.relation.iter(self, None, stores)? {
-                    out_store.meet_put(item_res?)?;
+                    out_store.meet_put(item_res?, budget)?;
                 }
                 poison.check()?;
             } else {
@@ -659,7 +661,7 @@ impl<'a> SessionTx<'a> {
                         delta_key, rule_symb, rule_n
                     );
                     for item_res in rule.relation.iter(self, Some(delta_key), stores)? {
-                        out_store.meet_put(item_res?)?;
+                        out_store.meet_put(item_res?, budget)?;
                     }
                     poison.check()?;
                 }
@@ -9,6 +9,7 @@
 use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
+use either::{Left, Right};
 use itertools::Itertools;
 use miette::{bail, Diagnostic, IntoDiagnostic, Result, WrapErr};
 use pest::Parser;
@@ -26,7 +27,9 @@ use crate::fixed_rule::FixedRuleHandle;
 use crate::fts::tokenizer::TextAnalyzer;
 use crate::parse::expr::build_expr;
 use crate::parse::{parse_script, CozoScriptParser, Rule};
-use crate::runtime::callback::{CallbackCollector, CallbackOp};
+use crate::runtime::callback::{
+    collect_schema_change, schema_rows, CallbackCollector, CallbackOp,
+};
 use crate::runtime::minhash_lsh::HashPermutations;
 use crate::runtime::relation::{
     extend_tuple_from_v, AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
@@ -56,6 +59,8 @@ impl<'a> SessionTx<'a> {
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
         let mut to_clear = vec![];
         let mut replaced_old_triggers = None;
+        let mut replaced_old_schema = None;
+        let mut replaced_changelog = None;
         if op == RelationOp::Replace {
             if !propagate_triggers {
                 #[derive(Debug, Error, Diagnostic)]
@@ -64,7 +69,7 @@ impl<'a> SessionTx<'a> {
                 struct ReplaceInTrigger(String);
                 bail!(ReplaceInTrigger(meta.name.to_string()))
             }
-            if let Ok(old_handle) = self.get_relation(&meta.name, true) {
+            if let Ok(mut old_handle) = self.get_relation(&meta.name, true) {
                 if !old_handle.indices.is_empty() {
                     #[derive(Debug, Error, Diagnostic)]
                     #[error("cannot replace relation {0} since it has indices")]
@@ -79,8 +84,19 @@ impl<'a> SessionTx<'a> {
                         old_handle.access_level
                     ));
                 }
+                // the changelog outlives the replacement, so that its consumers see the
+                // replacement in sequence with the other changes
+                if let Some(changelog) = old_handle.changelog.take() {
+                    self.write_relation_handle(&old_handle)?;
+                    replaced_changelog = Some(changelog);
+                }
                 if old_handle.has_triggers() {
-                    replaced_old_triggers = Some((old_handle.put_triggers, old_handle.rm_triggers))
+                    replaced_old_triggers = Some((
+                        old_handle.put_triggers,
+                        old_handle.rm_triggers,
+                        old_handle.before_put_triggers,
+                        old_handle.before_rm_triggers,
+                    ))
                 }
                 for trigger in &old_handle.replace_triggers {
                     let program = parse_script(
@@ -109,6 +125,7 @@ impl<'a> SessionTx<'a> {
                         })?;
                     to_clear.extend(cleanups);
                 }
+                replaced_old_schema = Some(schema_rows(&old_handle.metadata));
                 let destroy_res = self.destroy_relation(&meta.name)?;
                 if !meta.name.is_temp_store_name() {
                     to_clear.extend(destroy_res);
@@ -120,9 +137,50 @@ impl<'a> SessionTx<'a> {
         } else {
             self.get_relation(&meta.name, false)?
         };
-        if let Some((old_put, old_retract)) = replaced_old_triggers {
+        if let Some((old_put, old_retract, old_before_put, old_before_retract)) =
+            replaced_old_triggers
+        {
             relation_store.put_triggers = old_put;
             relation_store.rm_triggers = old_retract;
+            relation_store.before_put_triggers = old_before_put;
+            relation_store.before_rm_triggers = old_before_retract;
+        }
+        if let Some(changelog) = replaced_changelog {
+            relation_store.changelog = Some(changelog);
+            self.write_relation_handle(&relation_store)?;
+            let column_names = |schema: &NamedRows| {
+                schema
+                    .headers
+                    .iter()
+                    .map(|h| DataValue::from(h.as_str()))
+                    .collect_vec()
+            };
+            if let (Some(mut log), Some(old_schema)) = (
+                self.changelog_writer(&relation_store, cur_vld)?,
+                &replaced_old_schema,
+            ) {
+                log.append(
+                    self,
+                    CallbackOp::Replace,
+                    column_names(&schema_rows(&relation_store.metadata)),
+                    Some(column_names(old_schema)),
+                )?;
+                log.finish(self)?;
+            }
+        }
+        if op == RelationOp::Replace || op == RelationOp::Create {
+            let (event, old_schema) = match replaced_old_schema {
+                Some(old_schema) => (CallbackOp::Replace, old_schema),
+                None => (CallbackOp::Create, NamedRows::default()),
+            };
+            collect_schema_change(
+                callback_targets,
+                callback_collector,
+                &relation_store.name,
+                event,
+                schema_rows(&relation_store.metadata),
+                old_schema,
+            );
         }
         let InputRelationHandle {
             metadata,
@@ -273,12 +331,46 @@ impl<'a> SessionTx<'a> {
         let fts_lsh_processors = self.make_fts_lsh_processors(relation_store)?;
         let lsh_perms = self.make_lsh_hash_perms(relation_store);
 
-        for tuple in res_iter {
-            let extracted: Vec<DataValue> = key_extractors
+        let extract = |tuple: Tuple| -> Result<Vec<DataValue>> {
+            key_extractors
                 .iter()
                 .map(|ex| ex.extract_data(&tuple, cur_vld))
-                .try_collect()?;
+                .try_collect()
+        };
+        let rows = if propagate_triggers
+            && !relation_store.is_temp
+            && !relation_store.before_put_triggers.is_empty()
+        {
+            // rows are extracted only once, so that generated defaults seen by
+            // the triggers are the ones written
+            let rows: Vec<Vec<DataValue>> = res_iter.map(extract).try_collect()?;
+            let mut before_old = vec![];
+            for extracted in &rows {
+                let key = relation_store.encode_key_for_store(extracted, span)?;
+                if let Some(existing) = self.store_tx.get(&key, false)? {
+                    let mut tup = extracted[0..relation_store.metadata.keys.len()].to_vec();
+                    extend_tuple_from_v(&mut tup, &existing);
+                    before_old.push(DataValue::List(tup));
+                }
+            }
+            let before_new = rows.iter().map(|r| DataValue::List(r.clone())).collect();
+            self.run_before_triggers(
+                db,
+                cur_vld,
+                relation_store,
+                &relation_store.before_put_triggers,
+                relation_store.metadata.keys.len() + relation_store.metadata.non_keys.len(),
+                before_new,
+                before_old,
+            )?;
+            Left(rows.into_iter().map(Ok))
+        } else {
+            Right(res_iter.map(extract))
+        };
+        let mut changelog = self.changelog_writer(relation_store, cur_vld)?;
 
+        for extracted in rows {
+            let extracted = extracted?;
             let key = relation_store.encode_key_for_store(&extracted, span)?;
 
             if is_insert {
@@ -300,22 +392,25 @@ impl<'a> SessionTx<'a> {
             let val = relation_store.encode_val_for_store(&extracted, span)?;
 
             if need_to_collect
+                || changelog.is_some()
                 || has_indices
                 || has_hnsw_indices
                 || has_fts_indices
                 || has_lsh_indices
             {
-                if let Some(existing) = self.store_tx.get(&key, false)? {
-                    let mut tup = extracted[0..relation_store.metadata.keys.len()].to_vec();
-                    extend_tuple_from_v(&mut tup, &existing);
-                    if has_indices && extracted != tup {
-                        self.update_in_index(relation_store, &extracted, &tup)?;
-                        self.del_in_fts(relation_store, &mut stack, &fts_lsh_processors, &tup)?;
-                        self.del_in_lsh(relation_store, &tup)?;
+                let old_tup = match self.store_tx.get(&key, false)? {
+                    Some(existing) => {
+                        let mut tup = extracted[0..relation_store.metadata.keys.len()].to_vec();
+                        extend_tuple_from_v(&mut tup, &existing);
+                        Some(tup)
                     }
-
-                    if need_to_collect {
-                        old_tuples.push(DataValue::List(tup));
+                    None => None,
+                };
+                if let Some(tup) = &old_tup {
+                    if has_indices && extracted != *tup {
+                        self.update_in_index(relation_store, &extracted, tup)?;
+                        self.del_in_fts(relation_store, &mut stack, &fts_lsh_processors, tup)?;
+                        self.del_in_lsh(relation_store, tup)?;
                     }
                 } else if has_indices {
                     for (idx_rel, extractor) in relation_store.indices.values() {
@@ -339,7 +434,13 @@ impl<'a> SessionTx<'a> {
                     &lsh_perms,
                 )?;
 
+                if let Some(log) = &mut changelog {
+                    log.append(self, CallbackOp::Put, extracted.clone(), old_tup.clone())?;
+                }
                 if need_to_collect {
+                    if let Some(tup) = old_tup {
+                        old_tuples.push(DataValue::List(tup));
+                    }
                     new_tuples.push(DataValue::List(extracted));
                 }
             }
@@ -350,6 +451,9 @@ impl<'a> SessionTx<'a> {
                 self.store_tx.put(&key, &val)?;
             }
         }
+        if let Some(log) = changelog {
+            log.finish(self)?;
+        }
 
         if need_to_collect && !new_tuples.is_empty() {
             self.collect_mutations(
@@ -574,46 +678,53 @@ impl<'a> SessionTx<'a> {
         let fts_lsh_processors = self.make_fts_lsh_processors(relation_store)?;
         let lsh_perms = self.make_lsh_hash_perms(relation_store);
 
-        for tuple in res_iter {
-            let mut new_kv: Vec<DataValue> = key_extractors
-                .iter()
-                .map(|ex| ex.extract_data(&tuple, cur_vld))
-                .try_collect()?;
-
-            let key = relation_store.encode_key_for_store(&new_kv, span)?;
-            let original_val_bytes = if relation_store.is_temp {
-                self.temp_store_tx.get(&key, true)?
-            } else {
-                self.store_tx.get(&key, true)?
-            };
-            let original_val: Tuple = match original_val_bytes {
-                None => {
-                    bail!(TransactAssertionFailure {
-                        relation: relation_store.name.to_string(),
-                        key: new_kv,
-                        notice: "key to update does not exist".to_string()
-                    })
-                }
-                Some(v) => rmp_serde::from_slice(&v[ENCODED_KEY_MIN_LEN..]).unwrap(),
-            };
-            let mut old_kv = Vec::with_capacity(relation_store.arity());
-            old_kv.extend_from_slice(&new_kv);
-            old_kv.extend_from_slice(&original_val);
-            new_kv.reserve_exact(relation_store.arity());
-            for (i, extractor) in val_extractors.iter().enumerate() {
-                match extractor {
-                    None => {
-                        new_kv.push(original_val[i].clone());
-                    }
-                    Some(ex) => {
-                        let val = ex.extract_data(&tuple, cur_vld)?;
-                        new_kv.push(val);
-                    }
-                }
+        let res_iter = if propagate_triggers
+            && !relation_store.is_temp
+            && !relation_store.before_put_triggers.is_empty()
+        {
+            let tuples = res_iter.collect_vec();
+            let mut before_new = Vec::with_capacity(tuples.len());
+            let mut before_old = Vec::with_capacity(tuples.len());
+            for tuple in &tuples {
+                let (_, new_kv, old_kv) = self.updated_kv(
+                    relation_store,
+                    &key_extractors,
+                    &val_extractors,
+                    tuple,
+                    cur_vld,
+                    span,
+                )?;
+                before_new.push(DataValue::List(new_kv));
+                before_old.push(DataValue::List(old_kv));
             }
+            self.run_before_triggers(
+                db,
+                cur_vld,
+                relation_store,
+                &relation_store.before_put_triggers,
+                relation_store.arity(),
+                before_new,
+                before_old,
+            )?;
+            Left(tuples.into_iter())
+        } else {
+            Right(res_iter)
+        };
+        let mut changelog = self.changelog_writer(relation_store, cur_vld)?;
+
+        for tuple in res_iter {
+            let (key, new_kv, old_kv) = self.updated_kv(
+                relation_store,
+                &key_extractors,
+                &val_extractors,
+                &tuple,
+                cur_vld,
+                span,
+            )?;
             let new_val = relation_store.encode_val_for_store(&new_kv, span)?;
 
             if need_to_collect
+                || changelog.is_some()
                 || has_indices
                 || has_hnsw_indices
                 || has_fts_indices
@@ -623,10 +734,6 @@ impl<'a> SessionTx<'a> {
                 self.del_in_lsh(relation_store, &old_kv)?;
                 self.update_in_index(relation_store, &new_kv, &old_kv)?;
 
-                if need_to_collect {
-                    old_tuples.push(DataValue::List(old_kv));
-                }
-
                 self.update_in_hnsw(relation_store, &mut stack, &hnsw_filters, &new_kv)?;
                 self.put_in_fts(relation_store, &mut stack, &fts_lsh_processors, &new_kv)?;
                 self.put_in_lsh(
@@ -637,7 +744,11 @@ impl<'a> SessionTx<'a> {
                     &lsh_perms,
                 )?;
 
+                if let Some(log) = &mut changelog {
+                    log.append(self, CallbackOp::Put, new_kv.clone(), Some(old_kv.clone()))?;
+                }
                 if need_to_collect {
+                    old_tuples.push(DataValue::List(old_kv));
                     new_tuples.push(DataValue::List(new_kv));
                 }
             }
@@ -648,6 +759,9 @@ impl<'a> SessionTx<'a> {
                 self.store_tx.put(&key, &new_val)?;
             }
         }
+        if let Some(log) = changelog {
+            log.finish(self)?;
+        }
 
         if need_to_collect && !new_tuples.is_empty() {
             self.collect_mutations(
@@ -666,6 +780,121 @@ impl<'a> SessionTx<'a> {
         Ok(())
     }
 
+    /// Computes the key and the new and old rows for an update.
+    fn updated_kv(
+        &self,
+        relation_store: &RelationHandle,
+        key_extractors: &[DataExtractor],
+        val_extractors: &[Option<DataExtractor>],
+        tuple: &Tuple,
+        cur_vld: ValidityTs,
+        span: SourceSpan,
+    ) -> Result<(Vec<u8>, Vec<DataValue>, Vec<DataValue>)> {
+        let mut new_kv: Vec<DataValue> = key_extractors
+            .iter()
+            .map(|ex| ex.extract_data(tuple, cur_vld))
+            .try_collect()?;
+
+        let key = relation_store.encode_key_for_store(&new_kv, span)?;
+        let original_val_bytes = if relation_store.is_temp {
+            self.temp_store_tx.get(&key, true)?
+        } else {
+            self.store_tx.get(&key, true)?
+        };
+        let original_val: Tuple = match original_val_bytes {
+            None => {
+                bail!(TransactAssertionFailure {
+                    relation: relation_store.name.to_string(),
+                    key: new_kv,
+                    notice: "key to update does not exist".to_string()
+                })
+            }
+            Some(v) => rmp_serde::from_slice(&v[ENCODED_KEY_MIN_LEN..]).unwrap(),
+        };
+        let mut old_kv = Vec::with_capacity(relation_store.arity());
+        old_kv.extend_from_slice(&new_kv);
+        old_kv.extend_from_slice(&original_val);
+        new_kv.reserve_exact(relation_store.arity());
+        for (i, extractor) in val_extractors.iter().enumerate() {
+            match extractor {
+                None => {
+                    new_kv.push(original_val[i].clone());
+                }
+                Some(ex) => {
+                    let val = ex.extract_data(tuple, cur_vld)?;
+                    new_kv.push(val);
+                }
+            }
+        }
+        Ok((key, new_kv, old_kv))
+    }
+
+    /// Runs the before triggers of a relation on the rows about to be written.
+    /// Any row returned by a trigger rejects the write.
+    /// `new_arity` is the number of leading columns of the relation present in `new_tuples`.
+    fn run_before_triggers<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        cur_vld: ValidityTs,
+        relation_store: &RelationHandle,
+        triggers: &[String],
+        new_arity: usize,
+        new_tuples: Vec<DataValue>,
+        old_tuples: Vec<DataValue>,
+    ) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Write to relation {0} rejected by trigger: {1}")]
+        #[diagnostic(code(eval::rejected_by_trigger))]
+        struct RejectedByTrigger(String, String);
+
+        let kv_bindings = relation_store
+            .metadata
+            .keys
+            .iter()
+            .chain(relation_store.metadata.non_keys.iter())
+            .map(|k| Symbol::new(k.name.clone(), Default::default()))
+            .collect_vec();
+        let new_bindings = kv_bindings[..new_arity].to_vec();
+
+        for trigger in triggers {
+            let mut program = parse_script(
+                trigger,
+                &Default::default(),
+                &db.fixed_rules.read().unwrap(),
+                cur_vld,
+            )?
+            .get_single_program()?;
+
+            make_const_rule(&mut program, "_new", new_bindings.clone(), new_tuples.clone());
+            make_const_rule(&mut program, "_old", kv_bindings.clone(), old_tuples.clone());
+
+            let (res, _) = db
+                .run_query(
+                    self,
+                    program,
+                    cur_vld,
+                    &Default::default(),
+                    &mut Default::default(),
+                    false,
+                )
+                .map_err(|err| {
+                    if err.source_code().is_some() {
+                        err
+                    } else {
+                        err.with_source_code(format!("{trigger} "))
+                    }
+                })?;
+            if let Some(row) = res.rows.into_iter().next() {
+                let msg = match row.first() {
+                    Some(DataValue::Str(s)) if row.len() == 1 => s.to_string(),
+                    _ => format!("{row:?}"),
+                };
+                bail!(RejectedByTrigger(relation_store.name.to_string(), msg))
+            }
+        }
+        Ok(())
+    }
+
     fn collect_mutations<'s, S: Storage<'s>>(
         &mut self,
         db: &Db<S>,
@@ -957,11 +1186,44 @@ impl<'a> SessionTx<'a> {
         let mut old_tuples: Vec<DataValue> = vec![];
         let mut stack = vec![];
 
-        for tuple in res_iter {
-            let extracted: Vec<DataValue> = key_extractors
+        let extract = |tuple: Tuple| -> Result<Vec<DataValue>> {
+            key_extractors
                 .iter()
                 .map(|ex| ex.extract_data(&tuple, cur_vld))
-                .try_collect()?;
+                .try_collect()
+        };
+        let rows = if propagate_triggers
+            && !relation_store.is_temp
+            && !relation_store.before_rm_triggers.is_empty()
+        {
+            let rows: Vec<Vec<DataValue>> = res_iter.map(extract).try_collect()?;
+            let mut before_old = vec![];
+            for extracted in &rows {
+                let key = relation_store.encode_key_for_store(extracted, span)?;
+                if let Some(existing) = self.store_tx.get(&key, false)? {
+                    let mut tup = extracted.clone();
+                    extend_tuple_from_v(&mut tup, &existing);
+                    before_old.push(DataValue::List(tup));
+                }
+            }
+            let before_new = rows.iter().map(|r| DataValue::List(r.clone())).collect();
+            self.run_before_triggers(
+                db,
+                cur_vld,
+                relation_store,
+                &relation_store.before_rm_triggers,
+                relation_store.metadata.keys.len(),
+                before_new,
+                before_old,
+            )?;
+            Left(rows.into_iter().map(Ok))
+        } else {
+            Right(res_iter.map(extract))
+        };
+        let mut changelog = self.changelog_writer(relation_store, cur_vld)?;
+
+        for extracted in rows {
+            let extracted = extracted?;
             let key = relation_store.encode_key_for_store(&extracted, span)?;
             if check_exists {
                 let exists = if relation_store.is_temp {
@@ -977,7 +1239,12 @@ impl<'a> SessionTx<'a> {
                     });
                 }
             }
-            if need_to_collect || has_indices || has_hnsw_indices || has_fts_indices {
+            if need_to_collect
+                || changelog.is_some()
+                || has_indices
+                || has_hnsw_indices
+                || has_fts_indices
+            {
                 if let Some(existing) = self.store_tx.get(&key, false)? {
                     let mut tup = extracted.clone();
                     extend_tuple_from_v(&mut tup, &existing);
@@ -996,6 +1263,9 @@ impl<'a> SessionTx<'a> {
                             self.hnsw_remove(relation_store, idx_handle, &extracted)?;
                         }
                     }
+                    if let Some(log) = &mut changelog {
+                        log.append(self, CallbackOp::Rm, extracted.clone(), Some(tup.clone()))?;
+                    }
                     if need_to_collect {
                         old_tuples.push(DataValue::List(tup));
                     }
@@ -1010,6 +1280,9 @@ impl<'a> SessionTx<'a> {
                 self.store_tx.del(&key)?;
             }
         }
+        if let Some(log) = changelog {
+            log.finish(self)?;
+        }
 
         // triggers and callbacks
         if need_to_collect && !new_tuples.is_empty() {
@@ -0,0 +1,405 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Bulk import of data that is new to the database.
+//!
+//! Instead of being written in a transaction, the rows and their index entries are encoded and
+//! sorted in the byte order of their keys, spilling to temporary files when they do not fit in
+//! memory, and the sorted data is handed to [Storage::batch_put]. For RocksDB, this writes SST
+//! files that are ingested into the database atomically.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use itertools::Itertools;
+use miette::{bail, miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::relation::ColumnDef;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::db::ImportIntoIndex;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel};
+use crate::{Db, NamedRows, Storage};
+
+/// Amount of encoded data held in memory before a sorted run is spilled to disk.
+const SORT_BUFFER_BYTES: usize = 256 << 20;
+/// Largest number of sorted runs read at the same time when merging.
+const MERGE_FAN_IN: usize = 64;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot bulk import into relation '{0}': {1}")]
+#[diagnostic(code(import::bulk_unsupported))]
+#[diagnostic(help("Use `import_relations` instead"))]
+struct BulkImportUnsupported(String, &'static str);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Bulk import would overwrite existing rows of relation '{0}'")]
+#[diagnostic(code(import::bulk_key_exists))]
+#[diagnostic(help("Bulk import only adds new rows, use `import_relations` to update rows"))]
+struct BulkImportKeyExists(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The data for bulk import contains the same key more than once")]
+#[diagnostic(code(import::bulk_duplicate_key))]
+struct BulkImportDuplicateKey;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot bulk import while the mutation log is enabled")]
+#[diagnostic(code(import::bulk_with_mutation_log))]
+#[diagnostic(help("Use `import_relations` instead, which is recorded in the mutation log"))]
+struct BulkImportWithMutationLog;
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Import rows into stored relations, bypassing transactions. The format of `data` is the
+    /// same as for [Db::import_relations], except that deletions are not allowed.
+    ///
+    /// This is meant for loading large amounts of data into relations that are empty, or that
+    /// are only ever appended to: it is an error for a row to have the key of an existing row,
+    /// or of another row in `data`. Indices of the relations are built as the rows are written,
+    /// but relations with HNSW, full-text search or MinHash-LSH indices, or with a changelog,
+    /// cannot be imported into this way. Triggers are not run. As the import cannot be recorded
+    /// in the mutation log, it is an error to call this while the log is enabled.
+    ///
+    /// With RocksDB, the data is written to SST files that are ingested atomically.
+    pub fn import_relations_bulk(&'s self, data: BTreeMap<String, NamedRows>) -> Result<()> {
+        let rel_names = data.keys().map(SmartString::from).collect_vec();
+        let locks = self.obtain_relation_locks(rel_names.iter());
+        let _guards = locks.iter().map(|l| l.write().unwrap()).collect_vec();
+        self.ensure_writable()?;
+        let mutation_log = self.mutation_log.read().unwrap();
+        if mutation_log.is_some() {
+            bail!(BulkImportWithMutationLog)
+        }
+
+        let cur_vld = current_validity();
+        let tx = self.transact()?;
+        let mut sorter = ExternalSorter::default();
+
+        for (relation, in_data) in data {
+            if relation.starts_with('-') {
+                bail!(BulkImportUnsupported(relation, "deletion is not supported"))
+            }
+            if relation.contains(':') {
+                bail!(ImportIntoIndex(relation))
+            }
+            let handle = tx.get_relation(&relation, false)?;
+            if handle.access_level < AccessLevel::Protected {
+                bail!(InsufficientAccessLevel(
+                    handle.name.to_string(),
+                    "data import".to_string(),
+                    handle.access_level
+                ));
+            }
+            if !handle.hnsw_indices.is_empty()
+                || !handle.fts_indices.is_empty()
+                || !handle.lsh_indices.is_empty()
+            {
+                bail!(BulkImportUnsupported(
+                    relation,
+                    "HNSW, full-text search and MinHash-LSH indices cannot be bulk loaded"
+                ))
+            }
+            if handle.changelog.is_some() {
+                bail!(BulkImportUnsupported(
+                    relation,
+                    "relations with a changelog cannot be bulk loaded"
+                ))
+            }
+
+            let lower = Tuple::default().encode_as_key(handle.id);
+            let upper = Tuple::default().encode_as_key(handle.id.next());
+            let is_empty = tx.store_tx.range_scan(&lower, &upper).next().is_none();
+
+            let header2idx: BTreeMap<_, _> = in_data
+                .headers
+                .iter()
+                .enumerate()
+                .map(|(i, k)| (k as &str, i))
+                .collect();
+            let column_indices = |cols: &'_ [ColumnDef]| -> Result<Vec<usize>> {
+                cols.iter()
+                    .map(|col| {
+                        header2idx.get(&col.name as &str).copied().ok_or_else(|| {
+                            miette!(
+                                "required header {} not found for relation {}",
+                                col.name,
+                                relation
+                            )
+                        })
+                    })
+                    .try_collect()
+            };
+            let key_indices = column_indices(&handle.metadata.keys)?;
+            let val_indices = column_indices(&handle.metadata.non_keys)?;
+
+            for row in in_data.rows {
+                let extract = |indices: &[usize], cols: &[ColumnDef]| -> Result<Vec<DataValue>> {
+                    indices
+                        .iter()
+                        .zip(cols)
+                        .map(|(i, col)| {
+                            let v = row
+                                .get(*i)
+                                .ok_or_else(|| miette!("row too short: {:?}", row))?;
+                            col.typing.coerce(v.clone(), cur_vld)
+                        })
+                        .try_collect()
+                };
+                let keys = extract(&key_indices, &handle.metadata.keys)?;
+                let vals = extract(&val_indices, &handle.metadata.non_keys)?;
+                let k_store = handle.encode_key_for_store(&keys, Default::default())?;
+                if !is_empty && tx.store_tx.exists(&k_store, false)? {
+                    bail!(BulkImportKeyExists(relation))
+                }
+                let v_store = handle.encode_val_only_for_store(&vals, Default::default())?;
+                sorter.push(k_store, v_store)?;
+                if !handle.indices.is_empty() {
+                    let mut kv = keys;
+                    kv.extend(vals);
+                    for (idx_rel, extractor) in handle.indices.values() {
+                        let idx_tup = extractor.iter().map(|i| kv[*i].clone()).collect_vec();
+                        let encoded = idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                        sorter.push(encoded, vec![])?;
+                    }
+                }
+            }
+        }
+        drop(tx);
+
+        let mut last_key: Option<Vec<u8>> = None;
+        let sorted = sorter.finish()?.map(move |res| {
+            let (k, v) = res?;
+            if last_key.as_ref() == Some(&k) {
+                bail!(BulkImportDuplicateKey)
+            }
+            last_key = Some(k.clone());
+            Ok((k, v))
+        });
+        self.db.batch_put(Box::new(sorted))
+    }
+}
+
+/// Sorts key-value pairs by key, in memory while they fit in the buffer,
+/// and otherwise by merging sorted runs spilled to temporary files.
+pub(crate) struct ExternalSorter {
+    buffer: Vec<(Vec<u8>, Vec<u8>)>,
+    buffered_bytes: usize,
+    buffer_limit: usize,
+    dir: Option<PathBuf>,
+    runs: Vec<PathBuf>,
+    runs_created: usize,
+}
+
+impl Default for ExternalSorter {
+    fn default() -> Self {
+        Self::new(SORT_BUFFER_BYTES)
+    }
+}
+
+impl ExternalSorter {
+    /// A sorter spilling to disk whenever `buffer_limit` bytes of data are buffered.
+    pub(crate) fn new(buffer_limit: usize) -> Self {
+        Self {
+            buffer: vec![],
+            buffered_bytes: 0,
+            buffer_limit,
+            dir: None,
+            runs: vec![],
+            runs_created: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        self.buffered_bytes += key.len() + val.len() + 16;
+        self.buffer.push((key, val));
+        if self.buffered_bytes >= self.buffer_limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Path for a new run file in the temporary directory, which is created if needed.
+    fn new_run_path(&mut self) -> Result<PathBuf> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => {
+                let nanos = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_nanos();
+                let dir = std::env::temp_dir().join(format!(
+                    "cozo-sort-{}-{}",
+                    std::process::id(),
+                    nanos
+                ));
+                fs::create_dir_all(&dir)
+                    .into_diagnostic()
+                    .wrap_err_with(|| {
+                        format!("when creating directory {} for sorting", dir.display())
+                    })?;
+                self.dir.insert(dir)
+            }
+        };
+        let path = dir.join(format!("{}.run", self.runs_created));
+        self.runs_created += 1;
+        Ok(path)
+    }
+
+    fn write_run(
+        path: &Path,
+        pairs: impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+    ) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path).into_diagnostic()?);
+        for pair in pairs {
+            let (k, v) = pair?;
+            for bytes in [k, v] {
+                writer
+                    .write_all(&(bytes.len() as u32).to_be_bytes())
+                    .and_then(|_| writer.write_all(&bytes))
+                    .into_diagnostic()?;
+            }
+        }
+        writer.flush().into_diagnostic()
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        let path = self.new_run_path()?;
+        self.buffer.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        Self::write_run(&path, self.buffer.drain(..).map(Ok))?;
+        self.buffered_bytes = 0;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// All pairs pushed so far, in ascending order of their keys.
+    /// Pairs with equal keys are returned in an unspecified order.
+    ///
+    /// Runs are merged into larger ones first if there are more than [MERGE_FAN_IN] of them,
+    /// so that the number of files open at the same time stays bounded.
+    pub(crate) fn finish(mut self) -> Result<SortedPairs> {
+        while self.runs.len() > MERGE_FAN_IN {
+            let inputs = self.runs.drain(..MERGE_FAN_IN).collect_vec();
+            let path = self.new_run_path()?;
+            let merged = SortedPairs::new(vec![], &inputs, None)?;
+            Self::write_run(&path, merged)?;
+            for input in inputs {
+                let _ = fs::remove_file(input);
+            }
+            self.runs.push(path);
+        }
+        self.buffer.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let in_memory = std::mem::take(&mut self.buffer);
+        let runs = std::mem::take(&mut self.runs);
+        SortedPairs::new(in_memory, &runs, self.dir.take())
+    }
+}
+
+impl Drop for ExternalSorter {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// The merge of sorted runs of an [ExternalSorter]. The directory of the runs, if given,
+/// is deleted on drop.
+pub(crate) struct SortedPairs {
+    in_memory: std::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+    runs: Vec<BufReader<File>>,
+    /// The next pair of every source that is not exhausted, with the index of the source.
+    /// The in-memory pairs have the index `runs.len()`.
+    heap: BinaryHeap<Reverse<(Vec<u8>, usize, Vec<u8>)>>,
+    dir: Option<PathBuf>,
+}
+
+impl SortedPairs {
+    fn new(
+        in_memory: Vec<(Vec<u8>, Vec<u8>)>,
+        runs: &[PathBuf],
+        dir: Option<PathBuf>,
+    ) -> Result<Self> {
+        let mut ret = SortedPairs {
+            in_memory: in_memory.into_iter(),
+            runs: vec![],
+            heap: BinaryHeap::new(),
+            dir,
+        };
+        for path in runs {
+            let reader = BufReader::new(File::open(path).into_diagnostic()?);
+            ret.runs.push(reader);
+        }
+        for i in 0..=ret.runs.len() {
+            ret.advance(i)?;
+        }
+        Ok(ret)
+    }
+
+    fn read_run(reader: &mut BufReader<File>) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let mut read_bytes = |eof_ok: bool| -> Result<Option<Vec<u8>>> {
+            let mut len = [0u8; 4];
+            match reader.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(err) if eof_ok && err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(err) => return Err(err).into_diagnostic(),
+            }
+            let mut ret = vec![0; u32::from_be_bytes(len) as usize];
+            reader.read_exact(&mut ret).into_diagnostic()?;
+            Ok(Some(ret))
+        };
+        match read_bytes(true)? {
+            None => Ok(None),
+            Some(k) => {
+                let v = read_bytes(false)?.unwrap();
+                Ok(Some((k, v)))
+            }
+        }
+    }
+
+    fn advance(&mut self, source: usize) -> Result<()> {
+        let next = if source == self.runs.len() {
+            self.in_memory.next()
+        } else {
+            Self::read_run(&mut self.runs[source])?
+        };
+        if let Some((k, v)) = next {
+            self.heap.push(Reverse((k, source, v)));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for SortedPairs {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((k, source, v)) = self.heap.pop()?;
+        if let Err(err) = self.advance(source) {
+            self.heap.clear();
+            return Some(Err(err));
+        }
+        Some(Ok((k, v)))
+    }
+}
+
+impl Drop for SortedPairs {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
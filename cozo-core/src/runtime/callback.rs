@@ -12,23 +12,46 @@ use std::fmt::{Display, Formatter};
 use crossbeam::channel::Sender;
 use smartstring::{LazyCompact, SmartString};
 
+use crate::data::relation::StoredRelationMetadata;
+use crate::data::value::DataValue;
 use crate::{Db, NamedRows, Storage};
 
 /// Represents the kind of operation that triggered the callback
+///
+/// For the schema-change operations, the rows passed to the callback
+/// carry the column names of the relation as headers and no rows,
+/// unless stated otherwise.
+///
+/// More kinds of operations may be added in the future, so matches on this enum
+/// need a wildcard arm.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum CallbackOp {
     /// Triggered by Put operations
     Put,
     /// Triggered by Rm operations
     Rm,
+    /// Triggered by the creation of the relation.
+    Create,
+    /// Triggered by `:replace`. The new and old rows carry the new and old schema,
+    /// and the new content follows as `Put` events.
+    Replace,
+    /// Triggered by `::remove`. The old rows carry the schema of the removed relation.
+    Remove,
+    /// Triggered by `::rename`, for both the old and the new name.
+    /// The new and old rows each contain a single row holding the relation name.
+    Rename,
+    /// Triggered by the creation of an index.
+    /// The new rows contain a single row holding the index name.
+    CreateIndex,
+    /// Triggered by the removal of an index.
+    /// The old rows contain a single row holding the index name.
+    RemoveIndex,
 }
 
 impl Display for CallbackOp {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            CallbackOp::Put => f.write_str("Put"),
-            CallbackOp::Rm => f.write_str("Rm"),
-        }
+        f.write_str(self.as_str())
     }
 }
 
@@ -38,6 +61,12 @@ impl CallbackOp {
         match self {
             CallbackOp::Put => "Put",
             CallbackOp::Rm => "Rm",
+            CallbackOp::Create => "Create",
+            CallbackOp::Replace => "Replace",
+            CallbackOp::Remove => "Remove",
+            CallbackOp::Rename => "Rename",
+            CallbackOp::CreateIndex => "CreateIndex",
+            CallbackOp::RemoveIndex => "RemoveIndex",
         }
     }
 }
@@ -51,6 +80,44 @@ pub struct CallbackDeclaration {
 pub(crate) type CallbackCollector =
     BTreeMap<SmartString<LazyCompact>, Vec<(CallbackOp, NamedRows, NamedRows)>>;
 
+/// Records a schema change of `relation`, if it has any callbacks registered.
+pub(crate) fn collect_schema_change(
+    callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+    callback_collector: &mut CallbackCollector,
+    relation: &str,
+    op: CallbackOp,
+    new: NamedRows,
+    old: NamedRows,
+) {
+    if callback_targets.contains(relation) {
+        callback_collector
+            .entry(SmartString::from(relation))
+            .or_default()
+            .push((op, new, old));
+    }
+}
+
+/// Column headers of a relation, without any rows.
+pub(crate) fn schema_rows(metadata: &StoredRelationMetadata) -> NamedRows {
+    NamedRows::new(
+        metadata
+            .keys
+            .iter()
+            .chain(metadata.non_keys.iter())
+            .map(|col| col.name.to_string())
+            .collect(),
+        vec![],
+    )
+}
+
+/// A single row holding a name, e.g. of a relation or an index.
+pub(crate) fn name_row(header: &str, name: &str) -> NamedRows {
+    NamedRows::new(
+        vec![header.to_string()],
+        vec![vec![DataValue::from(name)]],
+    )
+}
+
 #[allow(dead_code)]
 pub(crate) type EventCallbackRegistry = (
     BTreeMap<u32, CallbackDeclaration>,
@@ -0,0 +1,265 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Durable per-relation change logs.
+//!
+//! A changelog is a hidden relation `<rel>:changelog` keyed by a monotonically increasing
+//! sequence number. Every row put into or removed from the base relation appends an entry in
+//! the same transaction as the mutation, so consumers never miss changes and can resume
+//! from the last sequence number they have seen. Replacing the base relation keeps its
+//! changelog and appends a `Replace` entry holding the new and old column names.
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::relation::{ColType, ColumnDef, NullableColType};
+use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
+use crate::data::value::{DataValue, ValidityTs};
+use crate::runtime::callback::CallbackOp;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+/// The name of the changelog, as seen by the index namespace of the base relation.
+pub(crate) const CHANGELOG_NAME: &str = "changelog";
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct ChangelogManifest {
+    /// Number of most recent entries kept, `None` keeps everything.
+    pub(crate) retain: Option<u64>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("relation {0} has no changelog")]
+#[diagnostic(code(tx::changelog_not_found))]
+struct ChangelogNotFound(String);
+
+/// Appends entries to the changelog of a relation during a single mutation.
+pub(crate) struct ChangelogWriter<'r> {
+    log: &'r RelationHandle,
+    retain: Option<u64>,
+    start: i64,
+    next: i64,
+    ts: DataValue,
+}
+
+impl<'r> ChangelogWriter<'r> {
+    pub(crate) fn append(
+        &mut self,
+        tx: &mut SessionTx<'_>,
+        op: CallbackOp,
+        new: Vec<DataValue>,
+        old: Option<Vec<DataValue>>,
+    ) -> Result<()> {
+        let row = vec![
+            DataValue::from(self.next),
+            DataValue::from(op.as_str()),
+            self.ts.clone(),
+            DataValue::List(new),
+            old.map(DataValue::List).unwrap_or(DataValue::Null),
+        ];
+        let key = self.log.encode_key_for_store(&row, Default::default())?;
+        let val = self.log.encode_val_for_store(&row, Default::default())?;
+        tx.store_tx.put(&key, &val)?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Persists the sequence counter and drops the entries that fell out of retention.
+    pub(crate) fn finish(self, tx: &mut SessionTx<'_>) -> Result<()> {
+        if self.next == self.start {
+            return Ok(());
+        }
+        tx.store_tx
+            .put(&seq_key(self.log), &(self.next - 1).to_be_bytes())?;
+        if let Some(retain) = self.retain {
+            let retain = retain as i64;
+            for seq in (self.start - retain).max(1)..(self.next - retain) {
+                let key = self
+                    .log
+                    .encode_key_for_store(&[DataValue::from(seq)], Default::default())?;
+                tx.store_tx.del(&key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn seq_key(log: &RelationHandle) -> Vec<u8> {
+    vec![
+        DataValue::Null,
+        DataValue::from("CHANGELOG_SEQ"),
+        DataValue::from(log.id.0 as i64),
+    ]
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+fn changelog_columns() -> (Vec<ColumnDef>, Vec<ColumnDef>) {
+    let col = |name: &str, coltype: ColType, nullable: bool| ColumnDef {
+        name: SmartString::from(name),
+        typing: NullableColType { coltype, nullable },
+        default_gen: None,
+    };
+    (
+        vec![col("seq", ColType::Int, false)],
+        vec![
+            col("op", ColType::String, false),
+            col("ts", ColType::Float, false),
+            col("new", ColType::Any, true),
+            col("old", ColType::Any, true),
+        ],
+    )
+}
+
+impl<'a> SessionTx<'a> {
+    /// Returns a writer for the changelog of the relation, if it has one.
+    /// The sequence counter is read for update, so that concurrent writers are serialized.
+    pub(crate) fn changelog_writer<'r>(
+        &self,
+        relation: &'r RelationHandle,
+        cur_vld: ValidityTs,
+    ) -> Result<Option<ChangelogWriter<'r>>> {
+        let (log, manifest) = match &relation.changelog {
+            None => return Ok(None),
+            Some(found) => &**found,
+        };
+        let last = self.last_changelog_seq(log, true)?;
+        Ok(Some(ChangelogWriter {
+            log,
+            retain: manifest.retain,
+            start: last + 1,
+            next: last + 1,
+            ts: DataValue::from(cur_vld.0 .0 as f64 / 1_000_000.),
+        }))
+    }
+
+    /// The sequence number of the last entry written to the changelog of the relation.
+    pub(crate) fn changelog_position(&self, relation: &RelationHandle) -> Result<i64> {
+        match &relation.changelog {
+            None => bail!(ChangelogNotFound(relation.name.to_string())),
+            Some(found) => self.last_changelog_seq(&found.0, false),
+        }
+    }
+
+    fn last_changelog_seq(&self, log: &RelationHandle, for_update: bool) -> Result<i64> {
+        Ok(match self.store_tx.get(&seq_key(log), for_update)? {
+            None => 0,
+            Some(bytes) => i64::from_be_bytes(bytes[..8].try_into().unwrap()),
+        })
+    }
+
+    /// Enables the changelog of a relation, or changes its retention if it already exists.
+    pub(crate) fn set_changelog(&mut self, rel_name: &Symbol, retain: Option<u64>) -> Result<()> {
+        let mut rel_handle = self.get_relation(rel_name, true)?;
+        if rel_handle.is_temp {
+            bail!("Cannot keep a changelog for temp relation {}", rel_name)
+        }
+        if rel_handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                rel_handle.name.to_string(),
+                "changelog setup".to_string(),
+                rel_handle.access_level
+            ))
+        }
+        let manifest = ChangelogManifest { retain };
+        match &mut rel_handle.changelog {
+            Some(existing) => {
+                let log = &existing.0;
+                if let Some(retain) = retain {
+                    let cutoff = self.last_changelog_seq(log, true)? - retain as i64 + 1;
+                    let to_delete: Vec<_> = log
+                        .scan_bounded_prefix(
+                            self,
+                            &[],
+                            &[DataValue::from(i64::MIN)],
+                            &[DataValue::from(cutoff - 1)],
+                        )
+                        .map_ok(|tuple| tuple[..1].to_vec())
+                        .try_collect()?;
+                    for key in to_delete {
+                        let key = log.encode_key_for_store(&key, Default::default())?;
+                        self.store_tx.del(&key)?;
+                    }
+                }
+                existing.1 = manifest;
+            }
+            None => {
+                if rel_handle.has_index(CHANGELOG_NAME) {
+                    bail!(
+                        "Cannot enable changelog for {}: an index named `{}` exists",
+                        rel_name,
+                        CHANGELOG_NAME
+                    )
+                }
+                let (keys, non_keys) = changelog_columns();
+                let log =
+                    self.write_idx_relation(&rel_handle.name, CHANGELOG_NAME, keys, non_keys)?;
+                rel_handle.changelog = Some(Box::new((log, manifest)));
+            }
+        }
+        self.write_relation_handle(&rel_handle)
+    }
+
+    /// Disables the changelog of a relation, returning the ranges to clean up.
+    pub(crate) fn remove_changelog(
+        &mut self,
+        rel_name: &Symbol,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut rel_handle = self.get_relation(rel_name, true)?;
+        let (log, _) = match rel_handle.changelog.take() {
+            None => bail!(ChangelogNotFound(rel_name.to_string())),
+            Some(found) => *found,
+        };
+        self.store_tx.del(&seq_key(&log))?;
+        let to_clean = self.destroy_relation(&log.name)?;
+        self.write_relation_handle(&rel_handle)?;
+        Ok(to_clean)
+    }
+
+    /// Reads the changelog of a relation, starting from the entry with sequence number `from`.
+    pub(crate) fn read_changelog(
+        &self,
+        rel_name: &str,
+        from: i64,
+        limit: Option<usize>,
+    ) -> Result<NamedRows> {
+        let rel_handle = self.get_relation(rel_name, false)?;
+        if rel_handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                rel_handle.name.to_string(),
+                "changelog read".to_string(),
+                rel_handle.access_level
+            ))
+        }
+        let (log, _) = match &rel_handle.changelog {
+            None => bail!(ChangelogNotFound(rel_name.to_string())),
+            Some(found) => &**found,
+        };
+        let rows = log.scan_bounded_prefix(
+            self,
+            &[],
+            &[DataValue::from(from)],
+            &[DataValue::from(i64::MAX)],
+        );
+        let rows: Vec<_> = match limit {
+            None => rows.try_collect()?,
+            Some(n) => rows.take(n).try_collect()?,
+        };
+        let headers = log
+            .metadata
+            .keys
+            .iter()
+            .chain(log.metadata.non_keys.iter())
+            .map(|col| col.name.to_string())
+            .collect_vec();
+        Ok(NamedRows::new(headers, rows))
+    }
+}
@@ -13,7 +13,7 @@ use std::fmt::{Debug, Formatter};
 use std::iter;
 use std::path::Path;
 #[allow(unused_imports)]
-use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 #[allow(unused_imports)]
 use std::thread;
@@ -49,14 +49,20 @@ use crate::query::ra::{
 };
 #[allow(unused_imports)]
 use crate::runtime::callback::{
-    CallbackCollector, CallbackDeclaration, CallbackOp, EventCallbackRegistry,
+    collect_schema_change, name_row, schema_rows, CallbackCollector, CallbackDeclaration,
+    CallbackOp, EventCallbackRegistry,
 };
+use crate::runtime::changelog::CHANGELOG_NAME;
 use crate::runtime::relation::{
     extend_tuple_from_v, AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId,
 };
+use crate::runtime::temp_store::MemoryBudget;
 use crate::runtime::transact::SessionTx;
+#[cfg(any(feature = "storage-sqlite", feature = "storage-rocksdb"))]
+use crate::storage::encrypted::EncryptedStorage;
+use crate::storage::mutation_log::{replay_mutation_log, LogOp, LoggedTx, MutationLog};
 use crate::storage::temp::TempStorage;
-use crate::storage::Storage;
+use crate::storage::{Storage, StoreTx};
 use crate::{decode_tuple_from_kv, FixedRule, Symbol};
 
 pub(crate) struct RunningQueryHandle {
@@ -102,6 +108,11 @@ pub struct Db<S> {
     pub(crate) running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
     pub(crate) fixed_rules: Arc<ShardedLock<BTreeMap<String, Arc<Box<dyn FixedRule>>>>>,
     pub(crate) tokenizers: Arc<TokenizerCache>,
+    default_memory_limit: Arc<AtomicUsize>,
+    /// The largest temp store memory used by a single query since the database was opened
+    temp_store_peak: Arc<AtomicUsize>,
+    pub(crate) mutation_log: Arc<ShardedLock<Option<Arc<MutationLog>>>>,
+    read_only: Arc<AtomicBool>,
     #[cfg(not(target_arch = "wasm32"))]
     callback_count: Arc<AtomicU32>,
     #[cfg(not(target_arch = "wasm32"))]
@@ -125,6 +136,12 @@ pub(crate) struct BadDbInit(#[help] pub(crate) String);
 #[diagnostic(code(tx::import_into_index))]
 pub(crate) struct ImportIntoIndex(pub(crate) String);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database is read-only")]
+#[diagnostic(code(db::read_only))]
+#[diagnostic(help("A replica only receives data from the database it replicates"))]
+pub(crate) struct ReadOnlyDb;
+
 #[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, Clone, Default)]
 /// Rows in a relation, together with headers for the fields.
 pub struct NamedRows {
@@ -251,6 +268,10 @@ impl<'s, S: Storage<'s>> Db<S> {
             running_queries: Default::default(),
             fixed_rules: Arc::new(ShardedLock::new(DEFAULT_FIXED_RULES.clone())),
             tokenizers: Arc::new(Default::default()),
+            default_memory_limit: Default::default(),
+            temp_store_peak: Default::default(),
+            mutation_log: Default::default(),
+            read_only: Default::default(),
             #[cfg(not(target_arch = "wasm32"))]
             callback_count: Default::default(),
             // callback_receiver: Arc::new(receiver),
@@ -449,12 +470,26 @@ impl<'s, S: Storage<'s>> Db<S> {
         }
         Ok(ret)
     }
+    /// Read the changelog of a stored relation, enabled with `::changelog on <rel>`.
+    /// Entries with sequence numbers starting from `from` are returned in order,
+    /// at most `limit` of them if given.
+    /// Each entry has the columns `seq`, `op`, `ts`, `new` and `old`.
+    pub fn read_changelog(
+        &'s self,
+        relation: &str,
+        from: i64,
+        limit: Option<usize>,
+    ) -> Result<NamedRows> {
+        let tx = self.transact()?;
+        tx.read_changelog(relation, from, limit)
+    }
     /// Import relations. The argument `data` accepts data in the shape of
     /// what was returned by [Self::export_relations].
     /// The target stored relations must already exist in the database.
     /// Any associated indices will be updated.
     ///
-    /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
+    /// Note that triggers and callbacks are _not_ run for the relations, if any exists,
+    /// and no changelog entries are written.
     /// If you need to activate triggers or callbacks, use queries with parameters.
     pub fn import_relations(&'s self, data: BTreeMap<String, NamedRows>) -> Result<()> {
         #[derive(Debug, Diagnostic, Error)]
@@ -595,52 +630,180 @@ impl<'s, S: Storage<'s>> Db<S> {
         tx.commit_tx()?;
         Ok(())
     }
-    /// Backup the running database into an Sqlite file
+    /// Backup the running database into an Sqlite file.
+    ///
+    /// Backups of an [crate::EncryptedStorage] are encrypted with the same key.
     #[allow(unused_variables)]
     pub fn backup_db(&'s self, out_file: impl AsRef<Path>) -> Result<()> {
         #[cfg(feature = "storage-sqlite")]
         {
-            let sqlite_db = crate::new_cozo_sqlite(out_file)?;
-            if sqlite_db.relation_store_id.load(Ordering::SeqCst) != 0 {
-                bail!("Cannot create backup: data exists in the target database.");
+            let storage = crate::storage::sqlite::new_sqlite_backup_storage(out_file)?;
+            match self.db.cipher() {
+                None => self.copy_into_storage(storage),
+                Some(cipher) => {
+                    self.copy_into_storage(EncryptedStorage::new(storage, cipher.clone()))
+                }
             }
-            let mut tx = self.transact()?;
-            let iter = tx.store_tx.range_scan(&[], &[0xFF]);
-            sqlite_db.db.batch_put(iter)?;
-            tx.commit_tx()?;
-            Ok(())
         }
         #[cfg(not(feature = "storage-sqlite"))]
         bail!("backup requires the 'storage-sqlite' feature to be enabled")
     }
-    /// Restore from an Sqlite backup
+    /// Backup the running database into the directory `out_dir`, using the backup mechanism
+    /// of the storage engine. Only RocksDB has one, other engines return an error.
+    ///
+    /// The backup is consistent and can be taken while the database is in use. Files already in
+    /// the directory from earlier backups are reused, so repeated backups into the same directory
+    /// are incremental. The backup can be read by [Self::restore_backup] and
+    /// [Self::import_from_backup], or restored as a new database with
+    /// [crate::new_cozo_rocksdb_from_backup].
+    pub fn backup_db_native(&'s self, out_dir: impl AsRef<Path>) -> Result<()> {
+        if !self.db.native_backup(out_dir.as_ref())? {
+            bail!(
+                "native backups are not supported by this storage engine, use `backup_db` instead"
+            )
+        }
+        Ok(())
+    }
+    /// Restore from a backup made by [Self::backup_db] or [Self::backup_db_native]: either an
+    /// Sqlite file, or a RocksDB backup directory.
+    ///
+    /// To restore a RocksDB backup into a new RocksDB database, [crate::new_cozo_rocksdb_from_backup]
+    /// is much faster.
     #[allow(unused_variables)]
     pub fn restore_backup(&'s self, in_file: impl AsRef<Path>) -> Result<()> {
+        self.ensure_writable()?;
+        if in_file.as_ref().is_dir() {
+            #[cfg(feature = "storage-rocksdb")]
+            return self.with_rocksdb_backup(in_file, |storage| self.restore_from_storage(storage));
+            #[cfg(not(feature = "storage-rocksdb"))]
+            bail!("restoring a RocksDB backup requires the 'storage-rocksdb' feature to be enabled")
+        }
         #[cfg(feature = "storage-sqlite")]
         {
-            let sqlite_db = crate::new_cozo_sqlite(in_file)?;
-            let mut s_tx = sqlite_db.transact()?;
-            {
-                let mut tx = self.transact()?;
-                let store_id = tx.relation_store_id.load(Ordering::SeqCst);
-                if store_id != 0 {
-                    bail!(
-                        "Cannot restore backup: data exists in the current database. \
-                You can only restore into a new database (store id: {}).",
-                        store_id
-                    );
-                }
-                tx.commit_tx()?;
-            }
-            let iter = s_tx.store_tx.total_scan();
-            self.db.batch_put(iter)?;
-            s_tx.commit_tx()?;
-            Ok(())
+            let storage = crate::storage::sqlite::new_sqlite_backup_storage(in_file)?;
+            self.restore_from_storage(storage)
         }
         #[cfg(not(feature = "storage-sqlite"))]
         bail!("backup requires the 'storage-sqlite' feature to be enabled")
     }
-    /// Import data from relations in a backup file.
+    /// Restores the RocksDB backup in `in_dir` into a scratch database,
+    /// and passes its storage to `f`.
+    #[cfg(feature = "storage-rocksdb")]
+    fn with_rocksdb_backup(
+        &'s self,
+        in_dir: impl AsRef<Path>,
+        f: impl FnOnce(crate::storage::rocks::RocksDbStorage) -> Result<()>,
+    ) -> Result<()> {
+        let scratch = std::env::temp_dir().join(format!(
+            "cozo-restore-{}-{}",
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let res = crate::storage::rocks::restore_rocksdb_backup(in_dir, &scratch)
+            .and_then(|_| crate::storage::rocks::new_rocksdb_storage(&scratch, Default::default()))
+            .and_then(f);
+        let _ = std::fs::remove_dir_all(&scratch);
+        res
+    }
+    #[cfg(feature = "storage-sqlite")]
+    fn copy_into_storage<T>(&'s self, storage: T) -> Result<()>
+    where
+        T: for<'t> Storage<'t>,
+    {
+        let target = Db::new(storage)?;
+        target.initialize()?;
+        if target.relation_store_id.load(Ordering::SeqCst) != 0 {
+            bail!("Cannot create backup: data exists in the target database.");
+        }
+        let mut tx = self.transact()?;
+        let iter = tx.store_tx.range_scan(&[], &[0xFF]);
+        target.db.batch_put(iter)?;
+        tx.commit_tx()?;
+        Ok(())
+    }
+    /// Restores from the database in `storage`, decrypting it with the cipher of this database.
+    #[cfg(any(feature = "storage-sqlite", feature = "storage-rocksdb"))]
+    fn restore_from_storage<T>(&'s self, storage: T) -> Result<()>
+    where
+        T: for<'t> Storage<'t> + 'static,
+    {
+        match self.db.cipher() {
+            None => self.copy_from_storage(storage),
+            Some(cipher) => self.copy_from_storage(EncryptedStorage::new(storage, cipher.clone())),
+        }
+    }
+    #[cfg(any(feature = "storage-sqlite", feature = "storage-rocksdb"))]
+    fn copy_from_storage<T>(&'s self, storage: T) -> Result<()>
+    where
+        T: for<'t> Storage<'t>,
+    {
+        let source = Db::new(storage)?;
+        source.initialize()?;
+        let mut s_tx = source.transact()?;
+        {
+            let mut tx = self.transact()?;
+            let store_id = tx.relation_store_id.load(Ordering::SeqCst);
+            if store_id != 0 {
+                bail!(
+                    "Cannot restore backup: data exists in the current database. \
+                You can only restore into a new database (store id: {}).",
+                    store_id
+                );
+            }
+            tx.commit_tx()?;
+        }
+        let iter = s_tx.store_tx.total_scan();
+        self.db.batch_put(iter)?;
+        s_tx.commit_tx()?;
+        Ok(())
+    }
+    /// Archive every write transaction committed from now on into the directory `dir`, so that
+    /// together with a backup made by [Self::backup_db], the database can be brought back to
+    /// any point in time with [Self::restore_to_point_in_time].
+    ///
+    /// A new log segment is started in `dir` each time this is called. Commits are serialized
+    /// while the log is enabled, and each of them waits for its record to be synced to disk.
+    /// Data written by [Self::restore_backup] and [Self::import_from_backup] is not logged.
+    pub fn enable_mutation_log(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let log = MutationLog::open(dir, self.db.cipher().cloned())?;
+        *self.mutation_log.write().unwrap() = Some(Arc::new(log));
+        Ok(())
+    }
+    /// Restore the state of the database as of the time `ts` (seconds since the UNIX epoch),
+    /// from the backup `backup` and the mutation log in `log_dir` written after
+    /// [Self::enable_mutation_log].
+    ///
+    /// The backup must have been made before `ts`, and the log must contain every transaction
+    /// committed between the backup and `ts`. Older transactions in the log are replayed too,
+    /// which is harmless. As with [Self::restore_backup], the database must be empty.
+    /// Returns the number of transactions replayed.
+    pub fn restore_to_point_in_time(
+        &'s self,
+        backup: impl AsRef<Path>,
+        log_dir: impl AsRef<Path>,
+        ts: f64,
+    ) -> Result<usize> {
+        self.restore_backup(backup)?;
+        let until = (ts * 1_000_000.) as i64;
+        let replayed = replay_mutation_log(log_dir, until, self.db.cipher(), |ops| {
+            let mut tx = self.db.transact(true)?;
+            for op in ops {
+                match op {
+                    LogOp::Put(key, val) => tx.put(&key, &val)?,
+                    LogOp::Del(key) => tx.del(&key)?,
+                    LogOp::DelRange(lower, upper) => tx.del_range_from_persisted(&lower, &upper)?,
+                }
+            }
+            tx.commit()
+        })?;
+        self.load_last_ids()?;
+        Ok(replayed)
+    }
+    /// Import data from relations in a backup file, or a RocksDB backup directory made by
+    /// [Self::backup_db_native].
     /// The target stored relations must already exist in the database, and it must not
     /// have any associated indices. If you want to import into relations with indices,
     /// use [Db::import_relations].
@@ -653,64 +816,108 @@ impl<'s, S: Storage<'s>> Db<S> {
         in_file: impl AsRef<Path>,
         relations: &[String],
     ) -> Result<()> {
+        if in_file.as_ref().is_dir() {
+            #[cfg(feature = "storage-rocksdb")]
+            return self.with_rocksdb_backup(in_file, |storage| match self.db.cipher() {
+                None => self.import_from_storage(storage, relations),
+                Some(cipher) => self
+                    .import_from_storage(EncryptedStorage::new(storage, cipher.clone()), relations),
+            });
+            #[cfg(not(feature = "storage-rocksdb"))]
+            bail!(
+                "importing from a RocksDB backup requires the 'storage-rocksdb' feature to be enabled"
+            )
+        }
         #[cfg(not(feature = "storage-sqlite"))]
         bail!("backup requires the 'storage-sqlite' feature to be enabled");
 
         #[cfg(feature = "storage-sqlite")]
         {
-            let rel_names = relations.iter().map(SmartString::from).collect_vec();
-            let locks = self.obtain_relation_locks(rel_names.iter());
-            let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+            let storage = crate::storage::sqlite::new_sqlite_backup_storage(in_file)?;
+            match self.db.cipher() {
+                None => self.import_from_storage(storage, relations),
+                Some(cipher) => self
+                    .import_from_storage(EncryptedStorage::new(storage, cipher.clone()), relations),
+            }
+        }
+    }
+    #[cfg(any(feature = "storage-sqlite", feature = "storage-rocksdb"))]
+    fn import_from_storage<T>(&'s self, storage: T, relations: &[String]) -> Result<()>
+    where
+        T: for<'t> Storage<'t>,
+    {
+        let rel_names = relations.iter().map(SmartString::from).collect_vec();
+        let locks = self.obtain_relation_locks(rel_names.iter());
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
 
-            let source_db = crate::new_cozo_sqlite(in_file)?;
-            let mut src_tx = source_db.transact()?;
-            let mut dst_tx = self.transact_write()?;
+        let source_db = Db::new(storage)?;
+        source_db.initialize()?;
+        let mut src_tx = source_db.transact()?;
+        let mut dst_tx = self.transact_write()?;
 
-            for relation in relations {
-                if relation.contains(':') {
-                    bail!(ImportIntoIndex(relation.to_string()))
-                }
-                let src_handle = src_tx.get_relation(relation, false)?;
-                let dst_handle = dst_tx.get_relation(relation, false)?;
+        for relation in relations {
+            if relation.contains(':') {
+                bail!(ImportIntoIndex(relation.to_string()))
+            }
+            let src_handle = src_tx.get_relation(relation, false)?;
+            let dst_handle = dst_tx.get_relation(relation, false)?;
 
-                if !dst_handle.indices.is_empty() {
-                    #[derive(Debug, Error, Diagnostic)]
-                    #[error("Cannot import data into relation {0} from backup as the relation has indices")]
-                    #[diagnostic(code(tx::bare_import_with_indices))]
-                    #[diagnostic(help("Use `import_relations()` instead"))]
-                    pub(crate) struct RestoreIntoRelWithIndices(pub(crate) String);
+            if !dst_handle.indices.is_empty() {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("Cannot import data into relation {0} from backup as the relation has indices")]
+                #[diagnostic(code(tx::bare_import_with_indices))]
+                #[diagnostic(help("Use `import_relations()` instead"))]
+                pub(crate) struct RestoreIntoRelWithIndices(pub(crate) String);
 
-                    bail!(RestoreIntoRelWithIndices(dst_handle.name.to_string()))
-                }
+                bail!(RestoreIntoRelWithIndices(dst_handle.name.to_string()))
+            }
 
-                if dst_handle.access_level < AccessLevel::Protected {
-                    bail!(InsufficientAccessLevel(
-                        dst_handle.name.to_string(),
-                        "data import".to_string(),
-                        dst_handle.access_level
-                    ));
-                }
+            if dst_handle.access_level < AccessLevel::Protected {
+                bail!(InsufficientAccessLevel(
+                    dst_handle.name.to_string(),
+                    "data import".to_string(),
+                    dst_handle.access_level
+                ));
+            }
 
-                let src_lower = Tuple::default().encode_as_key(src_handle.id);
-                let src_upper = Tuple::default().encode_as_key(src_handle.id.next());
+            let src_lower = Tuple::default().encode_as_key(src_handle.id);
+            let src_upper = Tuple::default().encode_as_key(src_handle.id.next());
 
-                let data_it = src_tx.store_tx.range_scan(&src_lower, &src_upper).map(
-                    |src_pair| -> Result<(Vec<u8>, Vec<u8>)> {
-                        let (mut src_k, mut src_v) = src_pair?;
-                        dst_handle.amend_key_prefix(&mut src_k);
-                        dst_handle.amend_key_prefix(&mut src_v);
-                        Ok((src_k, src_v))
-                    },
-                );
-                for result in data_it {
-                    let (key, val) = result?;
-                    dst_tx.store_tx.put(&key, &val)?;
-                }
+            let data_it = src_tx.store_tx.range_scan(&src_lower, &src_upper).map(
+                |src_pair| -> Result<(Vec<u8>, Vec<u8>)> {
+                    let (mut src_k, mut src_v) = src_pair?;
+                    dst_handle.amend_key_prefix(&mut src_k);
+                    dst_handle.amend_key_prefix(&mut src_v);
+                    Ok((src_k, src_v))
+                },
+            );
+            for result in data_it {
+                let (key, val) = result?;
+                dst_tx.store_tx.put(&key, &val)?;
             }
+        }
 
-            src_tx.commit_tx()?;
-            dst_tx.commit_tx()
+        src_tx.commit_tx()?;
+        dst_tx.commit_tx()
+    }
+    /// Make the database read-only, or writable again. A read-only database runs every script
+    /// as immutable, and rejects write transactions, imports and restores. Only
+    /// [Self::replicate_from] can still write to it, so that it can serve as a replica.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Release);
+    }
+    pub(crate) fn ensure_writable(&self) -> Result<()> {
+        if self.read_only.load(Ordering::Acquire) {
+            bail!(ReadOnlyDb)
         }
+        Ok(())
+    }
+    /// Set the memory budget in bytes applied to the temporary stores of queries that
+    /// do not specify `:memory_limit` themselves. Queries exceeding the budget are aborted.
+    /// `None` removes the limit.
+    pub fn set_default_memory_limit(&self, limit: Option<usize>) {
+        self.default_memory_limit
+            .store(limit.unwrap_or(0), Ordering::Relaxed);
     }
     /// Register a custom fixed rule implementation.
     pub fn register_fixed_rule<R>(&self, name: String, rule_impl: R) -> Result<()>
@@ -788,27 +995,28 @@ impl<'s, S: Storage<'s>> Db<S> {
         &'s self,
         rels: T,
     ) -> Vec<Arc<ShardedLock<()>>> {
-        let mut collected = vec![];
-        let mut pending = vec![];
+        // the returned locks are in the order of `rels`, which callers rely on
+        // to acquire them in a consistent order
+        let rels = rels.collect_vec();
+        let mut collected = Vec::with_capacity(rels.len());
+        let mut has_pending = false;
         {
             let locks = self.relation_locks.read().unwrap();
-            for rel in rels {
-                match locks.get(rel) {
-                    None => {
-                        pending.push(rel);
-                    }
-                    Some(lock) => collected.push(lock.clone()),
-                }
+            for rel in &rels {
+                let lock = locks.get(*rel).cloned();
+                has_pending |= lock.is_none();
+                collected.push(lock);
             }
         }
-        if !pending.is_empty() {
+        if has_pending {
             let mut locks = self.relation_locks.write().unwrap();
-            for rel in pending {
-                let lock = locks.entry(rel.clone()).or_default().clone();
-                collected.push(lock);
+            for (rel, slot) in rels.iter().zip(collected.iter_mut()) {
+                if slot.is_none() {
+                    *slot = Some(locks.entry((*rel).clone()).or_default().clone());
+                }
             }
         }
-        collected
+        collected.into_iter().map(|lock| lock.unwrap()).collect()
     }
 
     fn compact_relation(&'s self) -> Result<()> {
@@ -819,7 +1027,7 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
 
     fn load_last_ids(&'s self) -> Result<()> {
-        let mut tx = self.transact_write()?;
+        let mut tx = self.transact_write_unchecked()?;
         self.relation_store_id
             .store(tx.init_storage()?.0, Ordering::Release);
         tx.commit_tx()?;
@@ -836,8 +1044,17 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(ret)
     }
     pub(crate) fn transact_write(&'s self) -> Result<SessionTx<'_>> {
+        self.ensure_writable()?;
+        self.transact_write_unchecked()
+    }
+    /// A write transaction even if the database is read-only, for replication.
+    pub(crate) fn transact_write_unchecked(&'s self) -> Result<SessionTx<'_>> {
+        let mut store_tx: Box<dyn StoreTx<'s> + 's> = Box::new(self.db.transact(true)?);
+        if let Some(log) = &*self.mutation_log.read().unwrap() {
+            store_tx = Box::new(LoggedTx::new(store_tx, log.clone()));
+        }
         let ret = SessionTx {
-            store_tx: Box::new(self.db.transact(true)?),
+            store_tx,
             temp_store_tx: self.temp_db.transact(true)?,
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
@@ -874,6 +1091,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         cur_vld: ValidityTs,
         read_only: bool,
     ) -> Result<NamedRows> {
+        let read_only = read_only || self.read_only.load(Ordering::Acquire);
         match parse_script(
             payload,
             param_pool,
@@ -1169,7 +1387,20 @@ impl<'s, S: Storage<'s>> Db<S> {
         tx: &mut SessionTx<'_>,
         op: &SysOp,
         read_only: bool,
+        locks_held: bool,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
     ) -> Result<NamedRows> {
+        // the exclusive locks of the op, unless the caller already holds them
+        let exclusive_locks = if locks_held {
+            vec![]
+        } else {
+            self.obtain_relation_locks(op.exclusive_lock_names())
+        };
+        let _exclusive_guards = exclusive_locks
+            .iter()
+            .map(|l| l.write().unwrap())
+            .collect_vec();
         match op {
             SysOp::Explain(prog) => {
                 let (normalized_program, _) = prog.clone().into_normalized_program(&tx)?;
@@ -1188,6 +1419,8 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::Stats(rel_name, exact) => self.stats(tx, rel_name.as_ref(), *exact),
+            SysOp::Dump(rel_names) => self.dump_relations(tx, rel_names),
             SysOp::ListRelations => self.list_relations(tx),
             SysOp::ListFixedRules => {
                 let rules = self.fixed_rules.read().unwrap();
@@ -1208,6 +1441,17 @@ impl<'s, S: Storage<'s>> Db<S> {
                 let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
                 let mut bounds = vec![];
                 for rs in rel_names {
+                    if callback_targets.contains(&rs.name) {
+                        let handle = tx.get_relation(rs, false)?;
+                        collect_schema_change(
+                            callback_targets,
+                            callback_collector,
+                            rs,
+                            CallbackOp::Remove,
+                            NamedRows::default(),
+                            schema_rows(&handle.metadata),
+                        );
+                    }
                     let bound = tx.destroy_relation(&rs)?;
                     if !rs.is_temp_store_name() {
                         bounds.extend(bound);
@@ -1232,12 +1476,15 @@ impl<'s, S: Storage<'s>> Db<S> {
                 if read_only {
                     bail!("Cannot create index in read-only mode");
                 }
-                let lock = self
-                    .obtain_relation_locks(iter::once(&rel_name.name))
-                    .pop()
-                    .unwrap();
-                let _guard = lock.write().unwrap();
                 tx.create_index(&rel_name, &idx_name, cols)?;
+                collect_schema_change(
+                    callback_targets,
+                    callback_collector,
+                    rel_name,
+                    CallbackOp::CreateIndex,
+                    name_row("index", idx_name),
+                    NamedRows::default(),
+                );
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
@@ -1247,12 +1494,15 @@ impl<'s, S: Storage<'s>> Db<S> {
                 if read_only {
                     bail!("Cannot create vector index in read-only mode");
                 }
-                let lock = self
-                    .obtain_relation_locks(iter::once(&config.base_relation))
-                    .pop()
-                    .unwrap();
-                let _guard = lock.write().unwrap();
                 tx.create_hnsw_index(config)?;
+                collect_schema_change(
+                    callback_targets,
+                    callback_collector,
+                    &config.base_relation,
+                    CallbackOp::CreateIndex,
+                    name_row("index", &config.index_name),
+                    NamedRows::default(),
+                );
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
@@ -1262,12 +1512,15 @@ impl<'s, S: Storage<'s>> Db<S> {
                 if read_only {
                     bail!("Cannot create fts index in read-only mode");
                 }
-                let lock = self
-                    .obtain_relation_locks(iter::once(&config.base_relation))
-                    .pop()
-                    .unwrap();
-                let _guard = lock.write().unwrap();
                 tx.create_fts_index(config)?;
+                collect_schema_change(
+                    callback_targets,
+                    callback_collector,
+                    &config.base_relation,
+                    CallbackOp::CreateIndex,
+                    name_row("index", &config.index_name),
+                    NamedRows::default(),
+                );
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
@@ -1277,12 +1530,15 @@ impl<'s, S: Storage<'s>> Db<S> {
                 if read_only {
                     bail!("Cannot create minhash lsh index in read-only mode");
                 }
-                let lock = self
-                    .obtain_relation_locks(iter::once(&config.base_relation))
-                    .pop()
-                    .unwrap();
-                let _guard = lock.write().unwrap();
                 tx.create_minhash_lsh_index(config)?;
+                collect_schema_change(
+                    callback_targets,
+                    callback_collector,
+                    &config.base_relation,
+                    CallbackOp::CreateIndex,
+                    name_row("index", &config.index_name),
+                    NamedRows::default(),
+                );
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
@@ -1298,6 +1554,37 @@ impl<'s, S: Storage<'s>> Db<S> {
                     .unwrap();
                 let _guard = lock.read().unwrap();
                 let bounds = tx.remove_index(&rel_name, &idx_name)?;
+                collect_schema_change(
+                    callback_targets,
+                    callback_collector,
+                    rel_name,
+                    CallbackOp::RemoveIndex,
+                    NamedRows::default(),
+                    name_row("index", idx_name),
+                );
+                for (lower, upper) in bounds {
+                    tx.store_tx.del_range_from_persisted(&lower, &upper)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::SetChangelog(rel_name, retain) => {
+                if read_only {
+                    bail!("Cannot set changelog in read-only mode");
+                }
+                tx.set_changelog(&rel_name, *retain)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::RemoveChangelog(rel_name) => {
+                if read_only {
+                    bail!("Cannot remove changelog in read-only mode");
+                }
+                let bounds = tx.remove_changelog(&rel_name)?;
                 for (lower, upper) in bounds {
                     tx.store_tx.del_range_from_persisted(&lower, &upper)?;
                 }
@@ -1317,6 +1604,16 @@ impl<'s, S: Storage<'s>> Db<S> {
                 let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
                 for (old, new) in rename_pairs {
                     tx.rename_relation(old, new)?;
+                    for rel in [old, new] {
+                        collect_schema_change(
+                            callback_targets,
+                            callback_collector,
+                            rel,
+                            CallbackOp::Rename,
+                            name_row("relation", new),
+                            name_row("relation", old),
+                        );
+                    }
                 }
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
@@ -1352,6 +1649,12 @@ impl<'s, S: Storage<'s>> Db<S> {
                 for (i, trigger) in rel.replace_triggers.iter().enumerate() {
                     rows.push(vec![json!("replace"), json!(i), json!(trigger)])
                 }
+                for (i, trigger) in rel.before_put_triggers.iter().enumerate() {
+                    rows.push(vec![json!("before put"), json!(i), json!(trigger)])
+                }
+                for (i, trigger) in rel.before_rm_triggers.iter().enumerate() {
+                    rows.push(vec![json!("before rm"), json!(i), json!(trigger)])
+                }
                 let rows = rows
                     .into_iter()
                     .map(|row| row.into_iter().map(DataValue::from).collect_vec())
@@ -1361,11 +1664,11 @@ impl<'s, S: Storage<'s>> Db<S> {
                     rows,
                 ))
             }
-            SysOp::SetTriggers(name, puts, rms, replaces) => {
+            SysOp::SetTriggers(name, puts, rms, replaces, before_puts, before_rms) => {
                 if read_only {
                     bail!("Cannot set triggers in read-only mode");
                 }
-                tx.set_relation_triggers(name, puts, rms, replaces)?;
+                tx.set_relation_triggers(name, puts, rms, replaces, before_puts, before_rms)?;
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
@@ -1391,8 +1694,25 @@ impl<'s, S: Storage<'s>> Db<S> {
         } else {
             self.transact_write()?
         };
-        let res = self.run_sys_op_with_tx(&mut tx, &op, read_only)?;
+        let callback_targets = if read_only {
+            Default::default()
+        } else {
+            self.current_callback_targets()
+        };
+        let mut callback_collector = BTreeMap::new();
+        let res = self.run_sys_op_with_tx(
+            &mut tx,
+            &op,
+            read_only,
+            false,
+            &callback_targets,
+            &mut callback_collector,
+        )?;
         tx.commit_tx()?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if !callback_collector.is_empty() {
+            self.send_callbacks(callback_collector)
+        }
         Ok(res)
     }
     /// This is the entry to query evaluation
@@ -1452,6 +1772,14 @@ impl<'s, S: Storage<'s>> Db<S> {
         if let Some(secs) = out_opts.timeout {
             poison.set_timeout(secs)?;
         }
+        // the budget bounds the memory used by temp stores
+        let memory_limit = out_opts.memory_limit.unwrap_or_else(|| {
+            match self.default_memory_limit.load(Ordering::Relaxed) {
+                0 => None,
+                n => Some(n),
+            }
+        });
+        let budget = MemoryBudget::new(memory_limit);
         // give the query an ID and store it so that it can be queried and cancelled
         let id = self.queries_count.fetch_add(1, Ordering::AcqRel);
 
@@ -1483,13 +1811,18 @@ impl<'s, S: Storage<'s>> Db<S> {
         };
 
         // the real evaluation
-        let (result_store, early_return) = tx.stratified_magic_evaluate(
+        let evaluated = tx.stratified_magic_evaluate(
             &compiled,
             store_lifetimes,
             total_num_to_take,
             num_to_skip,
             poison,
-        )?;
+            budget.clone(),
+        );
+        // failed queries count too, as those exceeding the memory limit are of most interest
+        self.temp_store_peak
+            .fetch_max(budget.peak(), Ordering::Relaxed);
+        let (result_store, early_return) = evaluated?;
 
         // deal with assertions
         if let Some(assertion) = &out_opts.assertion {
@@ -1705,6 +2038,15 @@ impl<'s, S: Storage<'s>> Db<S> {
                 }),
             ]);
         }
+        if let Some(found) = &handle.changelog {
+            let (rel, manifest) = &**found;
+            rows.push(vec![
+                json!(CHANGELOG_NAME),
+                json!("changelog"),
+                json!([rel.name]),
+                json!({ "retain": manifest.retain }),
+            ]);
+        }
         let rows = rows
             .into_iter()
             .map(|row| row.into_iter().map(DataValue::from).collect_vec())
@@ -1758,6 +2100,118 @@ impl<'s, S: Storage<'s>> Db<S> {
             rows,
         ))
     }
+    fn stats(
+        &'s self,
+        tx: &SessionTx<'_>,
+        rel_name: Option<&Symbol>,
+        exact: bool,
+    ) -> Result<NamedRows> {
+        if let Some(name) = rel_name {
+            // errors out if the relation does not exist
+            tx.get_relation(name, false)?;
+        }
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let mut rows: Vec<Vec<DataValue>> = vec![];
+        for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            let meta = RelationHandle::decode(&v_slice)?;
+            let name = meta.name;
+            if let Some(wanted) = rel_name {
+                let is_index_of_wanted = matches!(
+                    name.strip_prefix(&wanted.name as &str),
+                    Some(rest) if rest.starts_with(':')
+                );
+                if name != wanted.name && !is_index_of_wanted {
+                    continue;
+                }
+            }
+            let category = match name.split_once(':') {
+                None => "relation",
+                Some((_, CHANGELOG_NAME)) => "changelog",
+                Some(_) => "index",
+            };
+            let rel_lower = Tuple::default().encode_as_key(meta.id);
+            let rel_upper = Tuple::default().encode_as_key(meta.id.next());
+            // sizes are estimated by the engine if it can, as counting scans the whole relation
+            let estimate = if exact {
+                None
+            } else {
+                self.db.approximate_size(&rel_lower, &rel_upper)?
+            };
+            let (metrics, n_keys, n_bytes) = match estimate {
+                Some((n_keys, n_bytes)) => (
+                    ["estimated_keys", "estimated_bytes"],
+                    n_keys as usize,
+                    n_bytes as usize,
+                ),
+                None => {
+                    let mut n_keys = 0;
+                    let mut n_bytes = 0;
+                    for kv in tx.store_tx.range_scan(&rel_lower, &rel_upper) {
+                        let (k, v) = kv?;
+                        n_keys += 1;
+                        n_bytes += k.len() + v.len();
+                    }
+                    (["keys", "bytes"], n_keys, n_bytes)
+                }
+            };
+            for (metric, value) in metrics.into_iter().zip([n_keys, n_bytes]) {
+                rows.push(vec![
+                    DataValue::from(category),
+                    DataValue::from(&name as &str),
+                    DataValue::from(metric),
+                    DataValue::from(value as i64),
+                ]);
+            }
+        }
+        if rel_name.is_none() {
+            let temp_store_metrics = [
+                (
+                    "peak_bytes",
+                    self.temp_store_peak.load(Ordering::Relaxed) as i64,
+                ),
+                (
+                    "memory_limit",
+                    self.default_memory_limit.load(Ordering::Relaxed) as i64,
+                ),
+                (
+                    "running_queries",
+                    self.running_queries.lock().unwrap().len() as i64,
+                ),
+            ];
+            for (metric, value) in temp_store_metrics {
+                rows.push(vec![
+                    DataValue::from("temp_store"),
+                    DataValue::Null,
+                    DataValue::from(metric),
+                    DataValue::from(value),
+                ]);
+            }
+            let kind = self.db.storage_kind();
+            for (metric, value) in self.db.engine_stats()? {
+                rows.push(vec![
+                    DataValue::from("engine"),
+                    DataValue::from(kind),
+                    DataValue::from(metric),
+                    value,
+                ]);
+            }
+        }
+        Ok(NamedRows::new(
+            vec![
+                "category".to_string(),
+                "name".to_string(),
+                "metric".to_string(),
+                "value".to_string(),
+            ],
+            rows,
+        ))
+    }
     fn list_relations(&'s self, tx: &SessionTx<'_>) -> Result<NamedRows> {
         let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
         let upper =
@@ -0,0 +1,422 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Logical dumps of stored relations as CozoScript.
+//!
+//! `::dump` returns an imperative script made of one block per row. Run as a whole, it recreates
+//! the relations with their schemas, loads the data, and then restores indices, descriptions,
+//! changelogs, triggers and access levels, in that order, so that loading neither fires triggers
+//! nor is blocked by access levels. The script does not depend on the key layout of the storage,
+//! so it can be loaded into a database of a later version.
+
+use std::fmt::Write;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use itertools::Itertools;
+use miette::{bail, Result};
+
+use crate::data::expr::Expr;
+use crate::data::relation::{ColumnDef, VecElementType};
+use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
+use crate::data::value::{DataValue, Num, Vector, LARGEST_UTF_CHAR};
+use crate::fts::TokenizerConfig;
+use crate::parse::sys::HnswDistance;
+use crate::runtime::relation::{AccessLevel, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage};
+
+/// Number of rows put by each block of the script.
+const DUMP_BATCH_SIZE: usize = 1000;
+
+/// Write a value as a CozoScript expression evaluating to it.
+pub(crate) fn write_literal(out: &mut String, val: &DataValue) -> Result<()> {
+    match val {
+        DataValue::Null => out.push_str("null"),
+        DataValue::Bool(b) => write!(out, "{b}").unwrap(),
+        DataValue::Num(Num::Int(i)) => {
+            if *i == i64::MIN {
+                // the literal of the absolute value does not fit
+                write!(out, "({} - 1)", i + 1).unwrap()
+            } else {
+                write!(out, "{i}").unwrap()
+            }
+        }
+        DataValue::Num(Num::Float(f)) => write_float(out, *f),
+        DataValue::Str(s) => write_str(out, s),
+        DataValue::Bytes(b) => {
+            out.push_str("decode_base64(");
+            write_str(out, &STANDARD.encode(b));
+            out.push(')');
+        }
+        DataValue::Uuid(u) => {
+            out.push_str("to_uuid(");
+            write_str(out, &u.0.to_string());
+            out.push(')');
+        }
+        DataValue::List(l) => {
+            out.push('[');
+            for (i, el) in l.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_literal(out, el)?;
+            }
+            out.push(']');
+        }
+        DataValue::Vec(v) => {
+            out.push_str("vec([");
+            let els = match v {
+                Vector::F32(a) => a.iter().map(|f| *f as f64).collect_vec(),
+                Vector::F64(a) => a.to_vec(),
+            };
+            for (i, f) in els.into_iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_float(out, f);
+            }
+            out.push(']');
+            if let Vector::F64(_) = v {
+                out.push_str(", 'F64'");
+            }
+            out.push(')');
+        }
+        DataValue::Json(j) => {
+            out.push_str("parse_json(");
+            write_str(out, &j.0.to_string());
+            out.push(')');
+        }
+        DataValue::Validity(v) => write!(out, "[{}, {}]", v.timestamp.0 .0, v.is_assert.0).unwrap(),
+        DataValue::Regex(_) | DataValue::Set(_) | DataValue::Bot => {
+            bail!("value {:?} cannot be written as a literal", val)
+        }
+    }
+    Ok(())
+}
+
+fn write_float(out: &mut String, f: f64) {
+    if f.is_nan() {
+        out.push_str(r#"to_float("NAN")"#)
+    } else if f.is_infinite() {
+        if f.is_sign_negative() {
+            out.push_str(r#"to_float("NEG_INF")"#)
+        } else {
+            out.push_str(r#"to_float("INF")"#)
+        }
+    } else {
+        // the debug format always has a decimal point or an exponent
+        write!(out, "{f:?}").unwrap()
+    }
+}
+
+fn write_str(out: &mut String, s: &str) {
+    // double-quoted strings are parsed as raw strings, escapes only work in single-quoted ones
+    out.push('\'');
+    for c in s.chars() {
+        match c {
+            '\'' => out.push_str("\\'"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+}
+
+/// Write an expression in a form that parses back to it.
+fn write_expr(out: &mut String, expr: &Expr) -> Result<()> {
+    let write_call = |out: &mut String, name: &str, args: &[Expr]| -> Result<()> {
+        out.push_str(name);
+        out.push('(');
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            write_expr(out, arg)?;
+        }
+        out.push(')');
+        Ok(())
+    };
+    match expr {
+        Expr::Binding { var, .. } => out.push_str(&var.name),
+        Expr::Const { val, .. } => write_literal(out, val)?,
+        Expr::Apply { op, args, .. } => {
+            let name = op.name.strip_prefix("OP_").unwrap().to_lowercase();
+            write_call(out, &name, args)?
+        }
+        Expr::UnboundApply { op, args, .. } => write_call(out, op, args)?,
+        Expr::Cond { clauses, .. } => {
+            let args = clauses
+                .iter()
+                .flat_map(|(cond, val)| [cond.clone(), val.clone()])
+                .collect_vec();
+            write_call(out, "cond", &args)?
+        }
+    }
+    Ok(())
+}
+
+/// The columns of a relation in the syntax used by `:create`, including defaults.
+pub(crate) fn schema_spec(handle: &RelationHandle) -> Result<String> {
+    let cols = |defs: &[ColumnDef]| -> Result<String> {
+        let mut out = String::new();
+        for (i, col) in defs.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            write!(out, "{}: {}", col.name, col.typing).unwrap();
+            if let Some(default_gen) = &col.default_gen {
+                out.push_str(" default ");
+                write_expr(&mut out, default_gen)?;
+            }
+        }
+        Ok(out)
+    };
+    Ok(if handle.metadata.non_keys.is_empty() {
+        format!("{{{}}}", cols(&handle.metadata.keys)?)
+    } else {
+        format!(
+            "{{{} => {}}}",
+            cols(&handle.metadata.keys)?,
+            cols(&handle.metadata.non_keys)?
+        )
+    })
+}
+
+fn write_tokenizer(out: &mut String, config: &TokenizerConfig) -> Result<()> {
+    out.push_str(&config.name);
+    if !config.args.is_empty() {
+        out.push('(');
+        for (i, arg) in config.args.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            write_literal(out, arg)?;
+        }
+        out.push(')');
+    }
+    Ok(())
+}
+
+fn write_filters(out: &mut String, filters: &[TokenizerConfig]) -> Result<()> {
+    // an empty list is not accepted as filters
+    if filters.is_empty() {
+        return Ok(());
+    }
+    out.push_str(", filters: [");
+    for (i, filter) in filters.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        write_tokenizer(out, filter)?;
+    }
+    out.push(']');
+    Ok(())
+}
+
+/// Blocks restoring the indices of a relation.
+fn index_statements(handle: &RelationHandle) -> Result<Vec<String>> {
+    let col_names = handle
+        .metadata
+        .keys
+        .iter()
+        .chain(handle.metadata.non_keys.iter())
+        .map(|col| &col.name)
+        .collect_vec();
+    let name = &handle.name;
+    let mut ret = vec![];
+    for (idx_name, (_, cols)) in &handle.indices {
+        ret.push(format!(
+            "{{::index create {name}:{idx_name} {{{}}}}}",
+            cols.iter().map(|i| col_names[*i]).join(", ")
+        ));
+    }
+    for (idx_name, (_, manifest)) in &handle.hnsw_indices {
+        let mut out = format!(
+            "{{::hnsw create {name}:{idx_name} {{dim: {}, m: {}, ef_construction: {}, dtype: {}, distance: {}, fields: [{}], extend_candidates: {}, keep_pruned_connections: {}",
+            manifest.vec_dim,
+            manifest.m_neighbours,
+            manifest.ef_construction,
+            match manifest.dtype {
+                VecElementType::F32 => "F32",
+                VecElementType::F64 => "F64",
+            },
+            match manifest.distance {
+                HnswDistance::L2 => "L2",
+                HnswDistance::InnerProduct => "IP",
+                HnswDistance::Cosine => "Cosine",
+            },
+            manifest.vec_fields.iter().map(|i| col_names[*i]).join(", "),
+            manifest.extend_candidates,
+            manifest.keep_pruned_connections,
+        );
+        if let Some(filter) = &manifest.index_filter {
+            write!(out, ", filter: {filter}").unwrap();
+        }
+        out.push_str("}}");
+        ret.push(out);
+    }
+    for (idx_name, (_, manifest)) in &handle.fts_indices {
+        let mut out = format!(
+            "{{::fts create {name}:{idx_name} {{extractor: {}, tokenizer: ",
+            manifest.extractor
+        );
+        write_tokenizer(&mut out, &manifest.tokenizer)?;
+        write_filters(&mut out, &manifest.filters)?;
+        out.push_str("}}");
+        ret.push(out);
+    }
+    for (idx_name, (_, _, manifest)) in &handle.lsh_indices {
+        // the number of bands and rows are derived again from these
+        let mut out = format!(
+            "{{::lsh create {name}:{idx_name} {{extractor: {}, n_gram: {}, n_perm: {}, target_threshold: ",
+            manifest.extractor, manifest.n_gram, manifest.num_perm
+        );
+        write_float(&mut out, manifest.threshold);
+        out.push_str(", tokenizer: ");
+        write_tokenizer(&mut out, &manifest.tokenizer)?;
+        write_filters(&mut out, &manifest.filters)?;
+        out.push_str("}}");
+        ret.push(out);
+    }
+    Ok(ret)
+}
+
+fn trigger_statement(handle: &RelationHandle) -> Option<String> {
+    let clauses = [
+        ("put", &handle.put_triggers),
+        ("rm", &handle.rm_triggers),
+        ("replace", &handle.replace_triggers),
+        ("before put", &handle.before_put_triggers),
+        ("before rm", &handle.before_rm_triggers),
+    ];
+    if clauses.iter().all(|(_, triggers)| triggers.is_empty()) {
+        return None;
+    }
+    let mut out = format!("{{::set_triggers {}", handle.name);
+    for (event, triggers) in clauses {
+        for trigger in triggers {
+            write!(out, " on {event} {{{trigger}}}").unwrap();
+        }
+    }
+    out.push('}');
+    Some(out)
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Run `::dump`: script blocks recreating the named stored relations, or all of them.
+    pub(crate) fn dump_relations(
+        &'s self,
+        tx: &SessionTx<'_>,
+        rel_names: &[Symbol],
+    ) -> Result<NamedRows> {
+        let handles = if rel_names.is_empty() {
+            let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+            let upper = vec![DataValue::from(String::from(LARGEST_UTF_CHAR))]
+                .encode_as_key(RelationId::SYSTEM);
+            let mut handles = vec![];
+            for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+                let (k_slice, v_slice) = kv_res?;
+                if upper <= k_slice {
+                    break;
+                }
+                let handle = RelationHandle::decode(&v_slice)?;
+                // indices and changelogs are restored with their relations
+                if !handle.name.contains(':') {
+                    handles.push(handle);
+                }
+            }
+            handles
+        } else {
+            rel_names
+                .iter()
+                .map(|name| tx.get_relation(name, false))
+                .try_collect()?
+        };
+
+        let mut statements = vec![];
+        for handle in &handles {
+            let schema = schema_spec(handle)?;
+            statements.push(format!("{{:create {} {}}}", handle.name, schema));
+
+            let keys = handle.metadata.keys.iter().map(|col| &col.name).join(", ");
+            let non_keys = handle
+                .metadata
+                .non_keys
+                .iter()
+                .map(|col| &col.name)
+                .join(", ");
+            let all_cols = handle
+                .metadata
+                .keys
+                .iter()
+                .chain(handle.metadata.non_keys.iter())
+                .map(|col| &col.name)
+                .join(", ");
+            let put_spec = if non_keys.is_empty() {
+                keys
+            } else {
+                format!("{keys} => {non_keys}")
+            };
+            for chunk in &handle.scan_all(tx).chunks(DUMP_BATCH_SIZE) {
+                let mut out = format!("{{?[{all_cols}] <- [");
+                for (i, tuple) in chunk.enumerate() {
+                    if i > 0 {
+                        out.push_str(",\n");
+                    }
+                    write_literal(&mut out, &DataValue::List(tuple?))?;
+                }
+                write!(out, "] :put {} {{{put_spec}}}}}", handle.name).unwrap();
+                statements.push(out);
+            }
+        }
+        for handle in &handles {
+            statements.extend(index_statements(handle)?);
+        }
+        for handle in &handles {
+            if !handle.description.is_empty() {
+                let mut out = format!("{{::describe {} ", handle.name);
+                write_str(&mut out, &handle.description);
+                out.push('}');
+                statements.push(out);
+            }
+            if let Some(found) = &handle.changelog {
+                let (_, manifest) = &**found;
+                let mut out = format!("{{::changelog on {}", handle.name);
+                if let Some(retain) = manifest.retain {
+                    write!(out, " retain {retain}").unwrap();
+                }
+                out.push('}');
+                statements.push(out);
+            }
+        }
+        for handle in &handles {
+            statements.extend(trigger_statement(handle));
+        }
+        for handle in &handles {
+            if handle.access_level != AccessLevel::Normal {
+                statements.push(format!(
+                    "{{::access_level {} {}}}",
+                    handle.access_level, handle.name
+                ));
+            }
+        }
+        Ok(NamedRows::new(
+            vec!["statement".to_string()],
+            statements
+                .into_iter()
+                .map(|s| vec![DataValue::from(s)])
+                .collect_vec(),
+        ))
+    }
+}
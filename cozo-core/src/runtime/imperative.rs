@@ -120,7 +120,14 @@ impl<'s, S: Storage<'s>> Db<S> {
                     ret = NamedRows::default();
                 }
                 ImperativeStmt::SysOp { sysop, .. } => {
-                    ret = self.run_sys_op_with_tx(tx, &sysop.sysop, readonly)?;
+                    ret = self.run_sys_op_with_tx(
+                        tx,
+                        &sysop.sysop,
+                        readonly,
+                        true,
+                        callback_targets,
+                        callback_collector,
+                    )?;
                     if let Some(store_as) = &sysop.store_as {
                         tx.script_store_as_relation(self, store_as, &ret, cur_vld)?;
                     }
@@ -263,12 +270,27 @@ impl<'s, S: Storage<'s>> Db<S> {
         if readonly && !write_lock_names.is_empty() {
             bail!("Read-only imperative program attempted to acquire write locks");
         }
+        let mut exclusive_lock_names = BTreeSet::new();
         for p in ps {
             p.needs_write_locks(&mut write_lock_names);
+            p.needs_exclusive_locks(&mut exclusive_lock_names);
         }
-        let is_write = !write_lock_names.is_empty();
-        let write_lock = self.obtain_relation_locks(write_lock_names.iter());
-        let _write_lock_guards = write_lock.iter().map(|l| l.read().unwrap()).collect_vec();
+        let is_write = !write_lock_names.is_empty() || !exclusive_lock_names.is_empty();
+        // all locks are taken in a single pass in name order, shared or exclusive,
+        // so that concurrent scripts cannot deadlock each other
+        let lock_names: BTreeSet<_> = write_lock_names.union(&exclusive_lock_names).collect();
+        let locks = self.obtain_relation_locks(lock_names.iter().copied());
+        let _lock_guards = lock_names
+            .iter()
+            .zip(locks.iter())
+            .map(|(name, l)| {
+                if exclusive_lock_names.contains(*name) {
+                    Right(l.write().unwrap())
+                } else {
+                    Left(l.read().unwrap())
+                }
+            })
+            .collect_vec();
 
         let callback_targets = if is_write {
             self.current_callback_targets()
@@ -6,10 +6,14 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub(crate) mod bulk_import;
 pub(crate) mod callback;
+pub(crate) mod changelog;
 pub(crate) mod db;
+pub(crate) mod dump;
 pub(crate) mod imperative;
 pub(crate) mod relation;
+pub(crate) mod replication;
 pub(crate) mod temp_store;
 pub(crate) mod transact;
 pub(crate) mod hnsw;
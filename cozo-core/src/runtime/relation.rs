@@ -29,6 +29,7 @@ use crate::parse::expr::build_expr;
 use crate::parse::sys::{FtsIndexConfig, HnswIndexConfig, MinHashLshConfig};
 use crate::parse::{CozoScriptParser, Rule, SourceSpan};
 use crate::query::compile::IndexPositionUse;
+use crate::runtime::changelog::{ChangelogManifest, CHANGELOG_NAME};
 use crate::runtime::hnsw::HnswIndexManifest;
 use crate::runtime::minhash_lsh::{HashPermutations, LshParams, MinHashLshIndexManifest, Weights};
 use crate::runtime::transact::SessionTx;
@@ -90,6 +91,13 @@ pub(crate) struct RelationHandle {
         (RelationHandle, RelationHandle, MinHashLshIndexManifest),
     >,
     pub(crate) description: SmartString<LazyCompact>,
+    #[serde(default)]
+    pub(crate) changelog: Option<Box<(RelationHandle, ChangelogManifest)>>,
+    // fields added later must stay at the end, as old handles may be encoded as arrays
+    #[serde(default)]
+    pub(crate) before_put_triggers: Vec<String>,
+    #[serde(default)]
+    pub(crate) before_rm_triggers: Vec<String>,
 }
 
 impl RelationHandle {
@@ -98,12 +106,14 @@ impl RelationHandle {
             || self.hnsw_indices.contains_key(index_name)
             || self.fts_indices.contains_key(index_name)
             || self.lsh_indices.contains_key(index_name)
+            || (index_name == CHANGELOG_NAME && self.changelog.is_some())
     }
     pub(crate) fn has_no_index(&self) -> bool {
         self.indices.is_empty()
             && self.hnsw_indices.is_empty()
             && self.fts_indices.is_empty()
             && self.lsh_indices.is_empty()
+            && self.changelog.is_none()
     }
 }
 
@@ -164,7 +174,10 @@ impl RelationHandle {
         ret
     }
     pub(crate) fn has_triggers(&self) -> bool {
-        !self.put_triggers.is_empty() || !self.rm_triggers.is_empty()
+        !self.put_triggers.is_empty()
+            || !self.rm_triggers.is_empty()
+            || !self.before_put_triggers.is_empty()
+            || !self.before_rm_triggers.is_empty()
     }
     fn encode_key_prefix(&self, len: usize) -> Vec<u8> {
         let mut ret = Vec::with_capacity(4 + 4 * len + 10 * len);
@@ -556,6 +569,8 @@ impl<'a> SessionTx<'a> {
         puts: &[String],
         rms: &[String],
         replaces: &[String],
+        before_puts: &[String],
+        before_rms: &[String],
     ) -> Result<()> {
         if name.name.starts_with('_') {
             bail!("Cannot set triggers for temp store")
@@ -571,17 +586,10 @@ impl<'a> SessionTx<'a> {
         original.put_triggers = puts.to_vec();
         original.rm_triggers = rms.to_vec();
         original.replace_triggers = replaces.to_vec();
+        original.before_put_triggers = before_puts.to_vec();
+        original.before_rm_triggers = before_rms.to_vec();
 
-        let name_key =
-            vec![DataValue::Str(original.name.clone())].encode_as_key(RelationId::SYSTEM);
-
-        let mut meta_val = vec![];
-        original
-            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
-            .unwrap();
-        self.store_tx.put(&name_key, &meta_val)?;
-
-        Ok(())
+        self.write_relation_handle(&original)
     }
     pub(crate) fn create_relation(
         &mut self,
@@ -620,6 +628,9 @@ impl<'a> SessionTx<'a> {
             fts_indices: Default::default(),
             lsh_indices: Default::default(),
             description: Default::default(),
+            changelog: None,
+            before_put_triggers: vec![],
+            before_rm_triggers: vec![],
         };
 
         let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
@@ -666,6 +677,10 @@ impl<'a> SessionTx<'a> {
         let mut meta = self.get_relation(name, true)?;
 
         meta.description = SmartString::from(description);
+        self.write_relation_handle(&meta)
+    }
+    /// Persists the metadata of a relation, replacing what was stored under its name.
+    pub(crate) fn write_relation_handle(&mut self, meta: &RelationHandle) -> Result<()> {
         let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
         let mut meta_val = vec![];
         meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
@@ -688,7 +703,7 @@ impl<'a> SessionTx<'a> {
         let store = self.get_relation(name, true)?;
         if !store.has_no_index() {
             bail!(
-                "Cannot remove stored relation `{}` with indices attached.",
+                "Cannot remove stored relation `{}` with indices or changelog attached.",
                 name
             );
         }
@@ -725,15 +740,7 @@ impl<'a> SessionTx<'a> {
     pub(crate) fn set_access_level(&mut self, rel: &Symbol, level: AccessLevel) -> Result<()> {
         let mut meta = self.get_relation(&rel, true)?;
         meta.access_level = level;
-
-        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
-
-        let mut meta_val = vec![];
-        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
-            .unwrap();
-        self.store_tx.put(&name_key, &meta_val)?;
-
-        Ok(())
+        self.write_relation_handle(&meta)
     }
 
     pub(crate) fn create_minhash_lsh_index(&mut self, config: &MinHashLshConfig) -> Result<()> {
@@ -859,7 +866,7 @@ impl<'a> SessionTx<'a> {
             vec![DataValue::from(&rel_handle.name as &str)].encode_as_key(RelationId::SYSTEM);
         let mut meta_val = vec![];
         rel_handle
-            .serialize(&mut Serializer::new(&mut meta_val))
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
             .unwrap();
         self.store_tx.put(&new_encoded, &meta_val)?;
 
@@ -1000,7 +1007,7 @@ impl<'a> SessionTx<'a> {
             vec![DataValue::from(&rel_handle.name as &str)].encode_as_key(RelationId::SYSTEM);
         let mut meta_val = vec![];
         rel_handle
-            .serialize(&mut Serializer::new(&mut meta_val))
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
             .unwrap();
         self.store_tx.put(&new_encoded, &meta_val)?;
 
@@ -1193,14 +1200,14 @@ impl<'a> SessionTx<'a> {
             vec![DataValue::from(&config.base_relation as &str)].encode_as_key(RelationId::SYSTEM);
         let mut meta_val = vec![];
         rel_handle
-            .serialize(&mut Serializer::new(&mut meta_val))
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
             .unwrap();
         self.store_tx.put(&new_encoded, &meta_val)?;
 
         Ok(())
     }
 
-    fn write_idx_relation(
+    pub(crate) fn write_idx_relation(
         &mut self,
         base_name: &str,
         idx_name: &str,
@@ -1360,7 +1367,7 @@ impl<'a> SessionTx<'a> {
             vec![DataValue::from(&rel_name.name as &str)].encode_as_key(RelationId::SYSTEM);
         let mut meta_val = vec![];
         rel_handle
-            .serialize(&mut Serializer::new(&mut meta_val))
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
             .unwrap();
         self.store_tx.put(&new_encoded, &meta_val)?;
 
@@ -1402,7 +1409,8 @@ impl<'a> SessionTx<'a> {
         let new_encoded =
             vec![DataValue::from(&rel_name.name as &str)].encode_as_key(RelationId::SYSTEM);
         let mut meta_val = vec![];
-        rel.serialize(&mut Serializer::new(&mut meta_val)).unwrap();
+        rel.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
         self.store_tx.put(&new_encoded, &meta_val)?;
 
         Ok(to_clean)
@@ -1433,7 +1441,8 @@ impl<'a> SessionTx<'a> {
         rel.name = new.name.clone();
 
         let mut meta_val = vec![];
-        rel.serialize(&mut Serializer::new(&mut meta_val)).unwrap();
+        rel.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
         self.store_tx.del(&old_encoded)?;
         self.store_tx.put(&new_encoded, &meta_val)?;
 
@@ -1454,7 +1463,8 @@ impl<'a> SessionTx<'a> {
         rel.name = new.name;
 
         let mut meta_val = vec![];
-        rel.serialize(&mut Serializer::new(&mut meta_val)).unwrap();
+        rel.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
         self.temp_store_tx.del(&old_encoded)?;
         self.temp_store_tx.put(&new_encoded, &meta_val)?;
 
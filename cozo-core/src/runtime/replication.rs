@@ -0,0 +1,279 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Logical replication built on relation changelogs.
+//!
+//! A replica pulls from a [ReplicationSource]: every relation of the source that has a changelog
+//! is first copied from a consistent snapshot, then kept up to date by applying the changelog
+//! entries in order. The position of the replica in each changelog is stored in the same
+//! transaction as the applied entries, so replication can be resumed after a crash.
+
+use std::collections::BTreeMap;
+use std::iter;
+
+use itertools::Itertools;
+use miette::{bail, Result};
+use smartstring::SmartString;
+
+use crate::data::functions::current_validity;
+use crate::data::tuple::TupleT;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::parse::parse_script;
+use crate::runtime::dump::schema_spec;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{Db, DbInstance, NamedRows, Storage};
+
+/// Number of changelog entries pulled from the source at a time.
+const REPLICATION_BATCH_SIZE: usize = 1024;
+
+/// A consistent copy of a relation, together with its position in the changelog.
+#[derive(Debug, Clone)]
+pub struct RelationSnapshot {
+    /// The columns of the relation, in the syntax used by `:create`.
+    pub schema: String,
+    /// All rows of the relation.
+    pub rows: NamedRows,
+    /// Sequence number of the last changelog entry reflected in `rows`.
+    pub seq: i64,
+}
+
+/// Where a replica pulls committed mutations from.
+pub trait ReplicationSource {
+    /// Names of the stored relations that have a changelog, which are the ones replicated.
+    fn changelog_relations(&self) -> Result<Vec<String>>;
+    /// A consistent snapshot of a relation.
+    fn snapshot(&self, relation: &str) -> Result<RelationSnapshot>;
+    /// Changelog entries of a relation, starting from the sequence number `from`.
+    /// See [crate::Db::read_changelog].
+    fn read_changelog(&self, relation: &str, from: i64, limit: Option<usize>) -> Result<NamedRows>;
+}
+
+impl ReplicationSource for DbInstance {
+    fn changelog_relations(&self) -> Result<Vec<String>> {
+        DbInstance::changelog_relations(self)
+    }
+
+    fn snapshot(&self, relation: &str) -> Result<RelationSnapshot> {
+        self.snapshot_relation(relation)
+    }
+
+    fn read_changelog(&self, relation: &str, from: i64, limit: Option<usize>) -> Result<NamedRows> {
+        DbInstance::read_changelog(self, relation, from, limit)
+    }
+}
+
+fn replica_seq_key(relation: &str) -> Vec<u8> {
+    vec![
+        DataValue::Null,
+        DataValue::from("REPLICA_SEQ"),
+        DataValue::from(relation),
+    ]
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Names of the stored relations that have a changelog.
+    pub fn changelog_relations(&'s self) -> Result<Vec<String>> {
+        let tx = self.transact()?;
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let mut ret = vec![];
+        for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+            let (_, v_slice) = kv_res?;
+            let handle = RelationHandle::decode(&v_slice)?;
+            if handle.changelog.is_some() {
+                ret.push(handle.name.to_string());
+            }
+        }
+        Ok(ret)
+    }
+    /// Take a consistent snapshot of a relation that has a changelog.
+    pub fn snapshot_relation(&'s self, relation: &str) -> Result<RelationSnapshot> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(relation, false)?;
+        if handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "snapshot".to_string(),
+                handle.access_level
+            ))
+        }
+        let seq = tx.changelog_position(&handle)?;
+        Ok(RelationSnapshot {
+            schema: schema_spec(&handle)?,
+            rows: handle.as_named_rows(&tx)?,
+            seq,
+        })
+    }
+    /// The sequence number of the last changelog entry of `relation` applied
+    /// by [Self::replicate_from], or `None` if the relation is not replicated yet.
+    pub fn replica_position(&'s self, relation: &str) -> Result<Option<i64>> {
+        let tx = self.transact()?;
+        Ok(tx
+            .store_tx
+            .get(&replica_seq_key(relation), false)?
+            .map(|bytes| i64::from_be_bytes(bytes[..8].try_into().unwrap())))
+    }
+    /// Pull committed mutations from `source` and apply them, returning the number of
+    /// snapshot rows and changelog entries applied.
+    ///
+    /// Relations are copied from a snapshot the first time they are seen, or when the
+    /// changelog entries needed have already been dropped by retention on the source,
+    /// in which case the local relation is replaced.
+    /// Call this repeatedly to keep the database as a follower of the source.
+    /// Writing to the replicated relations by other means breaks the replica, which can be
+    /// prevented with [Self::set_read_only], as replication still writes to a read-only database.
+    pub fn replicate_from(&'s self, source: &dyn ReplicationSource) -> Result<usize> {
+        let mut applied = 0;
+        for relation in source.changelog_relations()? {
+            applied += self.replicate_relation(source, &relation)?;
+        }
+        Ok(applied)
+    }
+    fn replicate_relation(
+        &'s self,
+        source: &dyn ReplicationSource,
+        relation: &str,
+    ) -> Result<usize> {
+        let mut applied = 0;
+        let mut pos = self.replica_position(relation)?;
+        loop {
+            let from = match pos {
+                None => {
+                    let snapshot = source.snapshot(relation)?;
+                    applied += snapshot.rows.rows.len();
+                    pos = Some(snapshot.seq);
+                    self.apply_snapshot(relation, snapshot)?;
+                    continue;
+                }
+                Some(seq) => seq + 1,
+            };
+            let entries = source.read_changelog(relation, from, Some(REPLICATION_BATCH_SIZE))?;
+            match entries.rows.first() {
+                None => return Ok(applied),
+                Some(entry) if entry.first().and_then(|v| v.get_int()) != Some(from) => {
+                    // entries were dropped by retention before we could apply them
+                    pos = None;
+                    continue;
+                }
+                Some(_) => {}
+            }
+            // the relation may have a new schema after a replacement, so it is copied anew
+            let mut entries = entries;
+            match entries
+                .rows
+                .iter()
+                .position(|entry| entry.get(1).and_then(|v| v.get_str()) == Some("Replace"))
+            {
+                Some(0) => {
+                    pos = None;
+                    continue;
+                }
+                Some(idx) => entries.rows.truncate(idx),
+                None => {}
+            }
+            applied += entries.rows.len();
+            pos = Some(self.apply_changelog_entries(relation, entries)?);
+        }
+    }
+    fn apply_snapshot(&'s self, relation: &str, snapshot: RelationSnapshot) -> Result<()> {
+        let script = format!(
+            "?[{}] <- $rows :replace {} {}",
+            snapshot.rows.headers.join(", "),
+            relation,
+            snapshot.schema
+        );
+        let rows = snapshot
+            .rows
+            .rows
+            .into_iter()
+            .map(DataValue::List)
+            .collect_vec();
+        self.run_replication_tx(relation, vec![(script, rows)], snapshot.seq)
+    }
+    fn apply_changelog_entries(&'s self, relation: &str, entries: NamedRows) -> Result<i64> {
+        let handle = self.transact()?.get_relation(relation, false)?;
+        let keys = handle.metadata.keys.iter().map(|c| &c.name).join(", ");
+        let vals = handle.metadata.non_keys.iter().map(|c| &c.name).join(", ");
+        let put_script = if vals.is_empty() {
+            format!("?[{keys}] <- $rows :put {relation} {{{keys}}}")
+        } else {
+            format!("?[{keys}, {vals}] <- $rows :put {relation} {{{keys} => {vals}}}")
+        };
+        let rm_script = format!("?[{keys}] <- $rows :rm {relation} {{{keys}}}");
+
+        let mut last_seq = 0;
+        let mut scripts: Vec<(String, Vec<DataValue>)> = vec![];
+        for entry in entries.rows {
+            let seq = entry.first().and_then(|v| v.get_int());
+            let op = entry.get(1).and_then(|v| v.get_str());
+            let (seq, op, row) = match (seq, op, entry.get(3)) {
+                (Some(seq), Some(op), Some(row)) => (seq, op, row.clone()),
+                _ => bail!("malformed changelog entry {:?}", entry),
+            };
+            last_seq = seq;
+            let script = match op {
+                "Put" => &put_script,
+                "Rm" => &rm_script,
+                _ => bail!("unknown changelog operation {}", op),
+            };
+            match scripts.last_mut() {
+                Some((last_script, rows)) if last_script == script => rows.push(row),
+                _ => scripts.push((script.clone(), vec![row])),
+            }
+        }
+        self.run_replication_tx(relation, scripts, last_seq)?;
+        Ok(last_seq)
+    }
+    /// Runs the scripts with their `$rows` and records the replica position, all in one transaction.
+    fn run_replication_tx(
+        &'s self,
+        relation: &str,
+        scripts: Vec<(String, Vec<DataValue>)>,
+        seq: i64,
+    ) -> Result<()> {
+        let cur_vld = current_validity();
+        let rel_name = SmartString::from(relation);
+        let lock = self
+            .obtain_relation_locks(iter::once(&rel_name))
+            .pop()
+            .unwrap();
+        let _guard = lock.read().unwrap();
+        let callback_targets = self.current_callback_targets();
+        let mut callback_collector = BTreeMap::new();
+        let mut cleanups = vec![];
+        let mut tx: SessionTx<'_> = self.transact_write_unchecked()?;
+        for (script, rows) in scripts {
+            let params = BTreeMap::from([("rows".to_string(), DataValue::List(rows))]);
+            let program =
+                parse_script(&script, &params, &self.fixed_rules.read().unwrap(), cur_vld)?
+                    .get_single_program()?;
+            self.execute_single_program(
+                program,
+                &mut tx,
+                &mut cleanups,
+                cur_vld,
+                &callback_targets,
+                &mut callback_collector,
+            )?;
+        }
+        tx.store_tx
+            .put(&replica_seq_key(relation), &seq.to_be_bytes())?;
+        for (lower, upper) in cleanups {
+            tx.store_tx.del_range_from_persisted(&lower, &upper)?;
+        }
+        tx.commit_tx()?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if !callback_collector.is_empty() {
+            self.send_callbacks(callback_collector)
+        }
+        Ok(())
+    }
+}
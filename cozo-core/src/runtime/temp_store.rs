@@ -11,21 +11,114 @@ use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::collections::Bound::Included;
 use std::mem;
+use std::mem::size_of;
 use std::ops::Bound::Excluded;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Arc;
 
 use either::{Left, Right};
 use itertools::Itertools;
-use miette::Result;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
 
 use crate::data::aggr::Aggregation;
 use crate::data::tuple::Tuple;
-use crate::data::value::DataValue;
+use crate::data::value::{DataValue, Vector};
+
+/// Bookkeeping overhead of a single entry in a B-tree backed temp store.
+const ENTRY_OVERHEAD: usize = 4 * size_of::<usize>();
+
+/// Rough estimate of the memory taken by a tuple stored in a temp store.
+pub(crate) fn approx_tuple_size(tuple: &[DataValue]) -> usize {
+    ENTRY_OVERHEAD + size_of::<Tuple>() + tuple.iter().map(approx_value_size).sum::<usize>()
+}
+
+fn approx_value_size(val: &DataValue) -> usize {
+    let heap = match val {
+        DataValue::Str(s) => {
+            if s.is_inline() {
+                0
+            } else {
+                s.len()
+            }
+        }
+        DataValue::Bytes(b) => b.len(),
+        DataValue::List(l) => l.iter().map(approx_value_size).sum(),
+        DataValue::Set(s) => s
+            .iter()
+            .map(|v| approx_value_size(v) + ENTRY_OVERHEAD)
+            .sum(),
+        DataValue::Vec(Vector::F32(a)) => a.len() * size_of::<f32>(),
+        DataValue::Vec(Vector::F64(a)) => a.len() * size_of::<f64>(),
+        DataValue::Json(j) => j.0.to_string().len(),
+        _ => 0,
+    };
+    size_of::<DataValue>() + heap
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Query exceeded its memory budget of {0} bytes")]
+#[diagnostic(code(eval::memory_limit_exceeded))]
+#[diagnostic(help(
+    "Rewrite the query to produce fewer intermediate tuples, or raise the limit with `:memory_limit`"
+))]
+pub(crate) struct MemoryLimitExceeded(pub(crate) usize);
+
+/// Approximate accounting of the memory held by the temp stores of a running query.
+/// Cloned handles share the same counters.
+#[derive(Clone, Default, Debug)]
+pub(crate) struct MemoryBudget {
+    limit: Option<usize>,
+    used: Arc<AtomicUsize>,
+    /// The high-water mark of `used` before its last reset
+    peak: Arc<AtomicUsize>,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            used: Default::default(),
+            peak: Default::default(),
+        }
+    }
+    /// Account for `bytes` more memory, returning `Err` if the limit is exceeded.
+    /// Without a limit this does nothing, and usage is only known from [Self::reset].
+    #[inline(always)]
+    pub(crate) fn charge(&self, bytes: usize) -> Result<()> {
+        if let Some(limit) = self.limit {
+            let used = self.used.fetch_add(bytes, AtomicOrdering::Relaxed) + bytes;
+            if used > limit {
+                bail!(MemoryLimitExceeded(limit))
+            }
+        }
+        Ok(())
+    }
+    /// Reset the accounted memory to `bytes`, e.g. after stores have been merged or dropped.
+    pub(crate) fn reset(&self, bytes: usize) -> Result<()> {
+        let prev = self.used.swap(bytes, AtomicOrdering::Relaxed);
+        self.peak.fetch_max(prev, AtomicOrdering::Relaxed);
+        if let Some(limit) = self.limit {
+            if bytes > limit {
+                bail!(MemoryLimitExceeded(limit))
+            }
+        }
+        Ok(())
+    }
+    /// The most memory accounted at any point so far. Without a limit, this is the largest
+    /// amount passed to [Self::reset].
+    pub(crate) fn peak(&self) -> usize {
+        let used = self.used.load(AtomicOrdering::Relaxed);
+        self.peak.load(AtomicOrdering::Relaxed).max(used)
+    }
+}
 
 /// A store holding temp data during evaluation of queries.
 /// The public interface is used in custom implementations of algorithms/utilities.
 #[derive(Default, Debug)]
 pub struct RegularTempStore {
     inner: BTreeMap<Tuple, bool>,
+    size: usize,
 }
 
 const EMPTY_TUPLE_REF: &Tuple = &vec![];
@@ -57,15 +150,36 @@ impl RegularTempStore {
     }
     /// Add a tuple to the store
     pub fn put(&mut self, tuple: Tuple) {
-        self.inner.insert(tuple, false);
+        let _ = self.insert(tuple, false);
+    }
+    /// Add a tuple to the store, charging any newly used memory to `budget`.
+    pub(crate) fn put_charged(
+        &mut self,
+        tuple: Tuple,
+        skip: bool,
+        budget: &MemoryBudget,
+    ) -> Result<()> {
+        let added = self.insert(tuple, skip);
+        budget.charge(added)
+    }
+    fn insert(&mut self, tuple: Tuple, skip: bool) -> usize {
+        let size = approx_tuple_size(&tuple);
+        if self.inner.insert(tuple, skip).is_none() {
+            self.size += size;
+            size
+        } else {
+            0
+        }
     }
-    pub(crate) fn put_with_skip(&mut self, tuple: Tuple) {
-        self.inner.insert(tuple, true);
+    /// Approximate memory held by the store, in bytes.
+    pub(crate) fn approx_size(&self) -> usize {
+        self.size
     }
     // returns true if prev is guaranteed to be the same as self after this function call,
     // false if we are not sure.
     pub(crate) fn merge_in(&mut self, prev: &mut Self, mut new: Self) -> bool {
         prev.inner.clear();
+        prev.size = 0;
         if new.inner.is_empty() {
             return false;
         }
@@ -76,7 +190,10 @@ impl RegularTempStore {
         for (k, v) in new.inner {
             match self.inner.entry(k) {
                 Entry::Vacant(ent) => {
+                    let size = approx_tuple_size(ent.key());
                     prev.inner.insert(ent.key().clone(), v);
+                    prev.size += size;
+                    self.size += size;
                     ent.insert(v);
                 }
                 Entry::Occupied(mut ent) => {
@@ -93,6 +210,7 @@ pub(crate) struct MeetAggrStore {
     inner: BTreeMap<Tuple, Tuple>,
     aggregations: Vec<(Aggregation, Vec<DataValue>)>,
     grouping_len: usize,
+    size: usize,
 }
 
 impl MeetAggrStore {
@@ -106,6 +224,10 @@ impl MeetAggrStore {
     pub(crate) fn is_empty(&self) -> bool {
         self.inner.is_empty()
     }
+    /// Approximate memory held by the store, in bytes.
+    pub(crate) fn approx_size(&self) -> usize {
+        self.size
+    }
     pub(crate) fn new(aggrs: Vec<Option<(Aggregation, Vec<DataValue>)>>) -> Result<Self> {
         let total_key_len = aggrs.len();
         let mut aggregations = aggrs.into_iter().flatten().collect_vec();
@@ -117,11 +239,12 @@ impl MeetAggrStore {
             inner: Default::default(),
             aggregations,
             grouping_len,
+            size: 0,
         })
     }
     // also need to check if value exists beforehand! use the idempotency!
     // need to think this through more carefully.
-    pub(crate) fn meet_put(&mut self, tuple: Tuple) -> Result<bool> {
+    pub(crate) fn meet_put(&mut self, tuple: Tuple, budget: &MemoryBudget) -> Result<bool> {
         let (key_part, val_part) = tuple.split_at(self.grouping_len);
         match self.inner.get_mut(key_part) {
             Some(prev_aggr) => {
@@ -133,7 +256,10 @@ impl MeetAggrStore {
                 Ok(changed)
             }
             None => {
+                let size = approx_tuple_size(key_part) + approx_tuple_size(val_part);
+                self.size += size;
                 self.inner.insert(key_part.to_vec(), val_part.to_vec());
+                budget.charge(size)?;
                 Ok(true)
             }
         }
@@ -181,6 +307,7 @@ impl MeetAggrStore {
     /// false if we are not sure.
     pub(crate) fn merge_in(&mut self, prev: &mut Self, mut new: Self) -> Result<bool> {
         prev.inner.clear();
+        prev.size = 0;
         if new.inner.is_empty() {
             return Ok(false);
         }
@@ -191,7 +318,10 @@ impl MeetAggrStore {
         for (k, v) in new.inner {
             match self.inner.entry(k) {
                 Entry::Vacant(ent) => {
+                    let size = approx_tuple_size(ent.key()) + approx_tuple_size(&v);
                     prev.inner.insert(ent.key().clone(), v.clone());
+                    prev.size += size;
+                    self.size += size;
                     ent.insert(v);
                 }
                 Entry::Occupied(mut ent) => {
@@ -204,6 +334,7 @@ impl MeetAggrStore {
                         }
                     }
                     if changed {
+                        prev.size += approx_tuple_size(ent.key()) + approx_tuple_size(ent.get());
                         prev.inner.insert(ent.key().clone(), ent.get().clone());
                     }
                 }
@@ -243,6 +374,12 @@ impl TempStore {
             TempStore::MeetAggr(m) => m.inner.is_empty(),
         }
     }
+    pub(crate) fn approx_size(&self) -> usize {
+        match self {
+            TempStore::Normal(n) => n.approx_size(),
+            TempStore::MeetAggr(m) => m.approx_size(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -285,6 +422,10 @@ impl EpochStore {
         }
         Ok(())
     }
+    /// Approximate memory held by the store, in bytes.
+    pub(crate) fn approx_size(&self) -> usize {
+        self.total.approx_size() + self.delta.approx_size()
+    }
     pub(crate) fn has_delta(&self) -> bool {
         if self.use_total_for_delta {
             !self.total.is_empty()
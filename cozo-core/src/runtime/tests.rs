@@ -8,7 +8,9 @@
  */
 
 use std::collections::BTreeMap;
-use std::time::Duration;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use itertools::Itertools;
 use log::debug;
@@ -21,9 +23,43 @@ use crate::data::value::DataValue;
 use crate::fixed_rule::FixedRulePayload;
 use crate::fts::{TokenizerCache, TokenizerConfig};
 use crate::parse::SourceSpan;
+use crate::runtime::bulk_import::ExternalSorter;
 use crate::runtime::callback::CallbackOp;
 use crate::runtime::db::Poison;
-use crate::{DbInstance, FixedRule, RegularTempStore, ScriptMutability};
+use crate::runtime::relation::RelationHandle;
+use crate::storage::{Storage, StoreTx};
+use crate::{DbInstance, FixedRule, MemStorage, NamedRows, RegularTempStore, ScriptMutability};
+
+/// A fresh directory under the system temp dir, removed with its contents when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("cozo-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
 
 #[test]
 fn test_limit_offset() {
@@ -392,6 +428,546 @@ fn test_trigger() {
     assert!(frs.rows.is_empty());
 }
 
+#[test]
+fn test_before_triggers() {
+    let db = DbInstance::default();
+    db.run_default(":create account {id: Int => balance: Float}")
+        .unwrap();
+    db.run_default(":create frozen {id: Int}").unwrap();
+    db.run_default(
+        r#"
+        ::set_triggers account
+
+        on before put {
+            ?[msg] := _new[id, balance], balance < 0, msg = concat('negative balance for ', to_string(id))
+        }
+        on before rm {
+            ?[id] := _new[id], *frozen{id}
+        }
+        "#,
+    )
+    .unwrap();
+    db.run_default(r"?[id, balance] <- [[1, 10.], [2, 20.]] :put account {id => balance}")
+        .unwrap();
+    let err = db
+        .run_default(r"?[id, balance] <- [[1, 5.], [2, -1.]] :put account {id => balance}")
+        .unwrap_err();
+    assert!(format!("{err:?}").contains("negative balance for 2"));
+    let err = db
+        .run_default(r"?[id, balance] <- [[1, -5.]] :update account {id => balance}")
+        .unwrap_err();
+    assert!(format!("{err:?}").contains("negative balance for 1"));
+    let res = db
+        .run_default(r"?[id, balance] := *account{id, balance}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 10.], [2, 20.]]));
+
+    db.run_default(r"?[id] <- [[2]] :put frozen {id}").unwrap();
+    assert!(db.run_default(r"?[id] <- [[2]] :rm account {id}").is_err());
+    db.run_default(r"?[id] <- [[1]] :rm account {id}").unwrap();
+    let res = db.run_default(r"?[id] := *account{id}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2]]));
+
+    assert!(db
+        .run_default(
+            r#"
+            ::set_triggers account
+
+            on before put {
+                ?[id] := _new[id, _]
+                :put frozen {id}
+            }
+            "#,
+        )
+        .is_err());
+}
+
+#[test]
+fn test_array_encoded_relation_handle() {
+    let db = crate::new_cozo_mem().unwrap();
+    for script in [":create a {k: Int => v: Int}", "::index create a:v {v}"] {
+        db.run_script(script, Default::default(), ScriptMutability::Mutable)
+            .unwrap();
+    }
+    let tx = db.transact().unwrap();
+    let handle = tx.get_relation("a", false).unwrap();
+    // handles used to be written by position, with only these fields
+    let old = rmp_serde::to_vec(&(
+        &handle.name,
+        &handle.id,
+        &handle.metadata,
+        &handle.put_triggers,
+        &handle.rm_triggers,
+        &handle.replace_triggers,
+        &handle.access_level,
+        &handle.is_temp,
+        &handle.indices,
+        &handle.hnsw_indices,
+        &handle.fts_indices,
+        &handle.lsh_indices,
+        &handle.description,
+    ))
+    .unwrap();
+    assert!(RelationHandle::decode(&old).unwrap() == handle);
+}
+
+#[test]
+fn test_changelog() {
+    let db = DbInstance::default();
+    db.run_default(":create kv {k: Int => v: String}").unwrap();
+    db.run_default(r"?[k, v] <- [[0, 'before']] :put kv {k => v}")
+        .unwrap();
+    db.run_default("::changelog on kv").unwrap();
+    db.run_default(r"?[k, v] <- [[1, 'a'], [2, 'b']] :put kv {k => v}")
+        .unwrap();
+    db.run_default(r"?[k, v] <- [[1, 'c']] :update kv {k => v}")
+        .unwrap();
+    db.run_default(r"?[k] <- [[2], [3]] :rm kv {k}").unwrap();
+    assert!(db
+        .run_default(r"?[k, v] <- [[4, 'x']] :put kv {k => v} :assert none")
+        .is_err());
+
+    let res = db.read_changelog("kv", 0, None).unwrap();
+    let rows = res.into_json()["rows"].clone();
+    let entries = rows
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| json!([row[0], row[1], row[3], row[4]]))
+        .collect_vec();
+    assert_eq!(
+        entries,
+        vec![
+            json!([1, "Put", [1, "a"], null]),
+            json!([2, "Put", [2, "b"], null]),
+            json!([3, "Put", [1, "c"], [1, "a"]]),
+            json!([4, "Rm", [2], [2, "b"]]),
+        ]
+    );
+    let res = db.read_changelog("kv", 3, Some(1)).unwrap();
+    assert_eq!(res.rows.len(), 1);
+    assert_eq!(res.rows[0][0], DataValue::from(3));
+    let res = db
+        .run_default("?[seq, op] := *kv:changelog{seq, op}, seq > 2")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3, "Put"], [4, "Rm"]]));
+
+    assert!(db.run_default("::remove kv").is_err());
+    db.run_default("::changelog on kv retain 2").unwrap();
+    db.run_default(r"?[k, v] <- [[5, 'e']] :put kv {k => v}")
+        .unwrap();
+    let res = db.run_default("?[seq] := *kv:changelog{seq}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[4], [5]]));
+
+    db.run_default("::changelog off kv").unwrap();
+    assert!(db.read_changelog("kv", 0, None).is_err());
+    db.run_default("::remove kv").unwrap();
+}
+
+#[test]
+fn test_changelog_replace() {
+    let leader = DbInstance::default();
+    let follower = DbInstance::default();
+    leader
+        .run_default(":create kv {k: Int => v: String}")
+        .unwrap();
+    leader.run_default("::changelog on kv").unwrap();
+    leader
+        .run_default(r"?[k, v] <- [[1, 'a']] :put kv {k => v}")
+        .unwrap();
+    assert_eq!(follower.replicate_from(&leader).unwrap(), 1);
+
+    leader
+        .run_default(r"?[k, v, w] <- [[2, 'b', 3]] :replace kv {k => v, w}")
+        .unwrap();
+    let res = leader.read_changelog("kv", 2, None).unwrap();
+    let entries = res.into_json()["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| json!([row[0], row[1], row[3], row[4]]))
+        .collect_vec();
+    assert_eq!(
+        entries,
+        vec![
+            json!([2, "Replace", ["k", "v", "w"], ["k", "v"]]),
+            json!([3, "Put", [2, "b", 3], null]),
+        ]
+    );
+
+    // the follower copies the replaced relation anew
+    leader
+        .run_default(r"?[k, v, w] <- [[4, 'd', 5]] :put kv {k => v, w}")
+        .unwrap();
+    follower.replicate_from(&leader).unwrap();
+    assert_eq!(follower.replica_position("kv").unwrap(), Some(4));
+    let query = "?[k, v, w] := *kv{k, v, w}";
+    assert_eq!(
+        follower.run_default(query).unwrap().into_json()["rows"],
+        json!([[2, "b", 3], [4, "d", 5]])
+    );
+
+    leader.run_default("::changelog off kv").unwrap();
+    leader.run_default("::remove kv").unwrap();
+}
+
+#[test]
+fn test_replication() {
+    let leader = DbInstance::default();
+    let follower = DbInstance::default();
+    leader
+        .run_default(":create kv {k: Int => v: String, tags: [String]? default null}")
+        .unwrap();
+    leader
+        .run_default(r"?[k, v, tags] <- [[1, 'a', null], [2, 'b', ['x']]] :put kv {k => v, tags}")
+        .unwrap();
+    leader.run_default(":create untracked {k}").unwrap();
+    leader.run_default("::changelog on kv retain 3").unwrap();
+
+    assert_eq!(follower.replicate_from(&leader).unwrap(), 2);
+    assert_eq!(follower.replica_position("kv").unwrap(), Some(0));
+    assert!(follower.run_default("?[k] := *untracked{k}").is_err());
+
+    leader
+        .run_default(r"?[k, v] <- [[3, 'c']] :put kv {k => v}")
+        .unwrap();
+    leader.run_default(r"?[k] <- [[1]] :rm kv {k}").unwrap();
+    leader
+        .run_default(r"?[k, v] <- [[2, 'bb']] :update kv {k => v}")
+        .unwrap();
+    assert_eq!(follower.replicate_from(&leader).unwrap(), 3);
+    assert_eq!(follower.replica_position("kv").unwrap(), Some(3));
+    let query = "?[k, v, tags] := *kv{k, v, tags}";
+    assert_eq!(
+        follower.run_default(query).unwrap().rows,
+        leader.run_default(query).unwrap().rows
+    );
+    assert_eq!(follower.replicate_from(&leader).unwrap(), 0);
+
+    // the follower falls behind retention and copies a fresh snapshot
+    for i in 10..15 {
+        leader
+            .run_default(&format!("?[k, v] <- [[{i}, 'x']] :put kv {{k => v}}"))
+            .unwrap();
+    }
+    assert_eq!(follower.replicate_from(&leader).unwrap(), 7);
+    assert_eq!(follower.replica_position("kv").unwrap(), Some(8));
+    assert_eq!(
+        follower.run_default(query).unwrap().rows,
+        leader.run_default(query).unwrap().rows
+    );
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn test_read_only_replica() {
+    let dir = TempDir::new("read-only-test");
+    let path = dir.join("leader.sqlite");
+    let read_only = r#"{"read_only": true}"#;
+    assert!(DbInstance::new("sqlite", &path, read_only).is_err());
+    assert!(!path.exists());
+
+    let leader = DbInstance::new("sqlite", &path, r#"{"journal_mode": "delete"}"#).unwrap();
+    leader.run_default(":create kv {k: Int => v: String}").unwrap();
+    leader.run_default("::changelog on kv").unwrap();
+    leader
+        .run_default(r"?[k, v] <- [[1, 'a']] :put kv {k => v}")
+        .unwrap();
+
+    let source = DbInstance::new("sqlite", &path, read_only).unwrap();
+    assert!(source
+        .run_default(r"?[k, v] <- [[2, 'b']] :put kv {k => v}")
+        .is_err());
+    assert!(source.import_relations(BTreeMap::new()).is_err());
+    // the file itself is opened read-only as well
+    source.set_read_only(false);
+    assert!(source
+        .run_default(r"?[k, v] <- [[2, 'b']] :put kv {k => v}")
+        .is_err());
+    let replica = DbInstance::default();
+    replica.set_read_only(true);
+    assert_eq!(replica.replicate_from(&source).unwrap(), 1);
+    leader
+        .run_default(r"?[k, v] <- [[2, 'b']] :put kv {k => v}")
+        .unwrap();
+    assert_eq!(replica.replicate_from(&source).unwrap(), 1);
+    assert!(!dir.join("leader.sqlite-wal").exists());
+
+    let query = "?[k, v] := *kv{k, v}";
+    assert_eq!(replica.run_default(query).unwrap().rows.len(), 2);
+    replica.run_default("::relations").unwrap();
+    for script in [
+        r"?[k, v] <- [[3, 'c']] :put kv {k => v}",
+        ":create other {k}",
+        "::remove kv",
+    ] {
+        assert!(replica.run_default(script).is_err());
+    }
+    let rows = NamedRows {
+        headers: vec!["k".to_string(), "v".to_string()],
+        rows: vec![vec![DataValue::from(3), DataValue::from("c")]],
+        next: None,
+    };
+    let data = BTreeMap::from([("kv".to_string(), rows)]);
+    assert!(replica.import_relations(data.clone()).is_err());
+    assert!(replica.import_relations_bulk(data).is_err());
+    assert!(replica.restore_backup(&path).is_err());
+    assert_eq!(replica.run_default(query).unwrap().rows.len(), 2);
+
+    replica.set_read_only(false);
+    replica
+        .run_default(r"?[k, v] <- [[3, 'c']] :put kv {k => v}")
+        .unwrap();
+}
+
+#[test]
+fn test_native_backup_unsupported() {
+    let db = DbInstance::default();
+    let dir = TempDir::new("native-backup");
+    let backup = dir.join("backup");
+    assert!(db.backup_db_native(&backup).is_err());
+    assert!(!backup.exists());
+}
+
+#[cfg(feature = "storage-rocksdb")]
+#[test]
+fn test_rocksdb_native_backup() {
+    let dir = TempDir::new("rocksdb-backup");
+    let backup = dir.join("backup");
+    let query = "?[k, v] := *kv{k, v}";
+    let expected = vec![
+        vec![DataValue::from(1), DataValue::from("a")],
+        vec![DataValue::from(2), DataValue::from("b")],
+    ];
+
+    let db = DbInstance::new("rocksdb", dir.join("db"), "").unwrap();
+    db.run_default(":create kv {k: Int => v: String}").unwrap();
+    db.run_default(r"?[k, v] <- [[1, 'a']] :put kv {k => v}")
+        .unwrap();
+    db.backup_db_native(&backup).unwrap();
+    db.run_default(r"?[k, v] <- [[2, 'b']] :put kv {k => v}")
+        .unwrap();
+    db.backup_db_native(&backup).unwrap();
+    assert!(backup.is_dir());
+    #[cfg(feature = "storage-sqlite")]
+    {
+        let sqlite_backup = dir.join("backup.sqlite");
+        db.backup_db(&sqlite_backup).unwrap();
+        assert!(sqlite_backup.is_file());
+        let restored = DbInstance::default();
+        restored.restore_backup(&sqlite_backup).unwrap();
+        assert_eq!(restored.run_default(query).unwrap().rows, expected);
+    }
+
+    let restored = DbInstance::default();
+    restored.restore_backup(&backup).unwrap();
+    assert_eq!(restored.run_default(query).unwrap().rows, expected);
+
+    let imported = DbInstance::default();
+    imported
+        .run_default(":create kv {k: Int => v: String}")
+        .unwrap();
+    imported
+        .import_from_backup(&backup, &["kv".to_string()])
+        .unwrap();
+    assert_eq!(imported.run_default(query).unwrap().rows, expected);
+
+    let reopened = crate::new_cozo_rocksdb_from_backup(&backup, dir.join("restored")).unwrap();
+    assert_eq!(
+        reopened
+            .run_script(query, Default::default(), ScriptMutability::Immutable)
+            .unwrap()
+            .rows,
+        expected
+    );
+    assert!(crate::new_cozo_rocksdb_from_backup(&backup, dir.join("restored")).is_err());
+}
+
+#[test]
+fn test_point_in_time_recovery() {
+    let dir = TempDir::new("pitr-test");
+    let backup = dir.join("backup.db");
+    let log_dir = dir.join("log");
+
+    let db = DbInstance::default();
+    db.run_default(":create kv {k: Int => v: String}").unwrap();
+    db.run_default(r"?[k, v] <- [[1, 'a']] :put kv {k => v}")
+        .unwrap();
+    db.enable_mutation_log(&log_dir).unwrap();
+    db.backup_db(&backup).unwrap();
+    db.run_default(r"?[k, v] <- [[2, 'b']] :put kv {k => v}")
+        .unwrap();
+    db.run_default(":create other {k}").unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    let before_remove = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+    std::thread::sleep(Duration::from_millis(10));
+    db.run_default("::remove kv").unwrap();
+
+    let restored = DbInstance::default();
+    assert!(
+        restored
+            .restore_to_point_in_time(&backup, &log_dir, before_remove)
+            .unwrap()
+            > 0
+    );
+    assert_eq!(
+        restored.run_default("?[k, v] := *kv{k, v}").unwrap().rows,
+        vec![
+            vec![DataValue::from(1), DataValue::from("a")],
+            vec![DataValue::from(2), DataValue::from("b")]
+        ]
+    );
+    restored.run_default("?[k] := *other{k}").unwrap();
+    restored.run_default(":create third {k}").unwrap();
+}
+
+#[test]
+fn test_encrypted_storage() {
+    let dir = TempDir::new("encryption-test");
+    let path = dir.join("db.sqlite");
+    let backup = dir.join("backup.sqlite");
+    let key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+    let options = format!(r#"{{"encryption_key": "{key}"}}"#);
+    let secret = "super-secret-value";
+
+    let db = DbInstance::new("sqlite", &path, &options).unwrap();
+    assert!(matches!(db, DbInstance::Encrypted(_)));
+    db.run_default(":create pii {k: Int => v: String}").unwrap();
+    db.run_default(&format!("?[k, v] <- [[1, '{secret}']] :put pii {{k => v}}"))
+        .unwrap();
+    db.backup_db(&backup).unwrap();
+    let query = "?[v] := *pii[1, v], v > 'a'";
+    assert_eq!(
+        db.run_default(query).unwrap().rows,
+        vec![vec![DataValue::from(secret)]]
+    );
+    drop(db);
+
+    for file in [&path, &backup] {
+        let raw = std::fs::read(file).unwrap();
+        assert!(!raw.windows(secret.len()).any(|w| w == secret.as_bytes()));
+    }
+    assert!(DbInstance::new("sqlite", &path, "").is_err());
+    let wrong_key = format!(r#"{{"encryption_key": "{}"}}"#, "ff".repeat(32));
+    assert!(DbInstance::new("sqlite", &path, &wrong_key).is_err());
+    assert!(DbInstance::new("sqlite", &path, r#"{"encrypt_keys": true}"#).is_err());
+    assert!(DbInstance::new("mem", "", &options).is_err());
+
+    let reopened = DbInstance::new("sqlite", &path, &options).unwrap();
+    assert_eq!(
+        reopened.run_default(query).unwrap().rows,
+        vec![vec![DataValue::from(secret)]]
+    );
+    reopened
+        .run_default(":create hist {k: Int, vld: Validity => v: String}")
+        .unwrap();
+    reopened
+        .run_default(r"?[k, vld, v] <- [[1, [1, true], 'old'], [1, [5, true], 'new']] :put hist {k, vld => v}")
+        .unwrap();
+    assert_eq!(
+        reopened
+            .run_default("?[v] := *hist{k: 1, v @ 3}")
+            .unwrap()
+            .rows,
+        vec![vec![DataValue::from("old")]]
+    );
+    let restored = DbInstance::new("sqlite", dir.join("restored.sqlite"), &options).unwrap();
+    restored.restore_backup(&backup).unwrap();
+    assert_eq!(
+        restored.run_default(query).unwrap().rows,
+        vec![vec![DataValue::from(secret)]]
+    );
+}
+
+#[test]
+fn test_encrypted_mutation_log() {
+    let dir = TempDir::new("encrypted-log-test");
+    let backup = dir.join("backup.sqlite");
+    let log_dir = dir.join("log");
+    let key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+    let options = format!(r#"{{"encryption_key": "{key}"}}"#);
+    let secret = "super-secret-value";
+
+    let db = DbInstance::new("sqlite", dir.join("db.sqlite"), &options).unwrap();
+    db.run_default(":create pii {k: Int => v: String}").unwrap();
+    db.enable_mutation_log(&log_dir).unwrap();
+    db.backup_db(&backup).unwrap();
+    db.run_default(&format!("?[k, v] <- [[1, '{secret}']] :put pii {{k => v}}"))
+        .unwrap();
+    drop(db);
+
+    for entry in std::fs::read_dir(&log_dir).unwrap() {
+        let raw = std::fs::read(entry.unwrap().path()).unwrap();
+        assert!(!raw.is_empty());
+        assert!(!raw.windows(secret.len()).any(|w| w == secret.as_bytes()));
+        assert!(!raw.windows(3).any(|w| w == b"pii"));
+    }
+
+    let until = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+    let wrong_key = format!(r#"{{"encryption_key": "{}"}}"#, "ff".repeat(32));
+    let wrong = DbInstance::new("sqlite", dir.join("wrong.sqlite"), &wrong_key).unwrap();
+    assert!(wrong
+        .restore_to_point_in_time(&backup, &log_dir, until)
+        .is_err());
+    let restored = DbInstance::new("sqlite", dir.join("restored.sqlite"), &options).unwrap();
+    assert!(
+        restored
+            .restore_to_point_in_time(&backup, &log_dir, until)
+            .unwrap()
+            > 0
+    );
+    assert_eq!(
+        restored.run_default("?[v] := *pii[1, v]").unwrap().rows,
+        vec![vec![DataValue::from(secret)]]
+    );
+}
+
+#[cfg(feature = "storage-redb")]
+#[test]
+fn test_redb_storage() {
+    let dir = TempDir::new("redb-test");
+    let path = dir.join("db.redb");
+
+    let db = DbInstance::new("redb", &path, "").unwrap();
+    db.run_default(":create hist {k: Int, vld: Validity => v: String}")
+        .unwrap();
+    db.run_default(
+        r"?[k, vld, v] <- [[1, [1, true], 'old'], [1, [5, true], 'new'], [2, [1, true], 'x'], [2, [3, false], '']]
+          :put hist {k, vld => v}",
+    )
+    .unwrap();
+    assert_eq!(
+        db.run_default("?[k, v] := *hist{k, v @ 4}").unwrap().rows,
+        vec![vec![DataValue::from(1), DataValue::from("old")]]
+    );
+    assert_eq!(
+        db.run_default("?[k, v] := *hist{k, v @ 'NOW'}").unwrap().rows,
+        vec![vec![DataValue::from(1), DataValue::from("new")]]
+    );
+
+    // many rows, so that scans in write transactions go through several pages
+    db.run_default(":create nums {n: Int}").unwrap();
+    db.run_default("?[n] := n in int_range(1000) :put nums {n}")
+        .unwrap();
+    db.run_default("?[n] := *nums{n}, n >= 10 :rm nums {n}")
+        .unwrap();
+    db.run_default("::remove hist").unwrap();
+    drop(db);
+
+    let db = DbInstance::new("redb", &path, "").unwrap();
+    assert_eq!(
+        db.run_default("?[count(n)] := *nums{n}").unwrap().rows,
+        vec![vec![DataValue::from(10)]]
+    );
+    assert!(db.run_default("?[k] := *hist{k}").is_err());
+}
+
 #[test]
 fn test_callback() {
     let db = DbInstance::default();
@@ -410,23 +986,69 @@ fn test_callback() {
         collected.push(d);
     }
     let collected = collected;
-    assert_eq!(collected[0].0, CallbackOp::Put);
-    assert_eq!(collected[0].1.rows.len(), 2);
-    assert_eq!(collected[0].1.rows[0].len(), 3);
-    assert_eq!(collected[0].2.rows.len(), 0);
+    assert_eq!(collected[0].0, CallbackOp::Create);
+    assert_eq!(collected[0].1.headers, vec!["fr", "to", "data"]);
     assert_eq!(collected[1].0, CallbackOp::Put);
     assert_eq!(collected[1].1.rows.len(), 2);
     assert_eq!(collected[1].1.rows[0].len(), 3);
-    assert_eq!(collected[1].2.rows.len(), 1);
+    assert_eq!(collected[1].2.rows.len(), 0);
+    assert_eq!(collected[2].0, CallbackOp::Put);
+    assert_eq!(collected[2].1.rows.len(), 2);
+    assert_eq!(collected[2].1.rows[0].len(), 3);
+    assert_eq!(collected[2].2.rows.len(), 1);
     assert_eq!(
-        collected[1].2.rows[0],
+        collected[2].2.rows[0],
         vec![DataValue::from(1), DataValue::from(2), DataValue::from(3)]
     );
-    assert_eq!(collected[2].0, CallbackOp::Rm);
-    assert_eq!(collected[2].1.rows.len(), 2);
-    assert_eq!(collected[2].1.rows[0].len(), 2);
-    assert_eq!(collected[2].2.rows.len(), 1);
-    assert_eq!(collected[2].2.rows[0].len(), 3);
+    assert_eq!(collected[3].0, CallbackOp::Rm);
+    assert_eq!(collected[3].1.rows.len(), 2);
+    assert_eq!(collected[3].1.rows[0].len(), 2);
+    assert_eq!(collected[3].2.rows.len(), 1);
+    assert_eq!(collected[3].2.rows[0].len(), 3);
+}
+
+#[test]
+fn test_schema_change_callback() {
+    let db = DbInstance::default();
+    let mut collected = vec![];
+    let (_id, receiver) = db.register_callback("friends", None);
+    let (_id, renamed_receiver) = db.register_callback("pals", None);
+    db.run_default(":create friends {fr: Int, to: Int}").unwrap();
+    db.run_default(r"?[fr, to, x] <- [[1,2,3]] :replace friends {fr, to => x}")
+        .unwrap();
+    db.run_default("::index create friends:rev {to, fr}").unwrap();
+    db.run_default("::index drop friends:rev").unwrap();
+    db.run_default("::rename friends -> pals").unwrap();
+    db.run_default("::remove pals").unwrap();
+    std::thread::sleep(Duration::from_secs_f64(0.01));
+    while let Ok(d) = receiver.try_recv() {
+        collected.push(d);
+    }
+    let ops = collected.iter().map(|(op, _, _)| *op).collect_vec();
+    assert_eq!(
+        ops,
+        vec![
+            CallbackOp::Create,
+            CallbackOp::Replace,
+            CallbackOp::Put,
+            CallbackOp::CreateIndex,
+            CallbackOp::RemoveIndex,
+            CallbackOp::Rename
+        ]
+    );
+    assert_eq!(collected[1].1.headers, vec!["fr", "to", "x"]);
+    assert_eq!(collected[1].2.headers, vec!["fr", "to"]);
+    assert_eq!(collected[3].1.rows, vec![vec![DataValue::from("rev")]]);
+    assert_eq!(collected[5].1.rows, vec![vec![DataValue::from("pals")]]);
+    assert_eq!(collected[5].2.rows, vec![vec![DataValue::from("friends")]]);
+
+    let mut collected = vec![];
+    while let Ok(d) = renamed_receiver.try_recv() {
+        collected.push(d);
+    }
+    let ops = collected.iter().map(|(op, _, _)| *op).collect_vec();
+    assert_eq!(ops, vec![CallbackOp::Rename, CallbackOp::Remove]);
+    assert_eq!(collected[1].2.headers, vec!["fr", "to", "x"]);
 }
 
 #[test]
@@ -660,6 +1282,245 @@ fn test_multi_tx() {
     assert!(db.run_default("?[a] := *a[a]").is_err());
 }
 
+#[test]
+fn test_mem_snapshot_isolation() {
+    let db = DbInstance::default();
+    db.run_default(":create a {a}").unwrap();
+    db.run_default("?[a] <- [[1]] :put a {a}").unwrap();
+    let tx = db.multi_transaction(true);
+    tx.run_script("?[a] <- [[2]] :put a {a}", Default::default())
+        .unwrap();
+    // readers are not blocked by the open write transaction, and do not see its changes
+    assert_eq!(
+        db.run_default("?[a] := *a[a]").unwrap().into_json()["rows"],
+        json!([[1]])
+    );
+    tx.commit().unwrap();
+    assert_eq!(
+        db.run_default("?[a] := *a[a]").unwrap().into_json()["rows"],
+        json!([[1], [2]])
+    );
+
+    let storage = MemStorage::default();
+    let mut tx1 = storage.transact(true).unwrap();
+    let mut tx2 = storage.transact(true).unwrap();
+    let mut tx3 = storage.transact(true).unwrap();
+    let reader = storage.transact(false).unwrap();
+    tx1.put(b"k", b"1").unwrap();
+    tx2.put(b"k", b"2").unwrap();
+    tx3.put(b"other", b"3").unwrap();
+    tx1.commit().unwrap();
+    assert!(tx2.commit().is_err());
+    tx3.commit().unwrap();
+    assert_eq!(reader.get(b"k", false).unwrap(), None);
+    let tx = storage.transact(false).unwrap();
+    assert_eq!(tx.get(b"k", false).unwrap(), Some(b"1".to_vec()));
+    assert_eq!(tx.get(b"other", false).unwrap(), Some(b"3".to_vec()));
+}
+
+#[test]
+fn test_mem_snapshot() {
+    let dir = TempDir::new("mem-snapshot-test");
+    let path = dir.join("db.snapshot");
+
+    let db = DbInstance::new("mem", &path, r#"{"save_on_close": true}"#).unwrap();
+    db.run_default(":create kv {k: Int => v: String}").unwrap();
+    db.run_default(r"?[k, v] <- [[1, 'a'], [2, 'b']] :put kv {k => v}")
+        .unwrap();
+    drop(db);
+
+    // the path is ignored unless asked to load from it
+    let db = DbInstance::new("mem", &path, "").unwrap();
+    assert!(db.run_default("?[k, v] := *kv{k, v}").is_err());
+    let other_file = dir.join("cozo.db");
+    std::fs::write(&other_file, "SQLite format 3\0").unwrap();
+    assert!(DbInstance::new("mem", &other_file, "").is_ok());
+    let db = DbInstance::new("mem", &path, r#"{"load_snapshot": true}"#).unwrap();
+    assert_eq!(
+        db.run_default("?[k, v] := *kv{k, v}").unwrap().into_json()["rows"],
+        json!([[1, "a"], [2, "b"]])
+    );
+    db.run_default(":create other {k}").unwrap();
+    let mut snapshot = vec![];
+    db.write_snapshot(&mut snapshot).unwrap();
+    let restored = DbInstance::Mem(crate::new_cozo_mem_from_snapshot(&snapshot[..]).unwrap());
+    assert_eq!(
+        restored.run_default("?[k, v] := *kv{k, v}").unwrap().into_json()["rows"],
+        json!([[1, "a"], [2, "b"]])
+    );
+    restored.run_default(":create another {k}").unwrap();
+
+    assert!(crate::new_cozo_mem_from_snapshot(&snapshot[..20]).is_err());
+    // a huge length prefix in a short snapshot is reported as truncation
+    let mut forged = snapshot[..8].to_vec();
+    forged.extend(1u64.to_be_bytes());
+    forged.extend(u32::MAX.to_be_bytes());
+    forged.extend(b"abc");
+    assert!(crate::new_cozo_mem_from_snapshot(&forged[..]).is_err());
+    assert!(crate::new_cozo_mem_from_snapshot(&b"not a snapshot at all"[..]).is_err());
+    assert!(DbInstance::new("mem", "", r#"{"save_on_close": true}"#).is_err());
+    assert!(DbInstance::new("mem", "", r#"{"load_snapshot": true}"#).is_err());
+}
+
+#[test]
+fn test_bulk_import() {
+    let db = DbInstance::default();
+    db.run_default(":create edge {fr: Int, to: Int => w: Float}")
+        .unwrap();
+    db.run_default("::index create edge:rev {to, fr}").unwrap();
+    let rows = |range: std::ops::Range<i64>| NamedRows {
+        headers: vec!["fr".to_string(), "to".to_string(), "w".to_string()],
+        rows: range
+            .rev()
+            .map(|i| {
+                vec![
+                    DataValue::from(i),
+                    DataValue::from(i % 7),
+                    DataValue::from(0.5),
+                ]
+            })
+            .collect(),
+        next: None,
+    };
+    db.import_relations_bulk(BTreeMap::from([("edge".to_string(), rows(0..100))]))
+        .unwrap();
+    db.import_relations_bulk(BTreeMap::from([("edge".to_string(), rows(100..200))]))
+        .unwrap();
+    let res = db.run_default("?[count(fr)] := *edge{fr, to: 3}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[29]]));
+    let res = db.run_default("?[fr] := *edge:rev{to: 6, fr}").unwrap();
+    assert_eq!(res.rows.len(), 28);
+
+    // only new keys can be imported
+    assert!(db
+        .import_relations_bulk(BTreeMap::from([("edge".to_string(), rows(150..250))]))
+        .is_err());
+    let mut duplicated = rows(300..310);
+    duplicated.rows.push(duplicated.rows[0].clone());
+    assert!(db
+        .import_relations_bulk(BTreeMap::from([("edge".to_string(), duplicated)]))
+        .is_err());
+    assert_eq!(
+        db.run_default("?[count(fr)] := *edge{fr}")
+            .unwrap()
+            .into_json()["rows"],
+        json!([[200]])
+    );
+    assert!(db
+        .import_relations_bulk(BTreeMap::from([("-edge".to_string(), rows(0..1))]))
+        .is_err());
+
+    let mut sorter = ExternalSorter::new(64);
+    let mut expected = vec![];
+    for i in (0..1000u32).rev() {
+        let key = (i * 7919 % 1000).to_be_bytes().to_vec();
+        sorter.push(key.clone(), vec![i as u8]).unwrap();
+        expected.push(key);
+    }
+    expected.sort();
+    let sorted: Vec<_> = sorter
+        .finish()
+        .unwrap()
+        .map_ok(|(k, _)| k)
+        .try_collect()
+        .unwrap();
+    assert_eq!(sorted, expected);
+
+    let log_dir = TempDir::new("bulk-log");
+    db.enable_mutation_log(&log_dir).unwrap();
+    assert!(db
+        .import_relations_bulk(BTreeMap::from([("edge".to_string(), rows(300..310))]))
+        .is_err());
+}
+
+#[cfg(feature = "storage-rocksdb")]
+#[test]
+fn test_rocksdb_sst_ingestion() {
+    let dir = TempDir::new("rocksdb-sst");
+    let db = crate::new_cozo_rocksdb(&dir).unwrap();
+    let pairs = |range: std::ops::Range<u32>| {
+        range.map(|i| -> miette::Result<(Vec<u8>, Vec<u8>)> {
+            Ok((
+                [0xF0, 0].into_iter().chain(i.to_be_bytes()).collect(),
+                vec![7; 100],
+            ))
+        })
+    };
+
+    // small files, so that several are ingested at once
+    db.db.ingest_sorted(Box::new(pairs(0..1000)), 4096).unwrap();
+    let bad = pairs(1000..1100).chain([Err(miette::miette!("bad data"))]);
+    assert!(db.db.ingest_sorted(Box::new(bad), 4096).is_err());
+    let tx = db.db.transact(false).unwrap();
+    let stored: Vec<(Vec<u8>, Vec<u8>)> = tx.range_scan(&[0xF0], &[0xF1]).try_collect().unwrap();
+    assert_eq!(stored.len(), 1000);
+    assert!(stored
+        .iter()
+        .map(|(k, _)| k)
+        .tuple_windows()
+        .all(|(a, b)| a < b));
+    assert!(stored.iter().all(|(_, v)| v == &vec![7; 100]));
+    drop(tx);
+    assert!(std::fs::read_dir(dir.join("data"))
+        .unwrap()
+        .all(|entry| !entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with("bulk-")));
+
+    let db = DbInstance::RocksDb(db);
+    db.run_default(":create edge {fr: Int, to: Int}").unwrap();
+    db.run_default("::index create edge:rev {to, fr}").unwrap();
+    let rows = NamedRows {
+        headers: vec!["fr".to_string(), "to".to_string()],
+        rows: (0..100)
+            .map(|i| vec![DataValue::from(i), DataValue::from(i % 7)])
+            .collect(),
+        next: None,
+    };
+    db.import_relations_bulk(BTreeMap::from([("edge".to_string(), rows)]))
+        .unwrap();
+    let res = db
+        .run_default("?[count(fr)] := *edge:rev{to: 3, fr}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[14]]));
+}
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn test_sqlite_concurrent_readers() {
+    let dir = TempDir::new("sqlite-wal-test");
+    let path = dir.join("db.sqlite");
+    let options = r#"{"synchronous": "normal", "cache_size": -4000, "mmap_size": 1048576}"#;
+
+    let db = DbInstance::new("sqlite", &path, options).unwrap();
+    assert!(dir.join("db.sqlite-wal").exists());
+    db.run_default(":create a {a}").unwrap();
+    db.run_default("?[a] <- [[1]] :put a {a}").unwrap();
+    let tx = db.multi_transaction(true);
+    tx.run_script("?[a] <- [[2]] :put a {a}", Default::default())
+        .unwrap();
+    // the open write transaction does not block readers
+    assert_eq!(
+        db.run_default("?[a] := *a[a]").unwrap().into_json()["rows"],
+        json!([[1]])
+    );
+    tx.commit().unwrap();
+    assert_eq!(
+        db.run_default("?[a] := *a[a]").unwrap().into_json()["rows"],
+        json!([[1], [2]])
+    );
+
+    let bad_path = dir.join("bad.sqlite");
+    assert!(DbInstance::new("sqlite", &bad_path, r#"{"journal_mode": "bogus"}"#).is_err());
+    assert!(DbInstance::new(
+        "sqlite",
+        &bad_path,
+        r#"{"synchronous": "off; drop table cozo"}"#
+    )
+    .is_err());
+}
+
 #[test]
 fn test_vec_types() {
     let db = DbInstance::new("mem", "", "").unwrap();
@@ -1225,4 +2086,222 @@ fn update_shall_work() {
     db.run_default(r"?[x, y] <- [[1, 4]] :update z {x, y}").unwrap();
     let r = db.run_default(r"?[x, y, z] := *z {x, y, z}").unwrap();
     assert_eq!(r.into_json()["rows"], json!([[1, 4, 3]]));
-}
\ No newline at end of file
+}
+
+#[test]
+fn memory_limit() {
+    let db = DbInstance::default();
+    let script = r"
+        r[x] := x in int_range(200)
+        ?[a, b, c] := r[a], r[b], r[c]
+    ";
+    let err = db
+        .run_default(&format!("{script} :memory_limit 100000"))
+        .unwrap_err();
+    assert!(err.to_string().contains("memory budget"));
+
+    let r = db
+        .run_default("?[x] := x in int_range(10) :memory_limit 100000")
+        .unwrap();
+    assert_eq!(r.rows.len(), 10);
+
+    db.set_default_memory_limit(Some(100000));
+    assert!(db.run_default(script).is_err());
+    assert!(db
+        .run_default(&format!("{script} :limit 1 :memory_limit 1000000"))
+        .is_ok());
+    assert!(db
+        .run_default(&format!("{script} :limit 1 :memory_limit null"))
+        .is_ok());
+    db.set_default_memory_limit(None);
+    assert!(db
+        .run_default("?[x] := x in int_range(10) :memory_limit 0")
+        .is_err());
+}
+
+#[test]
+fn test_stats() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x: Int => y: String}").unwrap();
+    db.run_default("::index create a:y {y}").unwrap();
+    db.run_default(":create b {x: Int}").unwrap();
+    db.run_default("?[x, y] := x in int_range(10), y = to_string(x) :put a {x => y}")
+        .unwrap();
+    db.run_default("?[n] := n in int_range(1000) :order -n")
+        .unwrap();
+
+    let metric = |res: &NamedRows, category: &str, name: &str, metric: &str| {
+        res.rows
+            .iter()
+            .find(|row| {
+                row[0] == DataValue::from(category)
+                    && row[1] == DataValue::from(name)
+                    && row[2] == DataValue::from(metric)
+            })
+            .map(|row| row[3].clone())
+    };
+    let res = db.run_default("::stats").unwrap();
+    assert_eq!(res.headers, ["category", "name", "metric", "value"]);
+    assert_eq!(
+        metric(&res, "relation", "a", "keys"),
+        Some(DataValue::from(10))
+    );
+    assert_eq!(
+        metric(&res, "index", "a:y", "keys"),
+        Some(DataValue::from(10))
+    );
+    assert_eq!(
+        metric(&res, "relation", "b", "keys"),
+        Some(DataValue::from(0))
+    );
+    assert!(
+        metric(&res, "relation", "a", "bytes")
+            .unwrap()
+            .get_int()
+            .unwrap()
+            > 0
+    );
+    let peak = res
+        .rows
+        .iter()
+        .find(|row| {
+            row[0] == DataValue::from("temp_store") && row[2] == DataValue::from("peak_bytes")
+        })
+        .unwrap();
+    assert!(peak[3].get_int().unwrap() > 0);
+    // the system relation holds the metadata as well
+    assert!(
+        metric(&res, "engine", "mem", "entries")
+            .and_then(|v| v.get_int())
+            .unwrap()
+            > 20
+    );
+
+    let res = db.run_default("::stats a").unwrap();
+    assert_eq!(res.rows.len(), 4);
+    assert!(res
+        .rows
+        .iter()
+        .all(|row| row[1] == DataValue::from("a") || row[1] == DataValue::from("a:y")));
+    assert_eq!(db.run_default("::stats exact a").unwrap().rows, res.rows);
+    db.run_default("::changelog on a").unwrap();
+    let res = db.run_default("::stats a").unwrap();
+    assert_eq!(
+        metric(&res, "changelog", "a:changelog", "keys"),
+        Some(DataValue::from(0))
+    );
+    assert!(db.run_default("::stats c").is_err());
+}
+
+#[cfg(feature = "storage-rocksdb")]
+#[test]
+fn test_rocksdb_statistics() {
+    let dir = TempDir::new("rocksdb-stats");
+    let hits = |db: &DbInstance| {
+        db.run_default("::stats")
+            .unwrap()
+            .rows
+            .into_iter()
+            .find(|row| {
+                row[0] == DataValue::from("engine") && row[2] == DataValue::from("block-cache-hits")
+            })
+            .map(|row| row[3].clone())
+            .unwrap()
+    };
+
+    let db = DbInstance::new("rocksdb", dir.join("plain"), "").unwrap();
+    assert_eq!(hits(&db), DataValue::from("unavailable"));
+    let db = DbInstance::new("rocksdb", dir.join("stats"), r#"{"statistics": true}"#).unwrap();
+    assert!(hits(&db).get_int().is_some());
+
+    // relation sizes are estimated unless exact counts are asked for
+    db.run_default("?[k] <- [[1], [2], [3]] :create a {k}")
+        .unwrap();
+    let metrics = |script: &str| {
+        db.run_default(script)
+            .unwrap()
+            .rows
+            .into_iter()
+            .filter(|row| row[1] == DataValue::from("a"))
+            .map(|row| row[2].get_str().unwrap().to_string())
+            .collect_vec()
+    };
+    assert_eq!(metrics("::stats a"), ["estimated_keys", "estimated_bytes"]);
+    assert_eq!(metrics("::stats exact a"), ["keys", "bytes"]);
+}
+
+#[test]
+fn test_dump() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#":create a {k: Int, vld: Validity default 'ASSERT' => s: String default "x\ty", f: Float?, any: Any?, v: <F32; 2>?}"#,
+    )
+    .unwrap();
+    db.run_default(
+        r#"?[k, vld, s, f, any, v] <- [
+            [1, [10, true], 'quote \' and\nnewline', 1.0, decode_base64("AAEC"), vec([1.5, 2])],
+            [-9223372036854775807 - 1, [5, false], "", to_float("NAN"), parse_json('{"a": [1, null]}'), null],
+            [3, [1, true], "ünïcode", -0.5, [to_uuid("8d2d4b6a-4b6e-11ed-bdc3-0242ac120002"), true, 1e300], null]
+        ] :put a {k, vld => s, f, any, v}"#,
+    )
+    .unwrap();
+    db.run_default(":create b {x: Int}").unwrap();
+    db.run_default("?[x] := x in int_range(2500) :put b {x}")
+        .unwrap();
+    db.run_default("::index create a:by_s {s}").unwrap();
+    db.run_default(
+        "::hnsw create a:vec {dim: 2, m: 16, dtype: F32, fields: [v], distance: Cosine, ef: 20, filter: k > 0}",
+    )
+    .unwrap();
+    db.run_default("::fts create a:fts {extractor: s, tokenizer: Simple, filters: [Lowercase]}")
+        .unwrap();
+    db.run_default(
+        "::lsh create a:lsh {extractor: s, tokenizer: NGram(1, 2, false), n_gram: 3, target_threshold: 0.5}",
+    )
+    .unwrap();
+    db.run_default(r#"::describe b "the numbers""#).unwrap();
+    db.run_default("::changelog on b retain 10").unwrap();
+    db.run_default(":create c {x: Int}").unwrap();
+    db.run_default("::set_triggers b on put { ?[x] := _new[x] :put c {x} }")
+        .unwrap();
+    db.run_default("::access_level protected a").unwrap();
+
+    let dumped = db.run_default("::dump").unwrap();
+    assert_eq!(dumped.headers, ["statement"]);
+    let script = dumped
+        .rows
+        .iter()
+        .map(|row| row[0].get_str().unwrap())
+        .join("\n");
+
+    let restored = DbInstance::default();
+    restored.run_default(&script).unwrap();
+    for query in [
+        "?[k, vld, s, f, any, v] := *a{k, vld, s, f, any, v}",
+        "?[count(x)] := *b{x}",
+        "::relations",
+        "::columns a",
+        "::indices a",
+        "::indices b",
+        "::show_triggers b",
+        "?[s, k] := *a:by_s{s, k}",
+    ] {
+        let expected = db.run_default(query).unwrap();
+        let actual = restored.run_default(query).unwrap();
+        // NaN is not equal to itself
+        assert_eq!(format!("{:?}", expected.rows), format!("{:?}", actual.rows));
+    }
+    // loading the data did not fire the trigger
+    assert_eq!(
+        restored.run_default("?[count(x)] := *c{x}").unwrap().rows,
+        vec![vec![DataValue::from(0)]]
+    );
+
+    // only some relations
+    let dumped = db.run_default("::dump b").unwrap();
+    assert!(dumped
+        .rows
+        .iter()
+        .all(|row| !row[0].get_str().unwrap().contains(":create a ")));
+    assert!(db.run_default("::dump d").is_err());
+}
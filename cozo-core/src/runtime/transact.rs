@@ -58,6 +58,7 @@ impl<'a> SessionTx<'a> {
                             let (pos_key, neg_key) = match kind {
                                 CallbackOp::Put => { ("inserted", "replaced") }
                                 CallbackOp::Rm => { ("requested", "deleted") }
+                                _ => continue
                             };
                             for row in &insertions.rows {
                                 let mut v = Vec::with_capacity(target_len + 1);
@@ -0,0 +1,383 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Encryption at rest, as a wrapper around any storage engine.
+//!
+//! Values are encrypted with AES-256-GCM under a fresh random nonce, with the key of the
+//! entry as associated data, so that a value cannot be moved to another key unnoticed.
+//!
+//! The wrapped engine is type-erased, so that a single [EncryptedStorage] type serves them all.
+//!
+//! Keys are stored in plaintext. Cozo relies on the byte order of keys for all of its range
+//! scans, and the only encryption schemes that keep this order also reveal it (and much of the
+//! plaintext with it), so key encryption is not offered. Keys contain the key columns of stored
+//! relations and indices, so put sensitive data in non-key columns if that matters to you.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use itertools::Itertools;
+use miette::{bail, miette, Diagnostic, Result};
+use rand::RngCore;
+use thiserror::Error;
+
+use crate::data::tuple::{check_key_for_validity, Tuple};
+use crate::data::value::{DataValue, ValidityTs};
+use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::storage::{Storage, StoreTx};
+use crate::Db;
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot decrypt stored data")]
+#[diagnostic(code(storage::decryption_failed))]
+#[diagnostic(help("The encryption key is wrong, or the data is not encrypted or corrupted"))]
+struct DecryptionFailed;
+
+/// A key for [EncryptedStorage].
+#[derive(Clone)]
+pub struct StorageCipher(Arc<Aes256Gcm>);
+
+impl StorageCipher {
+    /// Create the cipher from a 256-bit key.
+    pub fn new(key: &[u8]) -> Result<Self> {
+        if key.len() != 32 {
+            bail!("encryption key must be 32 bytes long, got {}", key.len())
+        }
+        Ok(Self(Arc::new(Aes256Gcm::new_from_slice(key).unwrap())))
+    }
+    /// Create the cipher from a 256-bit key written as 64 hexadecimal digits.
+    pub fn from_hex(key: &str) -> Result<Self> {
+        let bytes: Vec<u8> = key
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| {
+                std::str::from_utf8(pair)
+                    .ok()
+                    .and_then(|s| u8::from_str_radix(s, 16).ok())
+                    .ok_or_else(|| miette!("encryption key must be given in hexadecimal"))
+            })
+            .try_collect()?;
+        Self::new(&bytes)
+    }
+    pub(crate) fn encrypt(&self, key: &[u8], val: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let encrypted = self
+            .0
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: val, aad: key })
+            .map_err(|_| miette!("encryption failed"))?;
+        let mut ret = Vec::with_capacity(NONCE_LEN + encrypted.len());
+        ret.extend_from_slice(&nonce);
+        ret.extend_from_slice(&encrypted);
+        Ok(ret)
+    }
+    pub(crate) fn decrypt(&self, key: &[u8], val: &[u8]) -> Result<Vec<u8>> {
+        if val.len() < NONCE_LEN {
+            bail!(DecryptionFailed)
+        }
+        let (nonce, encrypted) = val.split_at(NONCE_LEN);
+        self.0
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: encrypted,
+                    aad: key,
+                },
+            )
+            .map_err(|_| DecryptionFailed.into())
+    }
+}
+
+/// Creates a database object with values encrypted by `cipher` before they reach `storage`.
+pub fn new_cozo_encrypted<S>(storage: S, cipher: StorageCipher) -> Result<Db<EncryptedStorage>>
+where
+    S: for<'s> Storage<'s> + 'static,
+{
+    let ret = Db::new(EncryptedStorage::new(storage, cipher))?;
+    ret.initialize()?;
+    Ok(ret)
+}
+
+/// Object-safe view of a storage engine, for [EncryptedStorage] to hold any of them.
+trait DynStorage: Send + Sync {
+    fn storage_kind(&self) -> &'static str;
+    fn transact<'s>(&'s self, write: bool) -> Result<Box<dyn StoreTx<'s> + 's>>;
+    fn range_compact(&self, lower: &[u8], upper: &[u8]) -> Result<()>;
+    fn batch_put<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()>;
+    fn native_backup(&self, dir: &Path) -> Result<bool>;
+    fn approximate_size(&self, lower: &[u8], upper: &[u8]) -> Result<Option<(u64, u64)>>;
+    fn engine_stats(&self) -> Result<Vec<(String, DataValue)>>;
+}
+
+impl<S> DynStorage for S
+where
+    S: for<'s> Storage<'s>,
+{
+    fn storage_kind(&self) -> &'static str {
+        Storage::storage_kind(self)
+    }
+    fn transact<'s>(&'s self, write: bool) -> Result<Box<dyn StoreTx<'s> + 's>> {
+        Ok(Box::new(Storage::transact(self, write)?))
+    }
+    fn range_compact(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        Storage::range_compact(self, lower, upper)
+    }
+    fn batch_put<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        Storage::batch_put(self, data)
+    }
+    fn native_backup(&self, dir: &Path) -> Result<bool> {
+        Storage::native_backup(self, dir)
+    }
+    fn approximate_size(&self, lower: &[u8], upper: &[u8]) -> Result<Option<(u64, u64)>> {
+        Storage::approximate_size(self, lower, upper)
+    }
+    fn engine_stats(&self) -> Result<Vec<(String, DataValue)>> {
+        Storage::engine_stats(self)
+    }
+}
+
+/// Storage engine that encrypts the values stored by another engine.
+/// See the [module documentation](self) for what is and is not encrypted.
+///
+/// Backups of the database are encrypted with the same key.
+#[derive(Clone)]
+pub struct EncryptedStorage {
+    inner: Arc<dyn DynStorage>,
+    cipher: StorageCipher,
+}
+
+impl EncryptedStorage {
+    /// Wrap `inner` so that the values stored in it are encrypted by `cipher`.
+    pub fn new<S>(inner: S, cipher: StorageCipher) -> Self
+    where
+        S: for<'s> Storage<'s> + 'static,
+    {
+        Self {
+            inner: Arc::new(inner),
+            cipher,
+        }
+    }
+}
+
+impl<'s> Storage<'s> for EncryptedStorage {
+    type Tx = EncryptedTx<'s>;
+
+    fn storage_kind(&self) -> &'static str {
+        self.inner.storage_kind()
+    }
+
+    fn transact(&'s self, write: bool) -> Result<Self::Tx> {
+        Ok(EncryptedTx {
+            inner: self.inner.transact(write)?,
+            cipher: self.cipher.clone(),
+        })
+    }
+
+    fn range_compact(&'s self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.range_compact(lower, upper)
+    }
+
+    fn approximate_size(&'s self, lower: &[u8], upper: &[u8]) -> Result<Option<(u64, u64)>> {
+        self.inner.approximate_size(lower, upper)
+    }
+
+    fn engine_stats(&'s self) -> Result<Vec<(String, DataValue)>> {
+        self.inner.engine_stats()
+    }
+
+    fn batch_put<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        let cipher = &self.cipher;
+        self.inner.batch_put(Box::new(data.map(|res| {
+            let (key, val) = res?;
+            let val = cipher.encrypt(&key, &val)?;
+            Ok((key, val))
+        })))
+    }
+
+    fn native_backup(&'s self, dir: &Path) -> Result<bool> {
+        self.inner.native_backup(dir)
+    }
+
+    fn cipher(&self) -> Option<&StorageCipher> {
+        Some(&self.cipher)
+    }
+}
+
+/// Transaction of [EncryptedStorage].
+pub struct EncryptedTx<'s> {
+    inner: Box<dyn StoreTx<'s> + 's>,
+    cipher: StorageCipher,
+}
+
+impl<'s> EncryptedTx<'s> {
+    fn decrypt_kv(&self, res: Result<(Vec<u8>, Vec<u8>)>) -> Result<(Vec<u8>, Vec<u8>)> {
+        let (key, val) = res?;
+        let val = self.cipher.decrypt(&key, &val)?;
+        Ok((key, val))
+    }
+}
+
+impl<'s> StoreTx<'s> for EncryptedTx<'s> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        match self.inner.get(key, for_update)? {
+            None => Ok(None),
+            Some(val) => Ok(Some(self.cipher.decrypt(key, &val)?)),
+        }
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner
+            .multi_get(keys, for_update)?
+            .into_iter()
+            .zip(keys)
+            .map(|(val, key)| match val {
+                None => Ok(None),
+                Some(val) => Ok(Some(self.cipher.decrypt(key, &val)?)),
+            })
+            .collect()
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        let val = self.cipher.encrypt(key, val)?;
+        self.inner.put(key, &val)
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        let val = self.cipher.encrypt(key, val)?;
+        self.inner.par_put(key, &val)
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.del(key)
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.inner.par_del(key)
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.del_range_from_persisted(lower, upper)
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(
+            self.range_scan(lower, upper)
+                .map_ok(|(k, v)| decode_tuple_from_kv(&k, &v, None)),
+        )
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        Box::new(SkipIter {
+            tx: self,
+            upper: upper.to_vec(),
+            valid_at,
+            next_bound: lower.to_vec(),
+        })
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(
+            self.inner
+                .range_scan(lower, upper)
+                .map(|res| self.decrypt_kv(res)),
+        )
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(self.inner.total_scan().map(|res| self.decrypt_kv(res)))
+    }
+}
+
+/// The validity check only looks at keys, so the skip scan works on the raw scan of the
+/// inner transaction, seeking past the versions that are skipped.
+struct SkipIter<'a, 's> {
+    tx: &'a EncryptedTx<'s>,
+    upper: Vec<u8>,
+    valid_at: ValidityTs,
+    next_bound: Vec<u8>,
+}
+
+impl<'a, 's> Iterator for SkipIter<'a, 's> {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, val) = match self
+                .tx
+                .inner
+                .range_scan(&self.next_bound, &self.upper)
+                .next()?
+            {
+                Ok(kv) => kv,
+                Err(err) => return Some(Err(err)),
+            };
+            let (ret, next_bound) = check_key_for_validity(&key, self.valid_at, None);
+            self.next_bound = next_bound;
+            if let Some(mut tuple) = ret {
+                return Some(self.tx.cipher.decrypt(&key, &val).map(|val| {
+                    extend_tuple_from_v(&mut tuple, &val);
+                    tuple
+                }));
+            }
+        }
+    }
+}
@@ -6,28 +6,48 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use crossbeam::sync::{ShardedLock, ShardedLockReadGuard, ShardedLockWriteGuard};
-use std::cmp::Ordering;
-use std::collections::btree_map::Range;
-use std::collections::BTreeMap;
+//! The in-memory storage engine.
+//!
+//! The data is kept in a persistent ordered map, so that taking a snapshot of it is cheap.
+//! Every transaction works on a snapshot taken when it starts: readers never wait for writers,
+//! and writers apply their changes to a private copy of the map. On commit, a writer checks the
+//! commits made since its snapshot was taken, and fails if any of them wrote a key that it
+//! wrote or read for update. Otherwise its changes are applied to the latest version of the map.
+//!
+//! The data can be saved to and loaded from snapshots in a compact format of its own:
+//!
+//! * the magic bytes `COZOMEM` followed by the format version as a byte, currently 1,
+//! * the number of entries, as a big-endian `u64`,
+//! * the entries in key order, each a key and a value prefixed by their big-endian `u32` lengths.
+
+use crossbeam::sync::ShardedLock;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::default::Default;
-use std::iter::Fuse;
-use std::mem;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::ops::Bound;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
+use im::OrdMap;
 use itertools::Itertools;
-use miette::{bail, Result};
+use miette::{bail, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use thiserror::Error;
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
-use crate::data::value::ValidityTs;
+use crate::data::value::{DataValue, ValidityTs};
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
 use crate::storage::{Storage, StoreTx};
-use crate::utils::swap_option_result;
+
+type MemMap = OrdMap<Vec<u8>, Vec<u8>>;
+
+const SNAPSHOT_MAGIC: &[u8; 7] = b"COZOMEM";
+const SNAPSHOT_VERSION: u8 = 1;
 
 /// Create a database backed by memory.
 /// This is the fastest storage, but non-persistent.
-/// Supports concurrent readers but only a single writer.
+/// Transactions run concurrently on snapshots of the data,
+/// and a write transaction fails to commit if it conflicts with one that committed first.
 pub fn new_cozo_mem() -> Result<crate::Db<MemStorage>> {
     let ret = crate::Db::new(MemStorage::default())?;
 
@@ -35,10 +55,236 @@ pub fn new_cozo_mem() -> Result<crate::Db<MemStorage>> {
     Ok(ret)
 }
 
+/// Create a database backed by memory, holding the data of a snapshot
+/// written by [MemStorage::write_snapshot].
+pub fn new_cozo_mem_from_snapshot(reader: impl Read) -> Result<crate::Db<MemStorage>> {
+    let ret = crate::Db::new(MemStorage::read_snapshot(reader)?)?;
+
+    ret.initialize()?;
+    Ok(ret)
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Transaction conflicts with a concurrent transaction that committed first")]
+#[diagnostic(code(storage::write_conflict))]
+#[diagnostic(help("Retry the transaction"))]
+struct WriteConflict;
+
 /// The non-persistent storage
 #[derive(Default, Clone)]
 pub struct MemStorage {
-    store: Arc<ShardedLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    state: Arc<ShardedLock<MemState>>,
+}
+
+#[derive(Default)]
+struct MemState {
+    /// The latest committed data
+    head: MemMap,
+    /// Incremented by every commit
+    version: u64,
+    /// Writes committed since the oldest running write transaction started
+    history: VecDeque<CommitRecord>,
+    /// Versions at which the running write transactions started, with their counts
+    writers: BTreeMap<u64, usize>,
+    /// Where to save a snapshot when the storage is dropped
+    save_on_drop: Option<PathBuf>,
+}
+
+impl Drop for MemState {
+    fn drop(&mut self) {
+        if let Some(path) = &self.save_on_drop {
+            if let Err(err) = save_snapshot(&self.head, path) {
+                log::error!("cannot save snapshot to {}: {:?}", path.display(), err);
+            }
+        }
+    }
+}
+
+impl MemState {
+    fn register_writer(&mut self) -> u64 {
+        *self.writers.entry(self.version).or_default() += 1;
+        self.version
+    }
+    fn unregister_writer(&mut self, version: u64) {
+        if let Some(count) = self.writers.get_mut(&version) {
+            *count -= 1;
+            if *count == 0 {
+                self.writers.remove(&version);
+            }
+        }
+        self.prune_history();
+    }
+    /// Drop the records that no running writer needs for conflict detection.
+    fn prune_history(&mut self) {
+        let oldest = self.writers.keys().next().copied().unwrap_or(self.version);
+        while let Some(record) = self.history.front() {
+            if record.version > oldest {
+                break;
+            }
+            self.history.pop_front();
+        }
+    }
+    fn record_commit(&mut self, keys: BTreeSet<Vec<u8>>, ranges: Vec<(Vec<u8>, Vec<u8>)>) {
+        self.version += 1;
+        if !self.writers.is_empty() {
+            self.history.push_back(CommitRecord {
+                version: self.version,
+                keys,
+                ranges,
+            });
+        }
+    }
+}
+
+/// The keys written by a committed transaction
+struct CommitRecord {
+    version: u64,
+    keys: BTreeSet<Vec<u8>>,
+    ranges: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+fn in_ranges(key: &[u8], ranges: &[(Vec<u8>, Vec<u8>)]) -> bool {
+    ranges
+        .iter()
+        .any(|(lower, upper)| lower.as_slice() <= key && key < upper.as_slice())
+}
+
+fn has_key_in_range<V>(map: &BTreeMap<Vec<u8>, V>, lower: &[u8], upper: &[u8]) -> bool {
+    map.range::<[u8], _>((Bound::Included(lower), Bound::Excluded(upper)))
+        .next()
+        .is_some()
+}
+
+fn remove_range(map: &mut MemMap, lower: &[u8], upper: &[u8]) {
+    let keys = map
+        .range::<_, [u8]>((Bound::Included(lower), Bound::Excluded(upper)))
+        .map(|(k, _)| k.clone())
+        .collect_vec();
+    for k in keys.iter() {
+        map.remove(k);
+    }
+}
+
+impl CommitRecord {
+    fn conflicts_with(&self, tx: &MemWriteTx<'_>, read_keys: &BTreeSet<Vec<u8>>) -> bool {
+        let touched = |key: &Vec<u8>| {
+            tx.delta.contains_key(key) || read_keys.contains(key) || in_ranges(key, &tx.del_ranges)
+        };
+        if self.keys.iter().any(touched) {
+            return true;
+        }
+        self.ranges.iter().any(|(lower, upper)| {
+            has_key_in_range(&tx.delta, lower, upper)
+                || read_keys
+                    .range::<[u8], _>((
+                        Bound::Included(lower.as_slice()),
+                        Bound::Excluded(upper.as_slice()),
+                    ))
+                    .next()
+                    .is_some()
+                || tx.del_ranges.iter().any(|(l, u)| {
+                    l.as_slice() < upper.as_slice() && lower.as_slice() < u.as_slice()
+                })
+        })
+    }
+}
+
+impl MemStorage {
+    /// Write a snapshot of the committed data to `writer`.
+    pub fn write_snapshot(&self, writer: impl Write) -> Result<()> {
+        let head = self.state.read().unwrap().head.clone();
+        write_snapshot(&head, writer)
+    }
+    /// Save a snapshot of the committed data to the file at `path`, replacing it atomically.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        let head = self.state.read().unwrap().head.clone();
+        save_snapshot(&head, path.as_ref())
+    }
+    /// Create a storage holding the data of a snapshot read from `reader`.
+    pub fn read_snapshot(reader: impl Read) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut header = [0u8; 16];
+        reader
+            .read_exact(&mut header)
+            .into_diagnostic()
+            .wrap_err("cannot read snapshot header")?;
+        if &header[..7] != SNAPSHOT_MAGIC {
+            bail!("data is not a snapshot of an in-memory database")
+        }
+        if header[7] != SNAPSHOT_VERSION {
+            bail!("unsupported snapshot format version {}", header[7])
+        }
+        let n_entries = u64::from_be_bytes(header[8..].try_into().unwrap());
+        let mut read_bytes = || -> Result<Vec<u8>> {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len).into_diagnostic()?;
+            let len = u32::from_be_bytes(len) as usize;
+            // the length is untrusted, so read incrementally instead of allocating it up front
+            let mut ret = vec![];
+            (&mut reader)
+                .take(len as u64)
+                .read_to_end(&mut ret)
+                .into_diagnostic()?;
+            if ret.len() != len {
+                bail!("expected {len} bytes, found {}", ret.len())
+            }
+            Ok(ret)
+        };
+        let mut head = MemMap::new();
+        for _ in 0..n_entries {
+            let key = read_bytes().wrap_err("snapshot is truncated")?;
+            let val = read_bytes().wrap_err("snapshot is truncated")?;
+            head.insert(key, val);
+        }
+        let ret = Self::default();
+        ret.state.write().unwrap().head = head;
+        Ok(ret)
+    }
+    /// Save a snapshot to `path` when the last handle to the storage is dropped.
+    pub fn save_snapshot_on_drop(&self, path: impl AsRef<Path>) {
+        self.state.write().unwrap().save_on_drop = Some(path.as_ref().to_path_buf());
+    }
+}
+
+fn write_snapshot(data: &MemMap, writer: impl Write) -> Result<()> {
+    let mut writer = BufWriter::new(writer);
+    let mut write_all = |bytes: &[u8]| writer.write_all(bytes).into_diagnostic();
+    write_all(SNAPSHOT_MAGIC)?;
+    write_all(&[SNAPSHOT_VERSION])?;
+    write_all(&(data.len() as u64).to_be_bytes())?;
+    for (k, v) in data.iter() {
+        write_all(&(k.len() as u32).to_be_bytes())?;
+        write_all(k)?;
+        write_all(&(v.len() as u32).to_be_bytes())?;
+        write_all(v)?;
+    }
+    writer.flush().into_diagnostic()
+}
+
+/// Writes to a temporary file first, so that a crash never leaves a partial snapshot at `path`.
+fn save_snapshot(data: &MemMap, path: &Path) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let file = File::create(&tmp_path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("when creating snapshot file {}", tmp_path.display()))?;
+    write_snapshot(data, &file)?;
+    file.sync_all().into_diagnostic()?;
+    fs::rename(&tmp_path, path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("when moving snapshot to {}", path.display()))
+}
+
+impl crate::Db<MemStorage> {
+    /// Write a snapshot of the database to `writer`, see [MemStorage::write_snapshot].
+    pub fn write_snapshot(&self, writer: impl Write) -> Result<()> {
+        self.db.write_snapshot(writer)
+    }
+    /// Save a snapshot of the database to `path`, see [MemStorage::save_snapshot].
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.db.save_snapshot(path)
+    }
 }
 
 impl<'s> Storage<'s> for MemStorage {
@@ -50,11 +296,18 @@ impl<'s> Storage<'s> for MemStorage {
 
     fn transact(&'s self, write: bool) -> Result<Self::Tx> {
         Ok(if write {
-            let wtr = self.store.write().unwrap();
-            MemTx::Writer(wtr, Default::default())
+            let mut state = self.state.write().unwrap();
+            let version = state.register_writer();
+            MemTx::Writer(MemWriteTx {
+                storage: self,
+                version,
+                data: state.head.clone(),
+                delta: Default::default(),
+                del_ranges: vec![],
+                read_keys: Default::default(),
+            })
         } else {
-            let rdr = self.store.read().unwrap();
-            MemTx::Reader(rdr)
+            MemTx::Reader(self.state.read().unwrap().head.clone())
         })
     }
 
@@ -66,32 +319,146 @@ impl<'s> Storage<'s> for MemStorage {
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
     ) -> Result<()> {
-        let mut store = self.store.write().unwrap();
+        let mut state = self.state.write().unwrap();
+        let mut keys = BTreeSet::new();
+        // values replaced so far, put back if the data turns out to be bad
+        let mut replaced = vec![];
         for pair in data {
-            let (k, v) = pair?;
-            store.insert(k, v);
+            match pair {
+                Ok((k, v)) => {
+                    if let Some(old) = state.head.insert(k.clone(), v) {
+                        replaced.push((k.clone(), old));
+                    }
+                    keys.insert(k);
+                }
+                Err(err) => {
+                    for k in &keys {
+                        state.head.remove(k);
+                    }
+                    state.head.extend(replaced);
+                    return Err(err);
+                }
+            }
         }
+        state.record_commit(keys, vec![]);
         Ok(())
     }
+
+    fn engine_stats(&'s self) -> Result<Vec<(String, DataValue)>> {
+        let state = self.state.read().unwrap();
+        let open_writers: usize = state.writers.values().sum();
+        Ok(vec![
+            (
+                "entries".to_string(),
+                DataValue::from(state.head.len() as i64),
+            ),
+            ("version".to_string(), DataValue::from(state.version as i64)),
+            (
+                "retained_commits".to_string(),
+                DataValue::from(state.history.len() as i64),
+            ),
+            (
+                "open_writers".to_string(),
+                DataValue::from(open_writers as i64),
+            ),
+        ])
+    }
 }
 
+/// Transaction of [MemStorage]
 pub enum MemTx<'s> {
-    Reader(ShardedLockReadGuard<'s, BTreeMap<Vec<u8>, Vec<u8>>>),
-    Writer(
-        ShardedLockWriteGuard<'s, BTreeMap<Vec<u8>, Vec<u8>>>,
-        BTreeMap<Vec<u8>, Option<Vec<u8>>>,
-    ),
+    /// A read transaction, holding the snapshot it reads from
+    Reader(MemMap),
+    /// A write transaction
+    Writer(MemWriteTx<'s>),
+}
+
+/// A write transaction of [MemStorage]
+pub struct MemWriteTx<'s> {
+    storage: &'s MemStorage,
+    /// The version of the snapshot the transaction started from
+    version: u64,
+    /// The snapshot, with the changes made by the transaction applied
+    data: MemMap,
+    /// The changes made by the transaction, `None` for deletions
+    delta: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    /// Ranges deleted by the transaction, applied before `delta` on commit
+    del_ranges: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Keys read for update
+    read_keys: Mutex<BTreeSet<Vec<u8>>>,
+}
+
+impl MemWriteTx<'_> {
+    fn note_read(&self, key: &[u8], for_update: bool) {
+        if for_update {
+            self.read_keys.lock().unwrap().insert(key.to_vec());
+        }
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let mut state = self.storage.state.write().unwrap();
+        let read_keys = std::mem::take(self.read_keys.get_mut().unwrap());
+        if self.delta.is_empty() && self.del_ranges.is_empty() {
+            return Ok(());
+        }
+        if state
+            .history
+            .iter()
+            .filter(|record| record.version > self.version)
+            .any(|record| record.conflicts_with(self, &read_keys))
+        {
+            bail!(WriteConflict)
+        }
+        let del_ranges = std::mem::take(&mut self.del_ranges);
+        let delta = std::mem::take(&mut self.delta);
+        for (lower, upper) in del_ranges.iter() {
+            remove_range(&mut state.head, lower, upper);
+        }
+        let mut keys = BTreeSet::new();
+        for (k, mv) in delta {
+            match mv {
+                None => {
+                    state.head.remove(&k);
+                }
+                Some(v) => {
+                    state.head.insert(k.clone(), v);
+                }
+            }
+            keys.insert(k);
+        }
+        state.record_commit(keys, del_ranges);
+        // later writes of this transaction are checked against commits made from now on
+        let old_version = self.version;
+        self.version = state.register_writer();
+        state.unregister_writer(old_version);
+        self.data = state.head.clone();
+        Ok(())
+    }
+}
+
+impl Drop for MemWriteTx<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.storage.state.write() {
+            state.unregister_writer(self.version);
+        }
+    }
+}
+
+impl<'s> MemTx<'s> {
+    fn data(&self) -> &MemMap {
+        match self {
+            MemTx::Reader(data) => data,
+            MemTx::Writer(tx) => &tx.data,
+        }
+    }
 }
 
 impl<'s> StoreTx<'s> for MemTx<'s> {
-    fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
-        Ok(match self {
-            MemTx::Reader(rdr) => rdr.get(key).cloned(),
-            MemTx::Writer(wtr, cache) => match cache.get(key) {
-                Some(r) => r.clone(),
-                None => wtr.get(key).cloned(),
-            },
-        })
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        if let MemTx::Writer(tx) = self {
+            tx.note_read(key, for_update);
+        }
+        Ok(self.data().get(key).cloned())
     }
 
     fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
@@ -99,8 +466,9 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
             MemTx::Reader(_) => {
                 bail!("write in read transaction")
             }
-            MemTx::Writer(_, cache) => {
-                cache.insert(key.to_vec(), Some(val.to_vec()));
+            MemTx::Writer(tx) => {
+                tx.data.insert(key.to_vec(), val.to_vec());
+                tx.delta.insert(key.to_vec(), Some(val.to_vec()));
                 Ok(())
             }
         }
@@ -119,8 +487,9 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
             MemTx::Reader(_) => {
                 bail!("write in read transaction")
             }
-            MemTx::Writer(_, cache) => {
-                cache.insert(key.to_vec(), None);
+            MemTx::Writer(tx) => {
+                tx.data.remove(key);
+                tx.delta.insert(key.to_vec(), None);
                 Ok(())
             }
         }
@@ -131,48 +500,35 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
             MemTx::Reader(_) => {
                 bail!("write in read transaction")
             }
-            MemTx::Writer(ref mut wtr, _) => {
-                let keys = wtr
-                    .range(lower.to_vec()..upper.to_vec())
-                    .map(|kv| kv.0.clone())
-                    .collect_vec();
-                for k in keys.iter() {
-                    wtr.remove(k);
+            MemTx::Writer(tx) => {
+                // only the data committed before is deleted, not the changes of this transaction
+                remove_range(&mut tx.data, lower, upper);
+                for (k, mv) in tx
+                    .delta
+                    .range::<[u8], _>((Bound::Included(lower), Bound::Excluded(upper)))
+                {
+                    if let Some(v) = mv {
+                        tx.data.insert(k.clone(), v.clone());
+                    }
                 }
+                tx.del_ranges.push((lower.to_vec(), upper.to_vec()));
             }
         }
 
         Ok(())
     }
 
-    fn exists(&self, key: &[u8], _for_update: bool) -> Result<bool> {
-        Ok(match self {
-            MemTx::Reader(rdr) => rdr.contains_key(key),
-            MemTx::Writer(wtr, cache) => match cache.get(key) {
-                Some(r) => r.is_some(),
-                None => wtr.contains_key(key),
-            },
-        })
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        if let MemTx::Writer(tx) = self {
+            tx.note_read(key, for_update);
+        }
+        Ok(self.data().contains_key(key))
     }
 
     fn commit(&mut self) -> Result<()> {
         match self {
             MemTx::Reader(_) => Ok(()),
-            MemTx::Writer(wtr, cached) => {
-                let mut cache = BTreeMap::default();
-                mem::swap(&mut cache, cached);
-                for (k, mv) in cache {
-                    match mv {
-                        None => {
-                            wtr.remove(&k);
-                        }
-                        Some(v) => {
-                            wtr.insert(k, v);
-                        }
-                    }
-                }
-                Ok(())
-            }
+            MemTx::Writer(tx) => tx.commit(),
         }
     }
 
@@ -184,18 +540,11 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     where
         's: 'a,
     {
-        match self {
-            MemTx::Reader(rdr) => Box::new(
-                rdr.range(lower.to_vec()..upper.to_vec())
-                    .map(|(k, v)| Ok(decode_tuple_from_kv(k, v, None))),
-            ),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIter {
-                change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
-                db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
-                change_cache: None,
-                db_cache: None,
-            }),
-        }
+        Box::new(
+            self.data()
+                .range(lower.to_vec()..upper.to_vec())
+                .map(|(k, v)| Ok(decode_tuple_from_kv(k, v, None))),
+        )
     }
 
     fn range_skip_scan_tuple<'a>(
@@ -204,28 +553,15 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
         upper: &[u8],
         valid_at: ValidityTs,
     ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
-        match self {
-            MemTx::Reader(stored) => Box::new(
-                SkipIterator {
-                    inner: stored,
-                    upper: upper.to_vec(),
-                    valid_at,
-                    next_bound: lower.to_vec(),
-                    size_hint: None,
-                }
-                .map(Ok),
-            ),
-            MemTx::Writer(stored, delta) => Box::new(
-                SkipDualIterator {
-                    stored,
-                    delta,
-                    upper: upper.to_vec(),
-                    valid_at,
-                    next_bound: lower.to_vec(),
-                }
-                .map(Ok),
-            ),
-        }
+        Box::new(
+            SnapshotSkipIterator {
+                inner: self.data(),
+                upper: upper.to_vec(),
+                valid_at,
+                next_bound: lower.to_vec(),
+            }
+            .map(Ok),
+        )
     }
 
     fn range_scan<'a>(
@@ -236,207 +572,25 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     where
         's: 'a,
     {
-        match self {
-            MemTx::Reader(rdr) => Box::new(
-                rdr.range(lower.to_vec()..upper.to_vec())
-                    .map(|(k, v)| Ok((k.clone(), v.clone()))),
-            ),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIterRaw {
-                change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
-                db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
-                change_cache: None,
-                db_cache: None,
-            }),
-        }
+        Box::new(
+            self.data()
+                .range(lower.to_vec()..upper.to_vec())
+                .map(|(k, v)| Ok((k.clone(), v.clone()))),
+        )
     }
 
     fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
     where
         's: 'a,
     {
-        Ok(match self {
-            MemTx::Reader(rdr) => rdr.range(lower.to_vec()..upper.to_vec()).count(),
-            MemTx::Writer(wtr, cache) => (CacheIterRaw {
-                change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
-                db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
-                change_cache: None,
-                db_cache: None,
-            })
-            .count(),
-        })
+        Ok(self.data().range(lower.to_vec()..upper.to_vec()).count())
     }
 
     fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
     where
         's: 'a,
     {
-        match self {
-            MemTx::Reader(rdr) => Box::new(rdr.iter().map(|(k, v)| Ok((k.clone(), v.clone())))),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIterRaw {
-                change_iter: cache.iter().fuse(),
-                db_iter: wtr.iter().fuse(),
-                change_cache: None,
-                db_cache: None,
-            }),
-        }
-    }
-}
-
-struct CacheIterRaw<'a, C, T>
-where
-    C: Iterator<Item = (&'a Vec<u8>, &'a Option<Vec<u8>>)> + 'a,
-    T: Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
-{
-    change_iter: C,
-    db_iter: T,
-    change_cache: Option<(&'a Vec<u8>, &'a Option<Vec<u8>>)>,
-    db_cache: Option<(&'a Vec<u8>, &'a Vec<u8>)>,
-}
-
-impl<'a, C, T> CacheIterRaw<'a, C, T>
-where
-    C: Iterator<Item = (&'a Vec<u8>, &'a Option<Vec<u8>>)> + 'a,
-    T: Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
-{
-    #[inline]
-    fn fill_cache(&mut self) -> Result<()> {
-        if self.change_cache.is_none() {
-            if let Some(kmv) = self.change_iter.next() {
-                self.change_cache = Some(kmv)
-            }
-        }
-
-        if self.db_cache.is_none() {
-            if let Some(kv) = self.db_iter.next() {
-                self.db_cache = Some(kv);
-            }
-        }
-
-        Ok(())
-    }
-
-    #[inline]
-    fn next_inner(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
-        loop {
-            self.fill_cache()?;
-            match (&self.change_cache, &self.db_cache) {
-                (None, None) => return Ok(None),
-                (Some(_), None) => {
-                    let (k, cv) = self.change_cache.take().unwrap();
-                    match cv {
-                        None => continue,
-                        Some(v) => return Ok(Some((k.clone(), v.clone()))),
-                    }
-                }
-                (None, Some(_)) => {
-                    let (k, v) = self.db_cache.take().unwrap();
-                    return Ok(Some((k.clone(), v.clone())));
-                }
-                (Some((ck, _)), Some((dk, _))) => match ck.cmp(dk) {
-                    Ordering::Less => {
-                        let (k, sv) = self.change_cache.take().unwrap();
-                        match sv {
-                            None => continue,
-                            Some(v) => return Ok(Some((k.clone(), v.clone()))),
-                        }
-                    }
-                    Ordering::Greater => {
-                        let (k, v) = self.db_cache.take().unwrap();
-                        return Ok(Some((k.clone(), v.clone())));
-                    }
-                    Ordering::Equal => {
-                        self.db_cache.take();
-                        continue;
-                    }
-                },
-            }
-        }
-    }
-}
-
-impl<'a, C, T> Iterator for CacheIterRaw<'a, C, T>
-where
-    C: Iterator<Item = (&'a Vec<u8>, &'a Option<Vec<u8>>)> + 'a,
-    T: Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
-{
-    type Item = Result<(Vec<u8>, Vec<u8>)>;
-
-    #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        swap_option_result(self.next_inner())
-    }
-}
-
-struct CacheIter<'a> {
-    change_iter: Fuse<Range<'a, Vec<u8>, Option<Vec<u8>>>>,
-    db_iter: Fuse<Range<'a, Vec<u8>, Vec<u8>>>,
-    change_cache: Option<(&'a Vec<u8>, &'a Option<Vec<u8>>)>,
-    db_cache: Option<(&'a Vec<u8>, &'a Vec<u8>)>,
-}
-
-impl CacheIter<'_> {
-    #[inline]
-    fn fill_cache(&mut self) -> Result<()> {
-        if self.change_cache.is_none() {
-            if let Some(kmv) = self.change_iter.next() {
-                self.change_cache = Some(kmv)
-            }
-        }
-
-        if self.db_cache.is_none() {
-            if let Some(kv) = self.db_iter.next() {
-                self.db_cache = Some(kv);
-            }
-        }
-
-        Ok(())
-    }
-
-    #[inline]
-    fn next_inner(&mut self) -> Result<Option<Tuple>> {
-        loop {
-            self.fill_cache()?;
-            match (&self.change_cache, &self.db_cache) {
-                (None, None) => return Ok(None),
-                (Some(_), None) => {
-                    let (k, cv) = self.change_cache.take().unwrap();
-                    match cv {
-                        None => continue,
-                        Some(v) => return Ok(Some(decode_tuple_from_kv(k, v, None))),
-                    }
-                }
-                (None, Some(_)) => {
-                    let (k, v) = self.db_cache.take().unwrap();
-                    return Ok(Some(decode_tuple_from_kv(k, v, None)));
-                }
-                (Some((ck, _)), Some((dk, _))) => match ck.cmp(dk) {
-                    Ordering::Less => {
-                        let (k, sv) = self.change_cache.take().unwrap();
-                        match sv {
-                            None => continue,
-                            Some(v) => return Ok(Some(decode_tuple_from_kv(k, v, None))),
-                        }
-                    }
-                    Ordering::Greater => {
-                        let (k, v) = self.db_cache.take().unwrap();
-                        return Ok(Some(decode_tuple_from_kv(k, v, None)));
-                    }
-                    Ordering::Equal => {
-                        self.db_cache.take();
-                        continue;
-                    }
-                },
-            }
-        }
-    }
-}
-
-impl Iterator for CacheIter<'_> {
-    type Item = Result<Tuple>;
-
-    #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        swap_option_result(self.next_inner())
+        Box::new(self.data().iter().map(|(k, v)| Ok((k.clone(), v.clone()))))
     }
 }
 
@@ -477,60 +631,25 @@ impl<'a> Iterator for SkipIterator<'a> {
     }
 }
 
-struct SkipDualIterator<'a> {
-    stored: &'a BTreeMap<Vec<u8>, Vec<u8>>,
-    delta: &'a BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+struct SnapshotSkipIterator<'a> {
+    inner: &'a MemMap,
     upper: Vec<u8>,
     valid_at: ValidityTs,
     next_bound: Vec<u8>,
 }
 
-impl<'a> Iterator for SkipDualIterator<'a> {
+impl<'a> Iterator for SnapshotSkipIterator<'a> {
     type Item = Tuple;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            let stored_nxt = self
-                .stored
-                .range::<Vec<u8>, (Bound<&Vec<u8>>, Bound<&Vec<u8>>)>((
-                    Bound::Included(&self.next_bound),
-                    Bound::Excluded(&self.upper),
-                ))
-                .next();
-            let delta_nxt = self
-                .delta
-                .range::<Vec<u8>, (Bound<&Vec<u8>>, Bound<&Vec<u8>>)>((
-                    Bound::Included(&self.next_bound),
-                    Bound::Excluded(&self.upper),
+            let (candidate_key, candidate_val) = self
+                .inner
+                .range::<_, [u8]>((
+                    Bound::Included(self.next_bound.as_slice()),
+                    Bound::Excluded(self.upper.as_slice()),
                 ))
-                .next();
-            let (candidate_key, candidate_val) = match (stored_nxt, delta_nxt) {
-                (None, None) => return None,
-                (None, Some((delta_key, maybe_delta_val))) => match maybe_delta_val {
-                    None => {
-                        let (_, nxt_seek) = check_key_for_validity(delta_key, self.valid_at, None);
-                        self.next_bound = nxt_seek;
-                        continue;
-                    }
-                    Some(delta_val) => (delta_key, delta_val),
-                },
-                (Some((stored_key, stored_val)), None) => (stored_key, stored_val),
-                (Some((stored_key, stored_val)), Some((delta_key, maybe_delta_val))) => {
-                    if stored_key < delta_key {
-                        (stored_key, stored_val)
-                    } else {
-                        match maybe_delta_val {
-                            None => {
-                                let (_, nxt_seek) =
-                                    check_key_for_validity(delta_key, self.valid_at, None);
-                                self.next_bound = nxt_seek;
-                                continue;
-                            }
-                            Some(delta_val) => (delta_key, delta_val),
-                        }
-                    }
-                }
-            };
+                .next()?;
             let (ret, nxt_bound) = check_key_for_validity(candidate_key, self.valid_at, None);
             self.next_bound = nxt_bound;
             if let Some(mut nk) = ret {
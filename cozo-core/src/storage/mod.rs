@@ -6,14 +6,21 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::path::Path;
+
 use itertools::Itertools;
 use miette::Result;
 
 use crate::data::tuple::Tuple;
-use crate::data::value::ValidityTs;
+use crate::data::value::{DataValue, ValidityTs};
 use crate::decode_tuple_from_kv;
+use crate::storage::encrypted::StorageCipher;
 
+pub(crate) mod encrypted;
 pub(crate) mod mem;
+pub(crate) mod mutation_log;
+#[cfg(feature = "storage-redb")]
+pub(crate) mod redb;
 #[cfg(feature = "storage-rocksdb")]
 pub(crate) mod rocks;
 #[cfg(feature = "storage-sled")]
@@ -42,11 +49,34 @@ pub trait Storage<'s>: Send + Sync + Clone {
 
     /// Put multiple key-value pairs into the database.
     /// No duplicate data will be sent, and the order data come in is strictly ascending.
-    /// There will be no other access to the database while this function is running.
+    /// The database may be in use while this function is running, but not the keys written.
     fn batch_put<'a>(
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
     ) -> Result<()>;
+
+    /// Write a consistent backup of the whole store into the directory `dir`, using a
+    /// mechanism native to the engine. Returns `false` if the engine has none.
+    fn native_backup(&'s self, _dir: &Path) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Approximate numbers of keys and of bytes in the key range, or `None` if the engine
+    /// cannot estimate them without scanning the range.
+    fn approximate_size(&'s self, _lower: &[u8], _upper: &[u8]) -> Result<Option<(u64, u64)>> {
+        Ok(None)
+    }
+
+    /// Engine-specific metrics, as pairs of names and values. Reported by `::stats`.
+    fn engine_stats(&'s self) -> Result<Vec<(String, DataValue)>> {
+        Ok(vec![])
+    }
+
+    /// The cipher values are encrypted with, if the engine encrypts them.
+    /// Backups of the database are encrypted with the same cipher.
+    fn cipher(&self) -> Option<&StorageCipher> {
+        None
+    }
 }
 
 /// Trait for the associated transaction type of a storage engine.
@@ -0,0 +1,359 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Archive of committed write transactions, for point-in-time recovery.
+//!
+//! The log is a directory of segment files. A new segment is started every time the log is
+//! opened, named after the time it was started so that segments sort in commit order.
+//! A segment is a sequence of records, one per committed transaction:
+//!
+//! * the length of the rest of the record, as a big-endian `u32`,
+//! * the commit timestamp in microseconds since the UNIX epoch, as a big-endian `i64`,
+//! * the operations of the transaction in the order they were made, each a one-byte tag
+//!   followed by its length-prefixed byte strings.
+//!
+//! For an encrypted database, the operations of a record are encrypted with the key of the
+//! database, with the timestamp as associated data, so that the log holds no plaintext.
+//!
+//! A record cut short by a crash is ignored when the log is read.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use itertools::Itertools;
+use miette::{bail, IntoDiagnostic, Result, WrapErr};
+
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::storage::encrypted::StorageCipher;
+use crate::storage::StoreTx;
+
+const SEGMENT_EXT: &str = "log";
+const TAG_PUT: u8 = 0;
+const TAG_DEL: u8 = 1;
+const TAG_DEL_RANGE: u8 = 2;
+
+/// A single mutation made by a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LogOp {
+    Put(Vec<u8>, Vec<u8>),
+    Del(Vec<u8>),
+    DelRange(Vec<u8>, Vec<u8>),
+}
+
+impl LogOp {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let push_bytes = |bytes: &[u8], buf: &mut Vec<u8>| {
+            buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+            buf.extend_from_slice(bytes);
+        };
+        match self {
+            LogOp::Put(key, val) => {
+                buf.push(TAG_PUT);
+                push_bytes(key, buf);
+                push_bytes(val, buf);
+            }
+            LogOp::Del(key) => {
+                buf.push(TAG_DEL);
+                push_bytes(key, buf);
+            }
+            LogOp::DelRange(lower, upper) => {
+                buf.push(TAG_DEL_RANGE);
+                push_bytes(lower, buf);
+                push_bytes(upper, buf);
+            }
+        }
+    }
+}
+
+/// Reads length-prefixed byte strings from an encoded record.
+struct RecordReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> RecordReader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.data.len() < n {
+            return None;
+        }
+        let (ret, rest) = self.data.split_at(n);
+        self.data = rest;
+        Some(ret)
+    }
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        let len = u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as usize;
+        Some(self.take(len)?.to_vec())
+    }
+    fn op(&mut self) -> Option<LogOp> {
+        Some(match self.take(1)?[0] {
+            TAG_PUT => LogOp::Put(self.bytes()?, self.bytes()?),
+            TAG_DEL => LogOp::Del(self.bytes()?),
+            TAG_DEL_RANGE => LogOp::DelRange(self.bytes()?, self.bytes()?),
+            _ => return None,
+        })
+    }
+}
+
+struct LogSegment {
+    file: File,
+    last_ts: i64,
+}
+
+/// An open mutation log, appended to by [LoggedTx] on commit.
+pub(crate) struct MutationLog {
+    segment: Mutex<LogSegment>,
+    cipher: Option<StorageCipher>,
+}
+
+fn now_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as i64
+}
+
+impl MutationLog {
+    /// Opens the log in `dir`, starting a new segment. Records are encrypted with `cipher`.
+    pub(crate) fn open(dir: impl AsRef<Path>, cipher: Option<StorageCipher>) -> Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("when creating mutation log directory {}", dir.display()))?;
+        let mut last_ts = now_micros();
+        let mut path = dir.join(format!("{:020}.{}", last_ts, SEGMENT_EXT));
+        while path.exists() {
+            last_ts += 1;
+            path = dir.join(format!("{:020}.{}", last_ts, SEGMENT_EXT));
+        }
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("when creating mutation log segment {}", path.display()))?;
+        Ok(Self {
+            segment: Mutex::new(LogSegment { file, last_ts }),
+            cipher,
+        })
+    }
+
+    /// Commits `tx` and appends its operations to the log, holding the log lock throughout
+    /// so that the order of the records is the order of the commits.
+    fn commit_and_append(&self, tx: &mut dyn StoreTx<'_>, ops: &[LogOp]) -> Result<()> {
+        let mut segment = self.segment.lock().unwrap();
+        tx.commit()?;
+        if ops.is_empty() {
+            return Ok(());
+        }
+        let ts = now_micros().max(segment.last_ts);
+        let mut encoded_ops = vec![];
+        for op in ops {
+            op.encode(&mut encoded_ops);
+        }
+        if let Some(cipher) = &self.cipher {
+            encoded_ops = cipher.encrypt(&ts.to_be_bytes(), &encoded_ops)?;
+        }
+        let mut record = vec![0; 4];
+        record.extend_from_slice(&ts.to_be_bytes());
+        record.extend_from_slice(&encoded_ops);
+        let len = (record.len() - 4) as u32;
+        record[..4].copy_from_slice(&len.to_be_bytes());
+        segment
+            .file
+            .write_all(&record)
+            .and_then(|_| segment.file.sync_data())
+            .into_diagnostic()
+            .wrap_err("transaction committed but could not be written to the mutation log")?;
+        segment.last_ts = ts;
+        Ok(())
+    }
+}
+
+/// Reads the records of the log in `dir` committed no later than `until` (microseconds since
+/// the UNIX epoch), in commit order, passing the operations of each to `f`.
+/// Records are decrypted with `cipher`, which must be the one the log was written with.
+pub(crate) fn replay_mutation_log(
+    dir: impl AsRef<Path>,
+    until: i64,
+    cipher: Option<&StorageCipher>,
+    mut f: impl FnMut(Vec<LogOp>) -> Result<()>,
+) -> Result<usize> {
+    let dir = dir.as_ref();
+    let segments: Vec<PathBuf> = fs::read_dir(dir)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("when reading mutation log directory {}", dir.display()))?
+        .map_ok(|entry| entry.path())
+        .filter_ok(|path| matches!(path.extension(), Some(ext) if ext == SEGMENT_EXT))
+        .try_collect()
+        .into_diagnostic()?;
+    let mut replayed = 0;
+    for path in segments.into_iter().sorted() {
+        let data = fs::read(&path).into_diagnostic()?;
+        let mut reader = RecordReader { data: &data };
+        while let Some(len) = reader.take(4) {
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            let record = match reader.take(len) {
+                Some(record) if len >= 8 => record,
+                // a record cut short by a crash, nothing after it was committed
+                _ => break,
+            };
+            let (ts_bytes, encoded_ops) = record.split_at(8);
+            let ts = i64::from_be_bytes(ts_bytes.try_into().unwrap());
+            if ts > until {
+                continue;
+            }
+            let decrypted;
+            let encoded_ops = match cipher {
+                None => encoded_ops,
+                Some(cipher) => {
+                    decrypted = cipher.decrypt(ts_bytes, encoded_ops)?;
+                    &decrypted
+                }
+            };
+            let mut ops_reader = RecordReader { data: encoded_ops };
+            let mut ops = vec![];
+            while !ops_reader.data.is_empty() {
+                match ops_reader.op() {
+                    Some(op) => ops.push(op),
+                    None => bail!("corrupt record in mutation log segment {}", path.display()),
+                }
+            }
+            f(ops)?;
+            replayed += 1;
+        }
+    }
+    Ok(replayed)
+}
+
+/// A write transaction that records its mutations, and appends them to a [MutationLog]
+/// when it commits.
+pub(crate) struct LoggedTx<'s> {
+    inner: Box<dyn StoreTx<'s> + 's>,
+    log: Arc<MutationLog>,
+    ops: Mutex<Vec<LogOp>>,
+}
+
+impl<'s> LoggedTx<'s> {
+    pub(crate) fn new(inner: Box<dyn StoreTx<'s> + 's>, log: Arc<MutationLog>) -> Self {
+        Self {
+            inner,
+            log,
+            ops: Default::default(),
+        }
+    }
+}
+
+impl<'s> StoreTx<'s> for LoggedTx<'s> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key, for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.multi_get(keys, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.put(key, val)?;
+        self.ops
+            .get_mut()
+            .unwrap()
+            .push(LogOp::Put(key.to_vec(), val.to_vec()));
+        Ok(())
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.par_put(key, val)?;
+        self.ops
+            .lock()
+            .unwrap()
+            .push(LogOp::Put(key.to_vec(), val.to_vec()));
+        Ok(())
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.del(key)?;
+        self.ops.get_mut().unwrap().push(LogOp::Del(key.to_vec()));
+        Ok(())
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.inner.par_del(key)?;
+        self.ops.lock().unwrap().push(LogOp::Del(key.to_vec()));
+        Ok(())
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.del_range_from_persisted(lower, upper)?;
+        self.ops
+            .get_mut()
+            .unwrap()
+            .push(LogOp::DelRange(lower.to_vec(), upper.to_vec()));
+        Ok(())
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let ops = std::mem::take(self.ops.get_mut().unwrap());
+        self.log.commit_and_append(&mut *self.inner, &ops)
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan_tuple(lower, upper)
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.inner.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan(lower, upper)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.total_scan()
+    }
+}
@@ -0,0 +1,364 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::VecDeque;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use itertools::Itertools;
+use miette::{bail, IntoDiagnostic, Result};
+use redb::{Database, ReadOnlyTable, ReadableTable, TableDefinition, WriteTransaction};
+
+use crate::data::tuple::{check_key_for_validity, Tuple};
+use crate::data::value::ValidityTs;
+use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::storage::{Storage, StoreTx};
+
+const TABLE: TableDefinition<'_, &[u8], &[u8]> = TableDefinition::new("cozo");
+
+/// Number of entries read at a time when scanning in a write transaction.
+const WRITE_SCAN_PAGE_SIZE: usize = 256;
+
+/// Creates a database object backed by [redb](https://www.redb.org/),
+/// an embedded store of copy-on-write B-trees written in pure Rust.
+/// Supports concurrent readers alongside a single writer, and time travel.
+pub fn new_cozo_redb(path: impl AsRef<Path>) -> Result<crate::Db<RedbStorage>> {
+    let ret = crate::Db::new(new_redb_storage(path)?)?;
+
+    ret.initialize()?;
+    Ok(ret)
+}
+
+pub(crate) fn new_redb_storage(path: impl AsRef<Path>) -> Result<RedbStorage> {
+    let db = Database::create(path).into_diagnostic()?;
+    let tx = db.begin_write().into_diagnostic()?;
+    tx.open_table(TABLE).into_diagnostic()?;
+    tx.commit().into_diagnostic()?;
+    Ok(RedbStorage { db: Arc::new(db) })
+}
+
+/// Storage engine using redb
+#[derive(Clone)]
+pub struct RedbStorage {
+    db: Arc<Database>,
+}
+
+impl Storage<'_> for RedbStorage {
+    type Tx = RedbTx;
+
+    fn storage_kind(&self) -> &'static str {
+        "redb"
+    }
+
+    fn transact(&self, write: bool) -> Result<Self::Tx> {
+        Ok(if write {
+            RedbTx::Writer(Box::new(Mutex::new(Some(
+                self.db.begin_write().into_diagnostic()?,
+            ))))
+        } else {
+            let tx = self.db.begin_read().into_diagnostic()?;
+            RedbTx::Reader(tx.open_table(TABLE).into_diagnostic()?)
+        })
+    }
+
+    fn range_compact(&self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn batch_put<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        let tx = self.db.begin_write().into_diagnostic()?;
+        {
+            let mut table = tx.open_table(TABLE).into_diagnostic()?;
+            for result in data {
+                let (key, val) = result?;
+                table
+                    .insert(key.as_slice(), val.as_slice())
+                    .into_diagnostic()?;
+            }
+        }
+        tx.commit().into_diagnostic()
+    }
+}
+
+/// A redb transaction. A read transaction sees a snapshot of the database and never blocks.
+/// Write transactions are serialized by redb, so they do not need conflict detection.
+pub enum RedbTx {
+    /// A read transaction
+    Reader(ReadOnlyTable<&'static [u8], &'static [u8]>),
+    /// A write transaction, `None` once committed
+    Writer(Box<Mutex<Option<WriteTransaction>>>),
+}
+
+impl RedbTx {
+    /// Runs `f` on the table of a write transaction. The table borrows the transaction,
+    /// so it is opened anew for every operation.
+    fn with_write_table<T>(
+        &self,
+        f: impl FnOnce(&mut redb::Table<'_, &'static [u8], &'static [u8]>) -> Result<T>,
+    ) -> Result<T> {
+        match self {
+            RedbTx::Reader(_) => bail!("write in a read transaction"),
+            RedbTx::Writer(tx) => {
+                let guard = tx.lock().unwrap();
+                let tx = match &*guard {
+                    None => bail!("transaction already committed"),
+                    Some(tx) => tx,
+                };
+                let mut table = tx.open_table(TABLE).into_diagnostic()?;
+                f(&mut table)
+            }
+        }
+    }
+
+    fn first_in_range(&self, lower: &[u8], upper: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        fn first(
+            table: &impl ReadableTable<&'static [u8], &'static [u8]>,
+            lower: &[u8],
+            upper: &[u8],
+        ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+            match table.range(lower..upper).into_diagnostic()?.next() {
+                None => Ok(None),
+                Some(res) => {
+                    let (k, v) = res.into_diagnostic()?;
+                    Ok(Some((k.value().to_vec(), v.value().to_vec())))
+                }
+            }
+        }
+        match self {
+            RedbTx::Reader(table) => first(table, lower, upper),
+            RedbTx::Writer(_) => self.with_write_table(|table| first(table, lower, upper)),
+        }
+    }
+
+    fn get_in_table(
+        table: &impl ReadableTable<&'static [u8], &'static [u8]>,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(table
+            .get(key)
+            .into_diagnostic()?
+            .map(|val| val.value().to_vec()))
+    }
+}
+
+impl<'s> StoreTx<'s> for RedbTx {
+    fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
+        match self {
+            RedbTx::Reader(table) => Self::get_in_table(table, key),
+            RedbTx::Writer(_) => self.with_write_table(|table| Self::get_in_table(table, key)),
+        }
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.with_write_table(|table| {
+            table.insert(key, val).into_diagnostic()?;
+            Ok(())
+        })
+    }
+
+    fn supports_par_put(&self) -> bool {
+        true
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.with_write_table(|table| {
+            table.insert(key, val).into_diagnostic()?;
+            Ok(())
+        })
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.par_del(key)
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.with_write_table(|table| {
+            table.remove(key).into_diagnostic()?;
+            Ok(())
+        })
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.with_write_table(|table| {
+            table
+                .retain_in(lower..upper, |_, _| false)
+                .into_diagnostic()
+        })
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        Ok(self.get(key, for_update)?.is_some())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        match self {
+            RedbTx::Reader(_) => Ok(()),
+            RedbTx::Writer(tx) => match tx.get_mut().unwrap().take() {
+                None => Ok(()),
+                Some(tx) => tx.commit().into_diagnostic(),
+            },
+        }
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(
+            self.range_scan(lower, upper)
+                .map_ok(|(k, v)| decode_tuple_from_kv(&k, &v, None)),
+        )
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        Box::new(RedbSkipIter {
+            tx: self,
+            upper: upper.to_vec(),
+            valid_at,
+            next_bound: lower.to_vec(),
+        })
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        match self {
+            RedbTx::Reader(table) => match table.range(lower..upper) {
+                Ok(it) => Box::new(it.map(|res| {
+                    let (k, v) = res.into_diagnostic()?;
+                    Ok((k.value().to_vec(), v.value().to_vec()))
+                })),
+                Err(err) => Box::new(std::iter::once(Err(err).into_diagnostic())),
+            },
+            RedbTx::Writer(_) => Box::new(RedbPagedIter {
+                tx: self,
+                lower: Bound::Included(lower.to_vec()),
+                upper: upper.to_vec(),
+                page: Default::default(),
+                exhausted: false,
+            }),
+        }
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        let mut count = 0;
+        for res in self.range_scan(lower, upper) {
+            res?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.range_scan(&[], &[0xFF])
+    }
+}
+
+/// Scan in a write transaction. The table cannot be kept open across calls, so the scan
+/// proceeds by pages, each starting after the last key of the previous one.
+struct RedbPagedIter<'a> {
+    tx: &'a RedbTx,
+    lower: Bound<Vec<u8>>,
+    upper: Vec<u8>,
+    page: VecDeque<(Vec<u8>, Vec<u8>)>,
+    exhausted: bool,
+}
+
+impl RedbPagedIter<'_> {
+    fn fetch_page(&mut self) -> Result<()> {
+        let lower = match &self.lower {
+            Bound::Included(k) => Bound::Included(k.as_slice()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_slice()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let bounds = (lower, Bound::Excluded(self.upper.as_slice()));
+        let page: Vec<_> = self.tx.with_write_table(|table| {
+            table
+                .range::<&[u8]>(bounds)
+                .into_diagnostic()?
+                .take(WRITE_SCAN_PAGE_SIZE)
+                .map(|res| {
+                    let (k, v) = res.into_diagnostic()?;
+                    Ok((k.value().to_vec(), v.value().to_vec()))
+                })
+                .try_collect()
+        })?;
+        if page.len() < WRITE_SCAN_PAGE_SIZE {
+            self.exhausted = true;
+        }
+        if let Some((last_key, _)) = page.last() {
+            self.lower = Bound::Excluded(last_key.clone());
+        }
+        self.page = page.into();
+        Ok(())
+    }
+}
+
+impl Iterator for RedbPagedIter<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.exhausted {
+            if let Err(err) = self.fetch_page() {
+                self.exhausted = true;
+                return Some(Err(err));
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
+
+struct RedbSkipIter<'a> {
+    tx: &'a RedbTx,
+    upper: Vec<u8>,
+    valid_at: ValidityTs,
+    next_bound: Vec<u8>,
+}
+
+impl Iterator for RedbSkipIter<'_> {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, val) = match self.tx.first_in_range(&self.next_bound, &self.upper) {
+                Ok(kv) => kv?,
+                Err(err) => return Some(Err(err)),
+            };
+            let (ret, next_bound) = check_key_for_validity(&key, self.valid_at, None);
+            self.next_bound = next_bound;
+            if let Some(mut tuple) = ret {
+                extend_tuple_from_v(&mut tuple, &val);
+                return Some(Ok(tuple));
+            }
+        }
+    }
+}
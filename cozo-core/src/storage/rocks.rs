@@ -8,29 +8,62 @@
 
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use log::info;
-use miette::{miette, IntoDiagnostic, Result, WrapErr};
+use miette::{bail, miette, IntoDiagnostic, Result, WrapErr};
 
-use cozorocks::{DbBuilder, DbIter, RocksDb, Tx};
+use cozorocks::{restore_from_backup, DbBuilder, DbIter, RocksDb, SstWriter, Tx};
 
-use crate::data::tuple::{check_key_for_validity, Tuple};
-use crate::data::value::ValidityTs;
+use crate::data::tuple::{check_key_for_validity, Tuple, TupleT};
+use crate::data::value::{DataValue, ValidityTs};
 use crate::runtime::db::{BadDbInit, DbManifest};
-use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v, RelationId};
 use crate::storage::{Storage, StoreTx};
 use crate::utils::swap_option_result;
 use crate::Db;
 
 const KEY_PREFIX_LEN: usize = 9;
 const CURRENT_STORAGE_VERSION: u64 = 3;
+/// Size of the SST files written by [RocksDbStorage::batch_put].
+const SST_FILE_BYTES: usize = 256 << 20;
 
 /// Creates a RocksDB database object.
 /// This is currently the fastest persistent storage and it can
 /// sustain huge concurrency.
 /// Supports concurrent readers and writers.
 pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
-    let builder = DbBuilder::default().path(path.as_ref());
+    new_cozo_rocksdb_with_options(path, RocksDbOptions::default())
+}
+
+/// Options of the RocksDB storage engine, given in the options of
+/// [DbInstance::new](crate::DbInstance::new).
+/// Tuning options are read from the file `options` in the database directory instead.
+#[derive(Debug, Clone, Default, serde_derive::Deserialize)]
+#[serde(default)]
+pub struct RocksDbOptions {
+    /// Collect statistics, for the block cache hit rate reported by `::stats`.
+    /// This costs some performance, so it is off by default.
+    pub statistics: bool,
+}
+
+/// Creates a RocksDB database object with the given options.
+pub fn new_cozo_rocksdb_with_options(
+    path: impl AsRef<Path>,
+    options: RocksDbOptions,
+) -> Result<Db<RocksDbStorage>> {
+    let ret = Db::new(new_rocksdb_storage(path, options)?)?;
+    ret.initialize()?;
+    Ok(ret)
+}
+
+pub(crate) fn new_rocksdb_storage(
+    path: impl AsRef<Path>,
+    options: RocksDbOptions,
+) -> Result<RocksDbStorage> {
+    let builder = DbBuilder::default()
+        .path(path.as_ref())
+        .enable_statistics(options.statistics);
     fs::create_dir_all(path.as_ref()).map_err(|err| {
         BadDbInit(format!(
             "cannot create directory {}: {}",
@@ -104,10 +137,52 @@ pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
         .options_path(options_path);
 
     let db = db_builder.build()?;
+    Ok(RocksDbStorage::new(db))
+}
 
-    let ret = Db::new(RocksDbStorage::new(db))?;
-    ret.initialize()?;
-    Ok(ret)
+/// Creates a RocksDB database at `path` from the latest backup in `backup_dir`,
+/// written by [Db::backup_db_native] of a RocksDB database.
+/// This copies the backup files directly and is much faster than restoring into an existing
+/// database with [Db::restore_backup].
+pub fn new_cozo_rocksdb_from_backup(
+    backup_dir: impl AsRef<Path>,
+    path: impl AsRef<Path>,
+) -> Result<Db<RocksDbStorage>> {
+    restore_rocksdb_backup(backup_dir, &path)?;
+    new_cozo_rocksdb(path)
+}
+
+/// Restores the files of the latest backup in `backup_dir` as a new database at `path`.
+pub(crate) fn restore_rocksdb_backup(
+    backup_dir: impl AsRef<Path>,
+    path: impl AsRef<Path>,
+) -> Result<()> {
+    let mut manifest_path = PathBuf::from(path.as_ref());
+    manifest_path.push("manifest");
+    if manifest_path.exists() {
+        bail!(BadDbInit(format!(
+            "cannot restore backup: a database already exists at {}",
+            path.as_ref().to_string_lossy()
+        )));
+    }
+    let mut store_path = PathBuf::from(path.as_ref());
+    store_path.push("data");
+    fs::create_dir_all(&store_path).map_err(|err| {
+        BadDbInit(format!(
+            "cannot create directory {}: {}",
+            store_path.to_string_lossy(),
+            err
+        ))
+    })?;
+    let backup_dir = backup_dir
+        .as_ref()
+        .to_str()
+        .ok_or_else(|| miette!("bad path name"))?;
+    let store_path = store_path
+        .to_str()
+        .ok_or_else(|| miette!("bad path name"))?;
+    restore_from_backup(backup_dir, store_path)?;
+    Ok(())
 }
 
 /// RocksDB storage engine
@@ -120,6 +195,63 @@ impl RocksDbStorage {
     pub(crate) fn new(db: RocksDb) -> Self {
         Self { db }
     }
+    /// Writes `data`, in ascending key order, to SST files of at most about `file_bytes` each,
+    /// then ingests them all in one atomic operation. Nothing is ingested if `data` has an error.
+    pub(crate) fn ingest_sorted<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+        file_bytes: usize,
+    ) -> Result<()> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        // the files are moved into the database on ingestion, so they are written in the same
+        // directory to be sure that they are on the same filesystem
+        let dir = PathBuf::from(self.db.db_path()).join(format!("bulk-{nanos}"));
+        fs::create_dir_all(&dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("when creating directory {}", dir.display()))?;
+        let res = self.write_and_ingest_ssts(&dir, data, file_bytes);
+        let _ = fs::remove_dir_all(&dir);
+        res
+    }
+    fn write_and_ingest_ssts<'a>(
+        &'a self,
+        dir: &Path,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+        file_bytes: usize,
+    ) -> Result<()> {
+        let mut paths = vec![];
+        let mut writer: Option<SstWriter> = None;
+        let mut written = 0;
+        for result in data {
+            let (key, val) = result?;
+            let sst = match &mut writer {
+                Some(sst) => sst,
+                None => {
+                    let path = dir.join(format!("{}.sst", paths.len()));
+                    let path = path.to_str().ok_or_else(|| miette!("bad path name"))?;
+                    paths.push(path.to_string());
+                    writer.insert(self.db.get_sst_writer(path)?)
+                }
+            };
+            sst.put(&key, &val)?;
+            written += key.len() + val.len();
+            if written >= file_bytes {
+                sst.finish()?;
+                writer = None;
+                written = 0;
+            }
+        }
+        if let Some(mut sst) = writer {
+            sst.finish()?;
+        }
+        if !paths.is_empty() {
+            self.db.ingest_sst_files(&paths)?;
+        }
+        Ok(())
+    }
 }
 
 impl Storage<'_> for RocksDbStorage {
@@ -142,14 +274,85 @@ impl Storage<'_> for RocksDbStorage {
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
     ) -> Result<()> {
-        for result in data {
-            let (key, val) = result?;
-            self.db.raw_put(&key, &val)?;
+        self.ingest_sorted(data, SST_FILE_BYTES)
+    }
+
+    fn native_backup(&self, dir: &Path) -> Result<bool> {
+        let dir = dir.to_str().ok_or_else(|| miette!("bad path name"))?;
+        self.db.create_backup(dir)?;
+        Ok(true)
+    }
+
+    fn approximate_size(&self, lower: &[u8], upper: &[u8]) -> Result<Option<(u64, u64)>> {
+        let n_bytes = self.db.approximate_size(lower, upper);
+        // RocksDB only estimates the number of keys of the whole database,
+        // which is apportioned to the range by its share of the bytes
+        let total_keys = self
+            .db
+            .int_property("rocksdb.estimate-num-keys")
+            .unwrap_or(0);
+        let total_bytes = self.db.approximate_size(
+            &Tuple::default().encode_as_key(RelationId(0)),
+            &vec![DataValue::Bot].encode_as_key(RelationId(u64::MAX)),
+        );
+        let n_keys = if total_bytes == 0 {
+            0
+        } else {
+            (n_bytes as f64 / total_bytes as f64 * total_keys as f64).round() as u64
+        };
+        Ok(Some((n_keys, n_bytes)))
+    }
+
+    fn engine_stats(&self) -> Result<Vec<(String, DataValue)>> {
+        let mut ret = vec![];
+        for prop in ROCKSDB_STATS_PROPERTIES {
+            if let Some(v) = self.db.int_property(prop) {
+                let name = prop.strip_prefix("rocksdb.").unwrap_or(prop);
+                ret.push((name.to_string(), DataValue::from(v as i64)));
+            }
         }
-        Ok(())
+        let (hits, misses, hit_rate) = match self.db.block_cache_stats() {
+            None => {
+                let unavailable = DataValue::from("unavailable");
+                (unavailable.clone(), unavailable.clone(), unavailable)
+            }
+            Some((hits, misses)) => {
+                let hit_rate = if hits + misses == 0 {
+                    DataValue::Null
+                } else {
+                    DataValue::from(hits as f64 / (hits + misses) as f64)
+                };
+                (
+                    DataValue::from(hits as i64),
+                    DataValue::from(misses as i64),
+                    hit_rate,
+                )
+            }
+        };
+        ret.push(("block-cache-hits".to_string(), hits));
+        ret.push(("block-cache-misses".to_string(), misses));
+        ret.push(("block-cache-hit-rate".to_string(), hit_rate));
+        Ok(ret)
     }
 }
 
+/// Integer properties of the database reported by `::stats`.
+const ROCKSDB_STATS_PROPERTIES: &[&str] = &[
+    "rocksdb.estimate-num-keys",
+    "rocksdb.total-sst-files-size",
+    "rocksdb.live-sst-files-size",
+    "rocksdb.cur-size-all-mem-tables",
+    "rocksdb.block-cache-usage",
+    "rocksdb.block-cache-pinned-usage",
+    "rocksdb.estimate-pending-compaction-bytes",
+    "rocksdb.compaction-pending",
+    "rocksdb.num-running-compactions",
+    "rocksdb.num-running-flushes",
+    "rocksdb.num-immutable-mem-table",
+    "rocksdb.actual-delayed-write-rate",
+    "rocksdb.is-write-stopped",
+];
+
 pub struct RocksDbTx {
     db_tx: Tx,
 }
@@ -25,13 +25,17 @@ use crate::utils::{swap_option_result, TempCollector};
 /// You should use [`new_cozo_rocksdb`](crate::new_cozo_rocksdb) or
 /// [`new_cozo_sqlite`](crate::new_cozo_sqlite) instead.
 pub fn new_cozo_sled(path: impl AsRef<Path>) -> Result<crate::Db<SledStorage>> {
-    let db = sled::open(path).into_diagnostic()?;
-    let ret = crate::Db::new(SledStorage { db })?;
+    let ret = crate::Db::new(new_sled_storage(path)?)?;
 
     ret.initialize()?;
     Ok(ret)
 }
 
+pub(crate) fn new_sled_storage(path: impl AsRef<Path>) -> Result<SledStorage> {
+    let db = sled::open(path).into_diagnostic()?;
+    Ok(SledStorage { db })
+}
+
 /// Storage engine using Sled
 #[derive(Clone)]
 pub struct SledStorage {
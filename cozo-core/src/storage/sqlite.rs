@@ -7,16 +7,14 @@
  */
 
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 use ::sqlite::Connection;
-use crossbeam::sync::{ShardedLock, ShardedLockReadGuard, ShardedLockWriteGuard};
-use either::{Either, Left, Right};
 use miette::{bail, miette, IntoDiagnostic, Result};
 use sqlite::{ConnectionWithFullMutex, State, Statement};
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
-use crate::data::value::ValidityTs;
+use crate::data::value::{DataValue, ValidityTs};
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
 use crate::storage::{Storage, StoreTx};
 use crate::utils::swap_option_result;
@@ -24,21 +22,119 @@ use crate::utils::swap_option_result;
 /// The Sqlite storage engine
 #[derive(Clone)]
 pub struct SqliteStorage {
-    lock: Arc<ShardedLock<()>>,
+    write_lock: Arc<Mutex<()>>,
     name: PathBuf,
+    options: Arc<SqliteOptions>,
     pool: Arc<Mutex<Vec<ConnectionWithFullMutex>>>,
 }
 
+/// Options of the Sqlite storage engine, given in the options of
+/// [DbInstance::new](crate::DbInstance::new).
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+#[serde(default)]
+pub struct SqliteOptions {
+    /// The journal mode of the database file, `wal` by default.
+    /// In WAL mode, readers and the writer do not block each other.
+    pub journal_mode: String,
+    /// The `synchronous` pragma: `off`, `normal`, `full` or `extra`.
+    /// Left to the Sqlite default if not given.
+    pub synchronous: Option<String>,
+    /// The `cache_size` pragma, in pages if positive and in KiB if negative.
+    pub cache_size: Option<i64>,
+    /// The `mmap_size` pragma, the number of bytes of the file to access through memory mapping.
+    pub mmap_size: Option<i64>,
+    /// How many milliseconds to wait for a lock held by another process before giving up.
+    pub busy_timeout: usize,
+    /// The maximum number of idle connections kept open for later transactions.
+    pub max_idle_connections: usize,
+    /// Open an existing database for reading only, e.g. to replicate from it while another
+    /// process writes to it. The file is neither created nor modified, its journal mode is
+    /// left as it is, and the database is made read-only with [crate::Db::set_read_only].
+    pub read_only: bool,
+}
+
+impl Default for SqliteOptions {
+    fn default() -> Self {
+        Self {
+            journal_mode: "wal".to_string(),
+            synchronous: None,
+            cache_size: None,
+            mmap_size: None,
+            busy_timeout: 5000,
+            max_idle_connections: 64,
+            read_only: false,
+        }
+    }
+}
+
+impl SqliteOptions {
+    /// The pragmas are interpolated into statements, so only known values get through.
+    fn validate(&self) -> Result<()> {
+        let journal_mode = self.journal_mode.to_lowercase();
+        if !["delete", "truncate", "persist", "memory", "wal", "off"].contains(&&*journal_mode) {
+            bail!("invalid sqlite journal mode '{}'", self.journal_mode)
+        }
+        if let Some(synchronous) = &self.synchronous {
+            if !["off", "normal", "full", "extra"].contains(&&*synchronous.to_lowercase()) {
+                bail!("invalid sqlite synchronous setting '{}'", synchronous)
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Create a sqlite backed database.
-/// Supports concurrent readers but only a single writer.
+/// Readers run concurrently with a single writer.
 ///
 /// You must provide a disk-based path: `:memory:` is not OK.
 /// If you want a pure memory storage, use [`new_cozo_mem`](crate::new_cozo_mem).
 pub fn new_cozo_sqlite(path: impl AsRef<Path>) -> Result<crate::Db<SqliteStorage>> {
+    new_cozo_sqlite_with_options(path, SqliteOptions::default())
+}
+
+/// Create a sqlite backed database with the given options.
+pub fn new_cozo_sqlite_with_options(
+    path: impl AsRef<Path>,
+    options: SqliteOptions,
+) -> Result<crate::Db<SqliteStorage>> {
+    let read_only = options.read_only;
+    let ret = crate::Db::new(new_sqlite_storage(path, options)?)?;
+
+    ret.initialize()?;
+    ret.set_read_only(read_only);
+    Ok(ret)
+}
+
+pub(crate) fn new_sqlite_storage(
+    path: impl AsRef<Path>,
+    options: SqliteOptions,
+) -> Result<SqliteStorage> {
     if path.as_ref().to_str() == Some("") {
         bail!("empty path for sqlite storage")
     }
-    let conn = Connection::open_with_full_mutex(&path).into_diagnostic()?;
+    options.validate()?;
+    if options.read_only && !path.as_ref().is_file() {
+        bail!(
+            "cannot open {} for reading: no such database file",
+            path.as_ref().display()
+        )
+    }
+    let ret = SqliteStorage {
+        write_lock: Default::default(),
+        name: PathBuf::from(path.as_ref()),
+        options: Arc::new(options),
+        pool: Default::default(),
+    };
+    let conn = ret.open_connection()?;
+    if ret.options.read_only {
+        ret.pool.lock().unwrap().push(conn);
+        return Ok(ret);
+    }
+    conn.execute(format!(
+        "pragma journal_mode = {};",
+        ret.options.journal_mode
+    ))
+    .into_diagnostic()?;
     let query = r#"
         create table if not exists cozo
         (
@@ -48,38 +144,72 @@ pub fn new_cozo_sqlite(path: impl AsRef<Path>) -> Result<crate::Db<SqliteStorage
     "#;
     let mut statement = conn.prepare(query).unwrap();
     while statement.next().into_diagnostic()? != State::Done {}
+    drop(statement);
+    ret.pool.lock().unwrap().push(conn);
 
-    let ret = crate::Db::new(SqliteStorage {
-        lock: Default::default(),
-        name: PathBuf::from(path.as_ref()),
-        pool: Default::default(),
-    })?;
-
-    ret.initialize()?;
     Ok(ret)
 }
 
+/// Storage for the Sqlite files of backups, which are kept in a single file.
+pub(crate) fn new_sqlite_backup_storage(path: impl AsRef<Path>) -> Result<SqliteStorage> {
+    new_sqlite_storage(
+        path,
+        SqliteOptions {
+            journal_mode: "delete".to_string(),
+            ..Default::default()
+        },
+    )
+}
+
+impl SqliteStorage {
+    fn open_connection(&self) -> Result<ConnectionWithFullMutex> {
+        let mut conn = Connection::open_with_full_mutex(&self.name).into_diagnostic()?;
+        conn.set_busy_timeout(self.options.busy_timeout)
+            .into_diagnostic()?;
+        if let Some(synchronous) = &self.options.synchronous {
+            conn.execute(format!("pragma synchronous = {synchronous};"))
+                .into_diagnostic()?;
+        }
+        if let Some(cache_size) = self.options.cache_size {
+            conn.execute(format!("pragma cache_size = {cache_size};"))
+                .into_diagnostic()?;
+        }
+        if let Some(mmap_size) = self.options.mmap_size {
+            conn.execute(format!("pragma mmap_size = {mmap_size};"))
+                .into_diagnostic()?;
+        }
+        if self.options.read_only {
+            conn.execute("pragma query_only = true;")
+                .into_diagnostic()?;
+        }
+        Ok(conn)
+    }
+}
+
 impl<'s> Storage<'s> for SqliteStorage {
     type Tx = SqliteTx<'s>;
 
     fn transact(&'s self, write: bool) -> Result<Self::Tx> {
+        // writes to a read-only database are rejected by Sqlite, there is no lock to take
+        let write = write && !self.options.read_only;
         let conn = {
             match self.pool.lock().unwrap().pop() {
-                None => Connection::open_with_full_mutex(&self.name).into_diagnostic()?,
+                None => self.open_connection()?,
                 Some(conn) => conn,
             }
         };
-        let lock = if write {
-            Right(self.lock.write().unwrap())
+        // Sqlite allows a single writer, which would get a busy error instead of waiting
+        // when another one commits, so the writers of this process take turns.
+        let write_lock = if write {
+            Some(self.write_lock.lock().unwrap())
         } else {
-            Left(self.lock.read().unwrap())
+            None
         };
-        if write {
-            let mut stmt = conn.prepare("begin;").into_diagnostic()?;
-            while stmt.next().into_diagnostic()? != State::Done {}
-        }
+        // Readers also run in a transaction, to read from a consistent snapshot.
+        let begin = if write { "begin immediate;" } else { "begin;" };
+        conn.execute(begin).into_diagnostic()?;
         Ok(SqliteTx {
-            lock,
+            write_lock,
             storage: self,
             conn: Some(conn),
             stmts: [
@@ -111,13 +241,41 @@ impl<'s> Storage<'s> for SqliteStorage {
         Ok(())
     }
 
+    fn engine_stats(&'s self) -> Result<Vec<(String, DataValue)>> {
+        let conn = {
+            match self.pool.lock().unwrap().pop() {
+                None => self.open_connection()?,
+                Some(conn) => conn,
+            }
+        };
+        let mut ret = vec![];
+        for pragma in ["page_count", "page_size", "freelist_count", "cache_size"] {
+            let mut statement = conn
+                .prepare(format!("pragma {pragma};"))
+                .into_diagnostic()?;
+            if statement.next().into_diagnostic()? == State::Row {
+                let v = statement.read::<i64, _>(0).into_diagnostic()?;
+                ret.push((pragma.to_string(), DataValue::from(v)));
+            }
+        }
+        ret.push((
+            "journal_mode".to_string(),
+            DataValue::from(&self.options.journal_mode as &str),
+        ));
+        let mut pool = self.pool.lock().unwrap();
+        if pool.len() < self.options.max_idle_connections {
+            pool.push(conn)
+        }
+        Ok(ret)
+    }
+
     fn storage_kind(&self) -> &'static str {
         "sqlite"
     }
 }
 
 pub struct SqliteTx<'a> {
-    lock: Either<ShardedLockReadGuard<'a, ()>, ShardedLockWriteGuard<'a, ()>>,
+    write_lock: Option<MutexGuard<'a, ()>>,
     storage: &'a SqliteStorage,
     conn: Option<ConnectionWithFullMutex>,
     stmts: [Mutex<Option<Statement<'a>>>; N_CACHED_QUERIES],
@@ -148,15 +306,21 @@ const COUNT_RANGE_QUERY: usize = 6;
 
 impl Drop for SqliteTx<'_> {
     fn drop(&mut self) {
-        if let Right(ShardedLockWriteGuard { .. }) = self.lock {
-            if !self.committed {
-                let query = r#"rollback;"#;
-                let _ = self.conn.as_ref().unwrap().execute(query);
+        // the cached statements must be finalized before the connection is reused
+        for stmt in self.stmts.iter_mut() {
+            stmt.get_mut().unwrap().take();
+        }
+        let conn = self.conn.take().unwrap();
+        if !self.committed {
+            let query = r#"rollback;"#;
+            if conn.execute(query).is_err() {
+                return;
             }
         }
         let mut pool = self.storage.pool.lock().unwrap();
-        let conn = self.conn.take().unwrap();
-        pool.push(conn)
+        if pool.len() < self.storage.options.max_idle_connections {
+            pool.push(conn)
+        }
     }
 }
 
@@ -256,7 +420,7 @@ impl<'s> StoreTx<'s> for SqliteTx<'s> {
     }
 
     fn commit(&mut self) -> Result<()> {
-        if let Right(ShardedLockWriteGuard { .. }) = self.lock {
+        if self.write_lock.is_some() {
             if !self.committed {
                 let query = r#"commit;"#;
                 let mut statement = self.conn.as_ref().unwrap().prepare(query).unwrap();
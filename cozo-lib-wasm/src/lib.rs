@@ -36,6 +36,20 @@ impl CozoDb {
         let db = DbInstance::new("mem", "", "").unwrap();
         Self { db }
     }
+    pub fn new_from_snapshot(snapshot: &[u8]) -> Result<CozoDb, String> {
+        utils::set_panic_hook();
+        let db = new_cozo_mem_from_snapshot(snapshot).map_err(|err| err.to_string())?;
+        Ok(Self {
+            db: DbInstance::Mem(db),
+        })
+    }
+    pub fn snapshot(&self) -> Result<Vec<u8>, String> {
+        let mut ret = vec![];
+        self.db
+            .write_snapshot(&mut ret)
+            .map_err(|err| err.to_string())?;
+        Ok(ret)
+    }
     pub fn run(&self, script: &str, params: &str, immutable: bool) -> String {
         self.db.run_script_str(script, params, immutable)
     }
@@ -119,6 +119,11 @@ shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
         options.prefix_extractor.reset(NewFixedPrefixTransform(opts.fixed_prefix_extractor_len));
     }
     options.create_missing_column_families = true;
+    if (opts.enable_statistics) {
+        // tickers only, for the block cache hit rate reported in the stats
+        options.statistics = CreateDBStatistics();
+        options.statistics->set_stats_level(StatsLevel::kExceptTimers);
+    }
 
     shared_ptr <RocksDbBridge> db = make_shared<RocksDbBridge>();
 
@@ -150,3 +155,16 @@ RocksDbBridge::~RocksDbBridge() {
         }
     }
 }
+
+void restore_from_backup(rust::Str backup_dir, rust::Str db_dir, RocksDbStatus &status) {
+    BackupEngineReadOnly *backup_engine;
+    string backup_dir_(backup_dir);
+    string db_dir_(db_dir);
+    auto s = BackupEngineReadOnly::Open(Env::Default(), BackupEngineOptions(backup_dir_), &backup_engine);
+    if (!s.ok()) {
+        write_status(s, status);
+        return;
+    }
+    unique_ptr<BackupEngineReadOnly> engine(backup_engine);
+    write_status(engine->RestoreDBFromLatestBackup(db_dir_, db_dir_), status);
+}
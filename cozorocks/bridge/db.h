@@ -61,6 +61,18 @@ struct RocksDbBridge {
         return sst_file_writer;
     }
 
+    inline void create_backup(rust::Str backup_dir, RocksDbStatus &status) const {
+        BackupEngine *backup_engine;
+        string dir_(backup_dir);
+        auto s = BackupEngine::Open(Env::Default(), BackupEngineOptions(dir_), &backup_engine);
+        if (!s.ok()) {
+            write_status(s, status);
+            return;
+        }
+        unique_ptr<BackupEngine> engine(backup_engine);
+        write_status(engine->CreateNewBackup(get_base_db(), true), status);
+    }
+
     inline void ingest_sst(rust::Str path, RocksDbStatus &status) const {
         IngestExternalFileOptions ifo;
         DB *db_ = get_base_db();
@@ -69,6 +81,43 @@ struct RocksDbBridge {
         write_status(db_->IngestExternalFile(cf, {std::move(path_)}, ifo), status);
     }
 
+    inline void ingest_ssts(rust::Slice<const rust::String> paths, RocksDbStatus &status) const {
+        IngestExternalFileOptions ifo;
+        ifo.move_files = true;
+        DB *db_ = get_base_db();
+        vector<string> paths_;
+        for (const auto &path: paths) {
+            paths_.emplace_back(string(path));
+        }
+        auto cf = db->DefaultColumnFamily();
+        write_status(db_->IngestExternalFile(cf, paths_, ifo), status);
+    }
+
+    inline bool get_int_property(rust::Str name, uint64_t &value) const {
+        string name_(name);
+        return db->GetIntProperty(db->DefaultColumnFamily(), name_, &value);
+    }
+
+    inline bool get_block_cache_stats(uint64_t &hits, uint64_t &misses) const {
+        auto stats = get_base_db()->GetOptions().statistics;
+        if (!stats) {
+            return false;
+        }
+        hits = stats->getTickerCount(BLOCK_CACHE_HIT);
+        misses = stats->getTickerCount(BLOCK_CACHE_MISS);
+        return true;
+    }
+
+    [[nodiscard]] inline uint64_t approximate_size(RustBytes start, RustBytes end) const {
+        Range range(convert_slice(start), convert_slice(end));
+        SizeApproximationOptions options;
+        options.include_memtables = true;
+        options.include_files = true;
+        uint64_t size = 0;
+        get_base_db()->GetApproximateSizes(options, db->DefaultColumnFamily(), &range, 1, &size);
+        return size;
+    }
+
     [[nodiscard]] inline const string &get_db_path() const {
         return db_path;
     }
@@ -120,4 +169,6 @@ struct RocksDbBridge {
 shared_ptr<RocksDbBridge>
 open_db(const DbOpts &opts, RocksDbStatus &status);
 
+void restore_from_backup(rust::Str backup_dir, rust::Str db_dir, RocksDbStatus &status);
+
 #endif //COZOROCKS_DB_H
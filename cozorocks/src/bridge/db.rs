@@ -54,6 +54,7 @@ impl Default for DbOpts {
             fixed_prefix_extractor_len: 0,
             destroy_on_exit: false,
             block_cache_size: 0,
+            enable_statistics: false,
         }
     }
 }
@@ -121,6 +122,11 @@ impl DbBuilder {
         self.opts.fixed_prefix_extractor_len = len;
         self
     }
+    /// Collect statistics such as the block cache hits and misses, at some cost in performance.
+    pub fn enable_statistics(mut self, val: bool) -> Self {
+        self.opts.enable_statistics = val;
+        self
+    }
     pub fn build(self) -> Result<RocksDb, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
 
@@ -195,6 +201,66 @@ impl RocksDb {
             Err(status)
         }
     }
+    /// Ingests the SST files at `paths` in one atomic operation, moving them into the database.
+    /// The key ranges of the files must not overlap.
+    pub fn ingest_sst_files(&self, paths: &[String]) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.ingest_ssts(paths, &mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    /// Reads an integer-valued property of the default column family,
+    /// e.g. `rocksdb.estimate-pending-compaction-bytes`. Returns `None` for unknown properties.
+    pub fn int_property(&self, name: &str) -> Option<u64> {
+        let mut value = 0;
+        if self.inner.get_int_property(name, &mut value) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+    /// Returns the number of block cache hits and misses since the database was opened,
+    /// or `None` if the database was not opened with statistics enabled.
+    pub fn block_cache_stats(&self) -> Option<(u64, u64)> {
+        let mut hits = 0;
+        let mut misses = 0;
+        if self.inner.get_block_cache_stats(&mut hits, &mut misses) {
+            Some((hits, misses))
+        } else {
+            None
+        }
+    }
+    /// Approximate number of bytes used by the keys in the range, in SST files and memtables.
+    pub fn approximate_size(&self, lower: &[u8], upper: &[u8]) -> u64 {
+        self.inner.approximate_size(lower, upper)
+    }
+    /// Creates a new backup in `backup_dir` with the RocksDB backup engine.
+    /// Files already present from earlier backups in the same directory are shared,
+    /// so repeated backups are incremental.
+    pub fn create_backup(&self, backup_dir: &str) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.create_backup(backup_dir, &mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+}
+
+/// Restores the latest backup in `backup_dir` into the database directory `db_dir`,
+/// which must not be opened.
+pub fn restore_from_backup(backup_dir: &str, db_dir: &str) -> Result<(), RocksDbStatus> {
+    let mut status = RocksDbStatus::default();
+    crate::bridge::ffi::restore_from_backup(backup_dir, db_dir, &mut status);
+    if status.is_ok() {
+        Ok(())
+    } else {
+        Err(status)
+    }
 }
 
 pub struct SstWriter {
@@ -41,6 +41,7 @@ pub(crate) mod ffi {
         pub fixed_prefix_extractor_len: usize,
         pub destroy_on_exit: bool,
         pub block_cache_size: usize,
+        pub enable_statistics: bool,
     }
 
     #[derive(Clone, Debug, Eq, PartialEq)]
@@ -139,6 +140,12 @@ pub(crate) mod ffi {
             status: &mut RocksDbStatus,
         ) -> UniquePtr<SstFileWriterBridge>;
         fn ingest_sst(self: &RocksDbBridge, path: &str, status: &mut RocksDbStatus);
+        fn ingest_ssts(self: &RocksDbBridge, paths: &[String], status: &mut RocksDbStatus);
+        fn create_backup(self: &RocksDbBridge, backup_dir: &str, status: &mut RocksDbStatus);
+        fn get_int_property(self: &RocksDbBridge, name: &str, value: &mut u64) -> bool;
+        fn get_block_cache_stats(self: &RocksDbBridge, hits: &mut u64, misses: &mut u64) -> bool;
+        fn approximate_size(self: &RocksDbBridge, lower: &[u8], upper: &[u8]) -> u64;
+        fn restore_from_backup(backup_dir: &str, db_dir: &str, status: &mut RocksDbStatus);
 
         type SstFileWriterBridge;
         fn put(